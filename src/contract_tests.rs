@@ -1,24 +1,104 @@
-use cosmwasm_std::testing::mock_env;
+// Helpers shared by the test modules: a permit signed by ADDRESS for the mock contract, and shortcuts for
+// registering users, donating and reading responses
+#[cfg(test)]
+mod fixtures {
+    use cosmwasm_std::testing::{mock_env, mock_info};
+    use cosmwasm_std::{coins, Addr, BankMsg, Binary, Coin, CosmosMsg, DepsMut, Env, Response, Timestamp};
+    use secret_toolkit_permit::{Permit, PermitParams, PermitSignature, PubKey};
+
+    use crate::execute::{execute, instantiate};
+    use crate::msg::{AlpinePermission, ExecuteMsg, InstantiateMsg};
+    use crate::state::AlpineUser;
+
+    pub const ADDRESS: &str = "secret1h3jx4rjkry20pctnzfj7ek8t4v4zaev2rn0rk2";
+    const PUBLIC_KEY: &str = "Ajc/LuFt+czBL/9kgFuvvQsdzUfR1dt2h1rrAt74/tbP";
+    const SIGNATURE: &str = "CpBEE52NP09JHAHEqLbWsAmu2GDJrIx6XX4G6uztkaMLiBNL61PyIHk9W3yTfhYWQ0vw+QH4q0HOWfEYWbZSoQ==";
+    const CONTRACT_ADDRESS: &str = "cosmos2contract";
+
+    pub fn permit() -> Permit<AlpinePermission> {
+        Permit {
+            params: PermitParams { 
+                allowed_tokens: vec![CONTRACT_ADDRESS.to_string()], 
+                permit_name: "test".to_owned(), 
+                chain_id: "secret-4".to_owned(), 
+                permissions: vec![AlpinePermission::Balance]
+            },
+            signature: PermitSignature { 
+                pub_key: PubKey {
+                    r#type: "tendermint/PubKeySecp256k1".to_string(),
+                    value: Binary::from_base64(PUBLIC_KEY).unwrap(),
+                },
+                signature: Binary::from_base64(SIGNATURE).unwrap() 
+            },
+        }
+    }
+
+    // Instantiate the contract with `owner` as the owner, and register each (address, username)
+    pub fn setup_users(mut deps: DepsMut<'_>, owner: &str, users: &[(&str, &str)]) {
+        instantiate(deps.branch(), mock_env(), mock_info(owner, &[]), InstantiateMsg {}).unwrap();
+        for (address, username) in users {
+            register_user(deps.branch(), address, username);
+        }
+    }
+
+    pub fn register_user(deps: DepsMut<'_>, address: &str, username: &str) -> Response {
+        let user = AlpineUser::new(deps.as_ref(), Addr::unchecked(address), None).unwrap();
+        let msg = ExecuteMsg::RegisterUser { user, username: String::from(username) };
+        execute(deps, mock_env(), mock_info(address, &[]), msg).unwrap()
+    }
+
+    // Donate from a sender's address under their username, which is left empty to donate anonymously
+    pub fn send_donation(deps: DepsMut<'_>, env: Env, (address, username): (&str, &str), recipient: &str, funds: Coin) -> Response {
+        let msg = ExecuteMsg::SendDonation {
+            sender: String::from(username),
+            recipient: String::from(recipient),
+            message: String::from(""),
+            campaign_id: None
+        };
+        execute(deps, env, mock_info(address, &[funds]), msg).unwrap()
+    }
+
+    pub fn env_at(seconds: u64) -> Env {
+        let mut env = mock_env();
+        env.block.time = Timestamp::from_seconds(seconds);
+        env
+    }
 
-use crate::{msg::ExecuteMsg, execute::execute};
+    pub fn bank_send(to_address: &str, amount: u128) -> CosmosMsg {
+        CosmosMsg::Bank(BankMsg::Send { to_address: String::from(to_address), amount: coins(amount, "uscrt") })
+    }
+
+    // The recipient and amount of each bank send in a response
+    pub fn bank_sends(response: &Response) -> Vec<(String, u128)> {
+        response.messages.iter().map(|sub_msg| match &sub_msg.msg {
+            CosmosMsg::Bank(BankMsg::Send { to_address, amount }) => (to_address.clone(), amount[0].amount.u128()),
+            msg => panic!("unexpected message {:?}", msg)
+        }).collect()
+    }
 
+    pub fn attribute(response: &Response, key: &str) -> String {
+        response.attributes.iter().find(|attribute| attribute.key == key).unwrap().value.clone()
+    }
+}
 
 #[cfg(test)]
 mod alpine_user_tests {
     use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
-    use cosmwasm_std::{from_binary, Addr, DepsMut };
+    use cosmwasm_std::{from_binary, Addr, Deps, DepsMut };
 
+    use super::fixtures::{self, ADDRESS, permit};
     use crate::execute::{execute, instantiate};
     use crate::msg::{
         ExecuteMsg,
         QueryMsg,
         MultiUserResponse,
-        AlpineUserResponse, InstantiateMsg
+        AlpineUserResponse, InstantiateMsg,
+        QueryWithPermitMsg, WhoAmIResponse
     };
     use crate::query::query;
-    use crate::state::{read_state, update_state};
+    use crate::state::{read_state, update_state, save_user, username_skeleton, UserSettings};
     use crate::{
-        error::ContractError,
+        error::{ContractError, QueryError},
         state::AlpineUser,
         msg::UsernameAvailableResponse
     };
@@ -233,7 +313,7 @@ mod alpine_user_tests {
         let msg = QueryMsg::IsUsernameAvailable { username: String::from("alpine_user_1") };
         let res = query(deps.as_ref(), mock_env(), msg).unwrap();
         let username_response: UsernameAvailableResponse = from_binary(&res).unwrap();
-        assert_eq!(username_response.is_available, true);
+        assert!(username_response.is_available);
     }
 
     // Check if an registered username is available. Should return false.
@@ -241,20 +321,18 @@ mod alpine_user_tests {
     fn is_username_available_false() {
         let mut deps = mock_dependencies();
         setup_contract(deps.as_mut());
-        let mut state = read_state(&deps.storage).load().unwrap();
 
         let test_user = AlpineUser::new(
             deps.as_ref(),
             Addr::unchecked("secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh"),
             Some(String::from("alpine_user_1"))
         ).unwrap();
-        state.users.append(&mut vec![test_user.clone()]);
-        update_state(&mut deps.storage).save(&state).unwrap();
+        save_user(&mut deps.storage, test_user.clone()).unwrap();
 
         let msg = QueryMsg::IsUsernameAvailable { username: String::from("alpine_user_1") };
         let res = query(deps.as_ref(), mock_env(), msg).unwrap();
         let username_response: UsernameAvailableResponse = from_binary(&res).unwrap();
-        assert_eq!(username_response.is_available, false);
+        assert!(!username_response.is_available);
     }
 
     // Check if a username is available. Technically the username is unregistered, but the only difference
@@ -263,20 +341,18 @@ mod alpine_user_tests {
     fn is_username_available_false_case_insensitive() {
         let mut deps = mock_dependencies();
         setup_contract(deps.as_mut());
-        let mut state = read_state(&deps.storage).load().unwrap();
 
         let test_user = AlpineUser::new(
             deps.as_ref(),
             Addr::unchecked("secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh"),
             Some(String::from("alpine_user_1"))
         ).unwrap();
-        state.users.append(&mut vec![test_user.clone()]);
-        update_state(&mut deps.storage).save(&state).unwrap();
+        save_user(&mut deps.storage, test_user.clone()).unwrap();
 
         let msg = QueryMsg::IsUsernameAvailable { username: String::from("ALPINE_USER_1") };
         let res = query(deps.as_ref(), mock_env(), msg).unwrap();
         let username_response: UsernameAvailableResponse = from_binary(&res).unwrap();
-        assert_eq!(username_response.is_available, false);
+        assert!(!username_response.is_available);
     }
 
     // Attempt to register a user with a taken username. Should error out.
@@ -284,15 +360,13 @@ mod alpine_user_tests {
     fn save_username_unavailable() {
         let mut deps = mock_dependencies();
         setup_contract(deps.as_mut());
-        let mut state = read_state(&deps.storage).load().unwrap();
 
         let test_user = AlpineUser::new(
             deps.as_ref(),
             Addr::unchecked("secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh"),
             Some(String::from("alpine_user_1"))
         ).unwrap();
-        state.users.append(&mut vec![test_user.clone()]);
-        update_state(&mut deps.storage).save(&state).unwrap();
+        save_user(&mut deps.storage, test_user.clone()).unwrap();
 
         let new_user = AlpineUser::new(
             deps.as_ref(),
@@ -330,45 +404,270 @@ mod alpine_user_tests {
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
         assert_eq!(_res.attributes[0].value, "alpine_user_1");
     }
-    
-    // Obtain a list of all saved usernames
+
+    // Attempt to register a username which only differs from a taken one by confusable characters. Should error out
     #[test]
-    fn get_usernames() {
+    fn save_username_confusable() {
         let mut deps = mock_dependencies();
         setup_contract(deps.as_mut());
-        let mut state = read_state(&deps.storage).load().unwrap();
 
-        // Save User One
         let test_user = AlpineUser::new(
             deps.as_ref(),
             Addr::unchecked("secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh"),
-            None
+            Some(String::from("alpine_user_1"))
         ).unwrap();
-        state.users.append(&mut vec![test_user]);
-        update_state(&mut deps.storage).save(&state).unwrap();
+        save_user(&mut deps.storage, test_user).unwrap();
 
-        // Save User Two
         let new_user = AlpineUser::new(
             deps.as_ref(),
             Addr::unchecked("secret1ysehn88p24d7769j4vj07hyndkjj7pccz3j3c9"),
             None
         ).unwrap();
-        state.users.append(&mut vec![new_user]);
-        update_state(&mut deps.storage).save(&state).unwrap();
+        let info = mock_info(new_user.address.as_str(), &[]);
 
-        // Save User Three
-        let new_user = AlpineUser::new(
+        let username = String::from("ALPlNE-USER-l");
+        let msg = ExecuteMsg::RegisterUser {
+            user: new_user,
+            username: username.clone()
+        };
+        let _res = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(_res, ContractError::InvalidUsername { username, reason: String::from("too similar to an existing username")});
+    }
+
+    // Attempt to register lookalikes of a taken username using numeric characters that aren't decimal digits
+    // (roman numerals, fractions) or digits the skeleton can't fold. Should error out
+    #[test]
+    fn save_username_unfoldable_numerics() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        fixtures::register_user(deps.as_mut(), "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh", "alice");
+
+        for username in ["a\u{217C}ice", "\u{2160}ce", "al\u{2170}ce", "alice\u{00BD}", "alice\u{1946}", "alice\u{A620}"] {
+            let new_user = AlpineUser::new(
+                deps.as_ref(),
+                Addr::unchecked("secret1ysehn88p24d7769j4vj07hyndkjj7pccz3j3c9"),
+                None
+            ).unwrap();
+            let info = mock_info(new_user.address.as_str(), &[]);
+            let username = String::from(username);
+            let msg = ExecuteMsg::RegisterUser {
+                user: new_user,
+                username: username.clone()
+            };
+            let _res = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+            assert_eq!(_res, ContractError::InvalidUsername { username, reason: String::from("only alphanumeric, underscores, and dashes are allowed")});
+        }
+    }
+
+    // Check if a username is available when it's only a lookalike of a taken one ("rn" vs "m", unicode digits). Should return false.
+    #[test]
+    fn is_username_available_false_confusable() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let test_user = AlpineUser::new(
             deps.as_ref(),
-            Addr::unchecked("secret1hrm44y69kzdjqq2tn6hh9cq3tzmfsa9rfgv7d9"),
-            None
+            Addr::unchecked("secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh"),
+            Some(String::from("modern_user_2"))
         ).unwrap();
-        state.users.append(&mut vec![new_user]);
-        update_state(&mut deps.storage).save(&state).unwrap();
+        save_user(&mut deps.storage, test_user).unwrap();
+
+        for username in ["rnodern_user_2", "modern_user_\u{0662}", "modern_user_\u{FF12}"] {
+            let msg = QueryMsg::IsUsernameAvailable { username: String::from(username) };
+            let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+            let username_response: UsernameAvailableResponse = from_binary(&res).unwrap();
+            assert!(!username_response.is_available);
+        }
+    }
+
+    // Validate that confusable characters are folded into the same skeleton
+    #[test]
+    fn username_skeletons_fold_confusables() {
+        assert_eq!(username_skeleton("Il1"), username_skeleton("lll"));
+        assert_eq!(username_skeleton("rn-0"), username_skeleton("m_o"));
+        assert_eq!(username_skeleton("vv\u{0967}"), username_skeleton("w1"));
+        assert_ne!(username_skeleton("alpine_user_1"), username_skeleton("alpine_user_2"));
+    }
+    
+    // A utility function to register a user through the contract and optionally list them in the search directory
+    fn register_user(mut deps: DepsMut<'_>, address: &str, username: &str, discoverable: bool) -> AlpineUser {
+        fixtures::register_user(deps.branch(), address, username);
+        if discoverable {
+            set_discoverable(deps, address, true);
+        }
+        AlpineUser { username: String::from(username), address: Addr::unchecked(address) }
+    }
+
+    fn set_discoverable(deps: DepsMut<'_>, address: &str, discoverable: bool) {
+        let info = mock_info(address, &[]);
+        let msg = ExecuteMsg::UpdateSettings { discoverable: Some(discoverable), claim_donations: None, public_leaderboard: None };
+        execute(deps, mock_env(), info, msg).unwrap();
+    }
+
+    // Search for users by prefix. Only discoverable users should be returned
+    #[test]
+    fn search_users_discoverable_only() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let user_a = register_user(deps.as_mut(), "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh", "alpine_user_a", true);
+        register_user(deps.as_mut(), "secret1ysehn88p24d7769j4vj07hyndkjj7pccz3j3c9", "alpine_user_b", false);
+        register_user(deps.as_mut(), "secret1hrm44y69kzdjqq2tn6hh9cq3tzmfsa9rfgv7d9", "other_user", true);
 
-        let msg = QueryMsg::GetAllUsers { };
+        let msg = QueryMsg::SearchUsers { prefix: String::from("ALPINE"), limit: None, start_after: None };
         let res = query(deps.as_ref(), mock_env(), msg).unwrap();
-        let users: MultiUserResponse  = from_binary(&res).unwrap();
-        assert_eq!(users.users.len(), 3)
+        let users: MultiUserResponse = from_binary(&res).unwrap();
+        assert_eq!(users.users, vec![user_a]);
+    }
+
+    // Search for users page by page. Should return the users in order without repeating any
+    #[test]
+    fn search_users_paginated() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let addresses = [
+            "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh",
+            "secret1ysehn88p24d7769j4vj07hyndkjj7pccz3j3c9",
+            "secret1hrm44y69kzdjqq2tn6hh9cq3tzmfsa9rfgv7d9"
+        ];
+        for (i, address) in addresses.iter().enumerate().rev() {
+            register_user(deps.as_mut(), address, &format!("creator_{}", i + 2), true);
+        }
+
+        let msg = QueryMsg::SearchUsers { prefix: String::from("creator"), limit: Some(2), start_after: None };
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+        let users: MultiUserResponse = from_binary(&res).unwrap();
+        let usernames: Vec<String> = users.users.into_iter().map(|user| user.username).collect();
+        assert_eq!(usernames, vec!["creator_2", "creator_3"]);
+
+        let msg = QueryMsg::SearchUsers { prefix: String::from("creator"), limit: Some(2), start_after: Some(String::from("creator_3")) };
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+        let users: MultiUserResponse = from_binary(&res).unwrap();
+        let usernames: Vec<String> = users.users.into_iter().map(|user| user.username).collect();
+        assert_eq!(usernames, vec!["creator_4"]);
+    }
+
+    // List usernames which start with each other, in mixed case. They should come back in lowercase order, and
+    // opting out of the shortest one shouldn't hide the others
+    #[test]
+    fn search_users_nested_names() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        register_user(deps.as_mut(), "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh", "Beekeeper", true);
+        register_user(deps.as_mut(), "secret1ysehn88p24d7769j4vj07hyndkjj7pccz3j3c9", "bee", true);
+        register_user(deps.as_mut(), "secret1hrm44y69kzdjqq2tn6hh9cq3tzmfsa9rfgv7d9", "bee_hive", true);
+
+        let search = |deps: Deps<'_>, prefix: &str, start_after: Option<&str>| {
+            let msg = QueryMsg::SearchUsers { prefix: String::from(prefix), limit: None, start_after: start_after.map(String::from) };
+            let users: MultiUserResponse = from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap();
+            users.users.into_iter().map(|user| user.username).collect::<Vec<String>>()
+        };
+        assert_eq!(search(deps.as_ref(), "BEE", None), vec!["bee", "bee_hive", "Beekeeper"]);
+        assert_eq!(search(deps.as_ref(), "bee", Some("bee_a")), vec!["bee_hive", "Beekeeper"]);
+        assert_eq!(search(deps.as_ref(), "beek", None), vec!["Beekeeper"]);
+
+        set_discoverable(deps.as_mut(), "secret1ysehn88p24d7769j4vj07hyndkjj7pccz3j3c9", false);
+        assert_eq!(search(deps.as_ref(), "bee", None), vec!["bee_hive", "Beekeeper"]);
+        set_discoverable(deps.as_mut(), "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh", false);
+        set_discoverable(deps.as_mut(), "secret1hrm44y69kzdjqq2tn6hh9cq3tzmfsa9rfgv7d9", false);
+        assert_eq!(search(deps.as_ref(), "b", None), Vec::<String>::new());
+    }
+
+    // Opt back out of the directory. The user should no longer be returned by searches
+    #[test]
+    fn search_users_opt_out() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        register_user(deps.as_mut(), "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh", "alpine_user_a", true);
+        set_discoverable(deps.as_mut(), "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh", false);
+
+        let msg = QueryMsg::SearchUsers { prefix: String::from("a"), limit: None, start_after: None };
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+        let users: MultiUserResponse = from_binary(&res).unwrap();
+        assert_eq!(users.users.len(), 0);
+    }
+
+    // Opt out of the directory and back in. Should be listed again
+    #[test]
+    fn search_users_opt_back_in() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let user = register_user(deps.as_mut(), "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh", "alpine_user_a", true);
+        set_discoverable(deps.as_mut(), "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh", false);
+        set_discoverable(deps.as_mut(), "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh", false);
+        set_discoverable(deps.as_mut(), "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh", true);
+
+        let msg = QueryMsg::SearchUsers { prefix: String::from("a"), limit: None, start_after: None };
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+        let users: MultiUserResponse = from_binary(&res).unwrap();
+        assert_eq!(users.users, vec![user]);
+    }
+
+    // Attempt to update settings from an address which isn't registered. Should error out
+    #[test]
+    fn update_settings_unregistered() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let info = mock_info("secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh", &[]);
+        let msg = ExecuteMsg::UpdateSettings { discoverable: Some(true), claim_donations: None, public_leaderboard: None };
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(res, ContractError::UserNotFound { user: String::from("secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh") });
+    }
+
+    // Look up the permit signer's own user. Should return their user and private settings
+    #[test]
+    fn who_am_i_success() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        let user = register_user(deps.as_mut(), ADDRESS, "alpine_user_1", true);
+
+        let msg = QueryMsg::WithPermit { permit: permit(), query: QueryWithPermitMsg::WhoAmI {  } };
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+        let who_am_i: WhoAmIResponse = from_binary(&res).unwrap();
+        assert_eq!(who_am_i.user, user);
+        assert_eq!(who_am_i.settings, UserSettings { discoverable: true, claim_donations: false, public_leaderboard: false });
+        assert_eq!(who_am_i.social_handles, Vec::<String>::new());
+    }
+
+    // Look up the permit signer when they haven't registered. Should error out
+    #[test]
+    fn who_am_i_unregistered() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let msg = QueryMsg::WithPermit { permit: permit(), query: QueryWithPermitMsg::WhoAmI {  } };
+        query(deps.as_ref(), mock_env(), msg).unwrap_err();
+    }
+
+    // Look up a user by address after the owner disabled address lookups. Should error out
+    #[test]
+    fn get_user_by_address_disabled() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        register_user(deps.as_mut(), ADDRESS, "alpine_user_1", false);
+
+        let msg = ExecuteMsg::UpdateConfig { disable_address_lookup: Some(true), escrow_timeout: None, team_wallets: None, fee_schedule: None, leaderboard_min_donors: None };
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+
+        let msg = QueryMsg::GetUserByAddr { address: Addr::unchecked(ADDRESS) };
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap_err();
+        assert_eq!(res, QueryError(ContractError::AddressLookupDisabled {  }));
+    }
+
+    // Attempt to update the contract config from an address other than the owner. Should error out
+    #[test]
+    fn update_config_unauthorized() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let msg = ExecuteMsg::UpdateConfig { disable_address_lookup: Some(true), escrow_timeout: None, team_wallets: None, fee_schedule: None, leaderboard_min_donors: None };
+        let res = execute(deps.as_mut(), mock_env(), mock_info(ADDRESS, &[]), msg).unwrap_err();
+        assert_eq!(res, ContractError::Unauthorized {  });
     }
 
     // Attempt to register a new user whose username prior to this was empty
@@ -450,19 +749,16 @@ mod alpine_user_tests {
         assert_eq!(user.user, test_user);
     }
 
-    // Try to grab a user with a nonexistent username. Results successful, user object is empty
+    // Try to grab a user with a nonexistent username. Should error out with a "user_not_found" code
     #[test]
     fn get_user_by_nonexistent_username() {
         let mut deps = mock_dependencies();
         setup_contract(deps.as_mut());
 
-        // Create an empty user for the assert
-        let empty_user = AlpineUser::empty();
-
         let msg = QueryMsg::GetUserByName{ username: String::from("alpine_user_1") };
-        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
-        let user: AlpineUserResponse = from_binary(&res).unwrap();
-        assert_eq!(user.user, empty_user);
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap_err();
+        assert_eq!(res, QueryError(ContractError::UserNotFound { user: String::from("alpine_user_1") }));
+        assert_eq!(res.to_string(), r#"{"code":"user_not_found","message":"User Not Found (\"alpine_user_1\")"}"#);
     }
 
     // Try to grab a user with a valid username. Results successful
@@ -492,19 +788,21 @@ mod alpine_user_tests {
 #[cfg(test)]
 mod donation_tests {
     use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
-    use cosmwasm_std::{from_binary, coins, MessageInfo, Addr, DepsMut, Binary, Deps, StdError};
-    use secret_toolkit_permit::{Permit, PermitParams, TokenPermissions, PermitSignature, PubKey, validate};
+    use cosmwasm_std::{from_binary, coin, coins, MessageInfo, Addr, DepsMut, Binary, Deps};
+    use secret_toolkit_permit::{Permit, PermitParams, PermitSignature, PubKey};
 
+    use super::fixtures;
     use crate::execute::{execute, instantiate};
     use crate::msg::{
+        AlpinePermission,
         ExecuteMsg,
         QueryMsg,
         MultiDonationResponse, DonationCountResponse, InstantiateMsg, QueryWithPermitMsg,
     };
     use crate::query::query;
-    use crate::state::{read_state, update_state, find_alpine_username, DonationInfo};
+    use crate::state::{read_state, update_state, donations, donations_by_recipient, user_stats, weekly_stats, top_supporters, supporter_totals, SupporterKey};
     use crate::{
-        error::ContractError,
+        error::{ContractError, QueryError},
         state::AlpineUser
     };
 
@@ -521,41 +819,13 @@ mod donation_tests {
         instantiate(deps, mock_env(), info, msg).unwrap();
     }
 
-    fn get_sent_donations(deps: Deps, sender: String) -> MultiDonationResponse {
-        let state = read_state(deps.storage).load().unwrap();
-        let sender_user = find_alpine_username(deps.storage, sender).unwrap();
-        let mut sent_donations: Vec<DonationInfo> = vec![];
-    
-        for donation in state.donations {
-            if donation.sender == sender_user {
-                sent_donations.append(&mut vec![donation]);
-            }
-        }
-    
-        MultiDonationResponse { donations: sent_donations }
-    }
-    
-    fn get_received_donations(deps: Deps, recipient: String) -> MultiDonationResponse {
-        let state = read_state(deps.storage).load().unwrap();
-        let recipient_user = find_alpine_username(deps.storage, recipient).unwrap();
-        let mut received_donations: Vec<DonationInfo> = vec![];
-    
-        for donation in state.donations {
-            if donation.recipient == recipient_user {
-                received_donations.append(&mut vec![donation]);
-            }
-        }
-    
-        MultiDonationResponse { donations: received_donations }
-    }
-
-    fn query_with_permit(deps: Deps, query: QueryWithPermitMsg) -> MultiDonationResponse {
-        let permit = Permit {
+    fn query_permit() -> Permit<AlpinePermission> {
+        Permit {
             params: PermitParams { 
                 allowed_tokens: vec![CONTRACT_ADDRESS.to_string()], 
                 permit_name: "test".to_owned(), 
                 chain_id: "secret-4".to_owned(), 
-                permissions: vec![TokenPermissions::Balance]
+                permissions: vec![AlpinePermission::Balance]
             },
             signature: PermitSignature { 
                 pub_key: PubKey {
@@ -564,33 +834,22 @@ mod donation_tests {
                 },
                 signature: Binary::from_base64(SIGNATURE).unwrap() 
             },
-        };
-
-        // Validate permit
-        let state = read_state(deps.storage).load().unwrap();
-        validate(
-            deps,
-            "revoked_permits",
-            &permit,
-            state.contract_address, 
-            None
-        ).unwrap();
-
-        // Don't grab the sender's address here because it won't canonicalize right in tests
-        // Because of that, testing that the user address is validated correctly requires integration tests
-        match query {
-            QueryWithPermitMsg::GetReceivedDonations { recipient } => get_received_donations(deps, recipient),
-            QueryWithPermitMsg::GetSentDonations { sender } => get_sent_donations(deps, sender)
         }
     }
+
+    fn query_with_permit(deps: Deps, permit_query: QueryWithPermitMsg) -> MultiDonationResponse {
+        // The permit is signed by ADDRESS, so the queried user needs to be registered with that address
+        let msg = QueryMsg::WithPermit { permit: query_permit(), query: permit_query };
+        from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap()
+    }
     
-    fn query_with_bad_permit(deps: Deps, query: QueryWithPermitMsg) -> StdError {
+    fn query_with_bad_permit(deps: Deps, permit_query: QueryWithPermitMsg) -> QueryError {
         let permit = Permit {
             params: PermitParams { 
                 allowed_tokens: vec![CONTRACT_ADDRESS.to_string()], 
                 permit_name: "incorrect".to_owned(), 
                 chain_id: "secret-4".to_owned(), 
-                permissions: vec![TokenPermissions::Balance]
+                permissions: vec![AlpinePermission::Balance]
             },
             signature: PermitSignature { 
                 pub_key: PubKey {
@@ -601,15 +860,8 @@ mod donation_tests {
             },
         };
 
-        // Validate permit
-        let state = read_state(deps.storage).load().unwrap();
-        validate(
-            deps,
-            "revoked_permits",
-            &permit,
-            state.contract_address, 
-            None
-        ).unwrap_err()
+        let msg = QueryMsg::WithPermit { permit, query: permit_query };
+        query(deps, mock_env(), msg).unwrap_err()
     }
 
     // Validate that instantiation is succesful
@@ -649,7 +901,8 @@ mod donation_tests {
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message, 
             sender: alpine_user_a.username,
-            recipient: invalid_user.username.clone()
+            recipient: invalid_user.username.clone(),
+            campaign_id: None
         };
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg)
             .unwrap_err();
@@ -684,7 +937,8 @@ mod donation_tests {
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message, 
             sender: alpine_user_a.username,
-            recipient: alpine_user_b.username
+            recipient: alpine_user_b.username,
+            campaign_id: None
         };
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg)
             .unwrap_err();
@@ -715,13 +969,42 @@ mod donation_tests {
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message, 
             sender: alpine_user_a.username,
-            recipient: alpine_user_b.username
+            recipient: alpine_user_b.username,
+            campaign_id: None
         };
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg)
             .unwrap_err();
         assert_eq!(_res, ContractError::NoDonation{ });
     }
 
+    // Attempt to send a donation in two denoms at once. Should error out, since only one denom is paid out
+    #[test]
+    fn send_donation_multiple_denoms() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        fixtures::register_user(deps.as_mut(), "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh", "USER_A");
+        fixtures::register_user(deps.as_mut(), "secret1ayjl4cm8e2nrnhstx92cr6uuljnumjxgkncs7x", "USER_B");
+        let funds = vec![coin(1000, "uscrt"), coin(1000, "uatom")];
+        let info = mock_info("secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh", &funds);
+
+        let msg = ExecuteMsg::SendDonation {
+            message: String::from("henlo :)"),
+            sender: String::from("USER_A"),
+            recipient: String::from("USER_B"),
+            campaign_id: None
+        };
+        let err = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap_err();
+        assert_eq!(err, ContractError::MultipleDenoms {  });
+
+        let msg = ExecuteMsg::EscrowDonation {
+            message: String::from("henlo :)"),
+            sender: String::from("USER_A"),
+            recipient: String::from("twitter:alpine")
+        };
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::MultipleDenoms {  });
+    }
+
     // Attempt to send a donation with a message that's too long. Should error out
     #[test]
     fn send_too_long_message() {
@@ -750,7 +1033,8 @@ mod donation_tests {
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message, 
             sender: alpine_user_a.username,
-            recipient: alpine_user_b.username
+            recipient: alpine_user_b.username,
+            campaign_id: None
         };
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg)
             .unwrap_err();
@@ -793,28 +1077,35 @@ mod donation_tests {
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone(), 
             sender: alpine_user_a.username.clone(),
-            recipient: alpine_user_b.username
+            recipient: alpine_user_b.username,
+            campaign_id: None
         };
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg);
 
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone(), 
             sender: alpine_user_a.username.clone(),
-            recipient: alpine_user_c.username
+            recipient: alpine_user_c.username,
+            campaign_id: None
         };
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
 
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone(), 
             sender: alpine_user_a.username.clone(),
-            recipient: alpine_user_d.username
+            recipient: alpine_user_d.username,
+            campaign_id: None
         };
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
         
         let sent_donations: MultiDonationResponse = query_with_permit(
             deps.as_ref(),
             QueryWithPermitMsg::GetSentDonations {
-                sender: alpine_user_a.username.clone()
+                sender: alpine_user_a.username.clone(),
+                filter: None,
+                order: None,
+                limit: None,
+                start_after: None
             }
         );
         assert_eq!(3, sent_donations.donations.len());
@@ -856,28 +1147,68 @@ mod donation_tests {
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone(), 
             sender: alpine_user_a.username.clone(),
-            recipient: alpine_user_b.username
+            recipient: alpine_user_b.username,
+            campaign_id: None
         };
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg);
 
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone(), 
             sender: alpine_user_a.username.clone(),
-            recipient: alpine_user_c.username
+            recipient: alpine_user_c.username,
+            campaign_id: None
         };
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
 
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone(), 
             sender: alpine_user_a.username.clone(),
-            recipient: alpine_user_d.username
+            recipient: alpine_user_d.username,
+            campaign_id: None
         };
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
         
-        let err = query_with_bad_permit(deps.as_ref(), QueryWithPermitMsg::GetSentDonations { sender: alpine_user_a.username.clone() });
-        assert_eq!(err, StdError::GenericErr { msg: "Failed to verify signatures for the given permit".to_owned() })
+        let err = query_with_bad_permit(deps.as_ref(), QueryWithPermitMsg::GetSentDonations { sender: alpine_user_a.username.clone(), filter: None, order: None , limit: None, start_after: None});
+        assert_eq!(err, QueryError(ContractError::InvalidPermit { reason: "Failed to verify signatures for the given permit".to_owned() }))
      }
 
+    // Query the donations sent by a username which doesn't exist. Should error out with a "user_not_found" code instead of panicking
+    #[test]
+    fn get_sent_donations_nonexistent_user() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let err = query_with_bad_permit(deps.as_ref(), QueryWithPermitMsg::GetSentDonations { sender: String::from("USER_A"), filter: None, order: None , limit: None, start_after: None});
+        assert_eq!(err.0.code(), "invalid_permit");
+
+        let permit = query_permit();
+        let msg = QueryMsg::WithPermit { permit, query: QueryWithPermitMsg::GetSentDonations { sender: String::from("USER_A"), filter: None, order: None , limit: None, start_after: None} };
+        let err = query(deps.as_ref(), mock_env(), msg).unwrap_err();
+        assert_eq!(err, QueryError(ContractError::UserNotFound { user: String::from("USER_A") }));
+    }
+
+    // Query the donations received by a user other than the permit signer. Should error out as unauthorized
+    #[test]
+    fn get_received_donations_wrong_signer() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        let mut state = read_state(&deps.storage).load().unwrap();
+
+        let alpine_user_a: AlpineUser = AlpineUser::new(
+            deps.as_ref(),
+            Addr::unchecked("secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh"),
+            Some(String::from("USER_A"))
+        ).unwrap();
+        state.users.append(&mut vec![alpine_user_a.clone()]);
+        update_state(&mut deps.storage).save(&state).unwrap();
+
+        let permit = query_permit();
+        let msg = QueryMsg::WithPermit { permit, query: QueryWithPermitMsg::GetReceivedDonations { recipient: alpine_user_a.username, filter: None, order: None , limit: None, start_after: None} };
+        let err = query(deps.as_ref(), mock_env(), msg).unwrap_err();
+        assert_eq!(err, QueryError(ContractError::Unauthorized {  }));
+        assert_eq!(err.to_string(), r#"{"code":"unauthorized","message":"Unauthorized"}"#);
+    }
+
     // Obtain a list of multiple sent donations and validate that they're sorted in the correct order. Should return success.
     #[test]
     fn get_multiple_sent_donations_sorted() {
@@ -914,25 +1245,28 @@ mod donation_tests {
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone() + "1", 
             sender: alpine_user_a.username.clone(),
-            recipient: alpine_user_b.username
+            recipient: alpine_user_b.username,
+            campaign_id: None
         };
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg);
 
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone() + "2", 
             sender: alpine_user_a.username.clone(),
-            recipient: alpine_user_c.username
+            recipient: alpine_user_c.username,
+            campaign_id: None
         };
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
 
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone() + "3", 
             sender: alpine_user_a.username.clone(),
-            recipient: alpine_user_d.username
+            recipient: alpine_user_d.username,
+            campaign_id: None
         };
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
         
-        let sent_donations: MultiDonationResponse = query_with_permit(deps.as_ref(), QueryWithPermitMsg::GetSentDonations { sender: alpine_user_a.username.clone() });
+        let sent_donations: MultiDonationResponse = query_with_permit(deps.as_ref(), QueryWithPermitMsg::GetSentDonations { sender: alpine_user_a.username.clone(), filter: None, order: None , limit: None, start_after: None});
         assert_eq!(donation_message.clone() + "1", sent_donations.donations[0].message);
         assert_eq!(donation_message.clone() + "2", sent_donations.donations[1].message);
         assert_eq!(donation_message.clone() + "3", sent_donations.donations[2].message);
@@ -949,7 +1283,7 @@ mod donation_tests {
 
         let alpine_user_a: AlpineUser = AlpineUser::new(
             deps.as_ref(),
-            Addr::unchecked("secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh"),
+            Addr::unchecked(ADDRESS),
             Some(String::from("USER_A"))
         ).unwrap();
         let alpine_user_b: AlpineUser = AlpineUser::new(
@@ -974,7 +1308,8 @@ mod donation_tests {
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone(), 
             sender: alpine_user_b.username.clone(),
-            recipient: alpine_user_a.username.clone()
+            recipient: alpine_user_a.username.clone(),
+            campaign_id: None
         };
         let info = mock_info(alpine_user_b.address.as_str(), &coins(1000, "earth"));
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
@@ -982,7 +1317,8 @@ mod donation_tests {
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone(), 
             sender: alpine_user_c.username,
-            recipient: alpine_user_a.username.clone()
+            recipient: alpine_user_a.username.clone(),
+            campaign_id: None
         };
         let info = mock_info(alpine_user_c.address.as_str(), &coins(1000, "earth"));
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
@@ -990,12 +1326,13 @@ mod donation_tests {
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone(), 
             sender: alpine_user_a.username.clone(),
-            recipient: alpine_user_b.username.clone()
+            recipient: alpine_user_b.username.clone(),
+            campaign_id: None
         };
         let info = mock_info(alpine_user_a.address.as_str(), &coins(1000, "earth"));
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
         
-        let received_donations: MultiDonationResponse = query_with_permit(deps.as_ref(), QueryWithPermitMsg::GetReceivedDonations { recipient: alpine_user_a.username.clone() });
+        let received_donations: MultiDonationResponse = query_with_permit(deps.as_ref(), QueryWithPermitMsg::GetReceivedDonations { recipient: alpine_user_a.username.clone(), filter: None, order: None , limit: None, start_after: None});
         assert_eq!(2, received_donations.donations.len());
     }
 
@@ -1009,7 +1346,7 @@ mod donation_tests {
 
         let alpine_user_a: AlpineUser = AlpineUser::new(
             deps.as_ref(),
-            Addr::unchecked("secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh"),
+            Addr::unchecked(ADDRESS),
             Some(String::from("USER_A"))
         ).unwrap();
         let alpine_user_b: AlpineUser = AlpineUser::new(
@@ -1034,7 +1371,8 @@ mod donation_tests {
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone(), 
             sender: alpine_user_b.username.clone(),
-            recipient: alpine_user_a.username.clone()
+            recipient: alpine_user_a.username.clone(),
+            campaign_id: None
         };
         let info = mock_info(alpine_user_b.address.as_str(), &coins(1000, "earth"));
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
@@ -1042,7 +1380,8 @@ mod donation_tests {
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone(), 
             sender: alpine_user_c.username,
-            recipient: alpine_user_a.username.clone()
+            recipient: alpine_user_a.username.clone(),
+            campaign_id: None
         };
         let info = mock_info(alpine_user_c.address.as_str(), &coins(1000, "earth"));
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
@@ -1050,13 +1389,14 @@ mod donation_tests {
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone(), 
             sender: alpine_user_a.username.clone(),
-            recipient: alpine_user_b.username.clone()
+            recipient: alpine_user_b.username.clone(),
+            campaign_id: None
         };
         let info = mock_info(alpine_user_a.address.as_str(), &coins(1000, "earth"));
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
         
-        let err = query_with_bad_permit(deps.as_ref(), QueryWithPermitMsg::GetReceivedDonations { recipient: alpine_user_a.username.clone() });
-        assert_eq!(err, StdError::GenericErr { msg: "Failed to verify signatures for the given permit".to_owned() })
+        let err = query_with_bad_permit(deps.as_ref(), QueryWithPermitMsg::GetReceivedDonations { recipient: alpine_user_a.username.clone(), filter: None, order: None , limit: None, start_after: None});
+        assert_eq!(err, QueryError(ContractError::InvalidPermit { reason: "Failed to verify signatures for the given permit".to_owned() }))
     }
 
     // Obtain a list of multiple received donations and validate that they're sorted in the correct order. Should return success.
@@ -1069,7 +1409,7 @@ mod donation_tests {
 
         let alpine_user_a: AlpineUser = AlpineUser::new(
             deps.as_ref(),
-            Addr::unchecked("secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh"),
+            Addr::unchecked(ADDRESS),
             Some(String::from("USER_A"))
         ).unwrap();
         let alpine_user_b: AlpineUser = AlpineUser::new(
@@ -1094,7 +1434,8 @@ mod donation_tests {
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone() + "1", 
             sender: alpine_user_b.username.clone(),
-            recipient: alpine_user_a.username.clone()
+            recipient: alpine_user_a.username.clone(),
+            campaign_id: None
         };
         let info = mock_info(alpine_user_b.address.as_str(), &coins(1000, "earth"));
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg);
@@ -1102,7 +1443,8 @@ mod donation_tests {
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone() + "2", 
             sender: alpine_user_c.username.clone(),
-            recipient: alpine_user_a.username.clone()
+            recipient: alpine_user_a.username.clone(),
+            campaign_id: None
         };
         let info = mock_info(alpine_user_c.address.as_str(), &coins(1000, "earth"));
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
@@ -1110,7 +1452,8 @@ mod donation_tests {
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone() + "3", 
             sender: alpine_user_c.username.clone(),
-            recipient: alpine_user_a.username.clone()
+            recipient: alpine_user_a.username.clone(),
+            campaign_id: None
         };
         let info = mock_info(alpine_user_c.address.as_str(), &coins(1000, "earth"));
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
@@ -1118,7 +1461,11 @@ mod donation_tests {
         let received_donations: MultiDonationResponse = query_with_permit(
             deps.as_ref(),
             QueryWithPermitMsg::GetReceivedDonations {
-                recipient: alpine_user_a.username.clone()
+                recipient: alpine_user_a.username.clone(),
+                filter: None,
+                order: None,
+                limit: None,
+                start_after: None
             }
         );
         assert_eq!(donation_message.clone() + "1", received_donations.donations[0].message);
@@ -1159,7 +1506,8 @@ mod donation_tests {
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone() + "1", 
             sender: alpine_user_b.username.clone(),
-            recipient: alpine_user_a.username.clone()
+            recipient: alpine_user_a.username.clone(),
+            campaign_id: None
         };
         let info = mock_info(alpine_user_b.address.as_str(), &coins(1000, "earth"));
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg);
@@ -1167,7 +1515,8 @@ mod donation_tests {
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone() + "2", 
             sender: alpine_user_c.username.clone(),
-            recipient: alpine_user_a.username.clone()
+            recipient: alpine_user_a.username.clone(),
+            campaign_id: None
         };
         let info = mock_info(alpine_user_c.address.as_str(), &coins(1000, "earth"));
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
@@ -1175,12 +1524,13 @@ mod donation_tests {
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone() + "3", 
             sender: alpine_user_c.username.clone(),
-            recipient: alpine_user_a.username.clone()
+            recipient: alpine_user_a.username.clone(),
+            campaign_id: None
         };
         let info = mock_info(alpine_user_c.address.as_str(), &coins(1000, "earth"));
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
 
-        let msg = ExecuteMsg::ClearContract {  };
+        let msg = ExecuteMsg::ClearContract { limit: None };
         let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
         assert_eq!(res, ContractError::Unauthorized {  });
     }
@@ -1219,7 +1569,8 @@ mod donation_tests {
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone() + "1", 
             sender: alpine_user_b.username.clone(),
-            recipient: alpine_user_a.username.clone()
+            recipient: alpine_user_a.username.clone(),
+            campaign_id: None
         };
         let info = mock_info(alpine_user_b.address.as_str(), &coins(1000, "earth"));
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg);
@@ -1227,7 +1578,8 @@ mod donation_tests {
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone() + "2", 
             sender: alpine_user_c.username.clone(),
-            recipient: alpine_user_a.username.clone()
+            recipient: alpine_user_a.username.clone(),
+            campaign_id: None
         };
         let info = mock_info(alpine_user_c.address.as_str(), &coins(1000, "earth"));
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
@@ -1235,47 +1587,2371 @@ mod donation_tests {
         let msg = ExecuteMsg::SendDonation { 
             message: donation_message.clone() + "3", 
             sender: alpine_user_c.username.clone(),
-            recipient: alpine_user_a.username.clone()
+            recipient: alpine_user_a.username.clone(),
+            campaign_id: None
         };
         let info = mock_info(alpine_user_c.address.as_str(), &coins(1000, "earth"));
         let _res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
 
-        let msg = ExecuteMsg::ClearContract {  };
+        let msg = ExecuteMsg::ClearContract { limit: None };
         let info = mock_info("creator", &[]);
         execute(deps.as_mut(), mock_env(), info, msg).unwrap();
         let state = read_state(&deps.storage).load().unwrap();
         assert_eq!(state.donation_count, 0);
         assert_eq!(state.donations.len(), 0);
+        assert!(donations().get(&deps.storage, &0).is_none());
+        assert_eq!(state.users.len(), 0);
+        assert!(!user_stats().contains(&deps.storage, &alpine_user_a.address));
+        assert!(!weekly_stats().contains(&deps.storage, &alpine_user_a.address));
+        assert!(!top_supporters().contains(&deps.storage, &(alpine_user_a.address.clone(), String::from("earth"))));
+        let supporter = SupporterKey { address: alpine_user_c.address.clone(), anonymous: false };
+        assert!(!supporter_totals().contains(&deps.storage, &(alpine_user_a.address.clone(), supporter)));
+    }
+
+    // Clear the smart contract a page at a time. The newest donations should go first, and the users should only
+    // be cleared with the last donation
+    #[test]
+    fn clear_contract_in_pages() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        fixtures::register_user(deps.as_mut(), "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh", "USER_A");
+        fixtures::register_user(deps.as_mut(), "secret1ayjl4cm8e2nrnhstx92cr6uuljnumjxgkncs7x", "USER_B");
+        for _ in 0..3 {
+            fixtures::send_donation(deps.as_mut(), mock_env(), ("secret1ayjl4cm8e2nrnhstx92cr6uuljnumjxgkncs7x", "USER_B"), "USER_A", coin(1000, "uscrt"));
+        }
+        let recipient = Addr::unchecked("secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh");
+
+        let msg = ExecuteMsg::ClearContract { limit: Some(2) };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg.clone()).unwrap();
+        assert_eq!(fixtures::attribute(&res, "remaining_donations"), "1");
+        let state = read_state(&deps.storage).load().unwrap();
+        assert_eq!(state.donation_count, 1);
+        assert_eq!(state.users.len(), 2);
+        assert!(donations().get(&deps.storage, &0).is_some());
+        assert!(donations().get(&deps.storage, &1).is_none());
+        assert_eq!(donations_by_recipient().bounds(&deps.storage, &recipient).len(), 1);
+        assert!(user_stats().contains(&deps.storage, &recipient));
+
+        let res = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        assert_eq!(fixtures::attribute(&res, "remaining_donations"), "0");
+        let state = read_state(&deps.storage).load().unwrap();
+        assert_eq!(state.donation_count, 0);
         assert_eq!(state.users.len(), 0);
-    }  
+        assert!(donations_by_recipient().bounds(&deps.storage, &recipient).is_empty());
+        assert!(!user_stats().contains(&deps.storage, &recipient));
+    }
 }
 
 
 
-// Define a set of integration tests that use our entry points instead of internal calls
+// A set of tests for donations to usernames and social handles which haven't been registered yet
 #[cfg(test)]
-mod integration_tests {
-    use cosmwasm_std::{
-        testing::{mock_dependencies, mock_info, mock_env}, 
-        Addr, 
-        from_binary
-    };
+mod escrow_tests {
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{coins, from_binary, Addr, DepsMut, Response};
 
-    use crate::{
-        msg::{InstantiateMsg, MigrateMsg, ExecuteMsg, QueryMsg, MultiUserResponse},
-        state::AlpineUser, execute::{instantiate, migrate, execute}, query::query
-    };
-    // use entry::{ instantiate, migrate, query, execute };
+    use super::fixtures::{ADDRESS, attribute, bank_sends, permit, register_user, setup_users};
+    use crate::error::ContractError;
+    use crate::execute::execute;
+    use crate::msg::{ExecuteMsg, QueryMsg, QueryWithPermitMsg, EscrowedDonationsResponse, WhoAmIResponse};
+    use crate::query::query;
+    use crate::state::{DEFAULT_ESCROW_TIMEOUT, donation_count};
 
-    // Validate that instantiation works from the client's perspective
-    #[test]
-    fn proper_instantiation() {
-        let mut deps = mock_dependencies();
-        let msg = InstantiateMsg {};
-        let info = mock_info("creator", &[]);
+    const CREATOR_ADDRESS: &str = "secret1ysehn88p24d7769j4vj07hyndkjj7pccz3j3c9";
 
-        let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
-        assert_eq!(0, res.messages.len());
+    fn setup_contract(deps: DepsMut<'_>) {
+        setup_users(deps, "creator", &[(ADDRESS, "alpine_fan")]);
+    }
+
+    fn escrow_donation(deps: DepsMut<'_>, recipient: &str) -> Response {
+        let msg = ExecuteMsg::EscrowDonation {
+            sender: String::from("alpine_fan"),
+            recipient: String::from(recipient),
+            message: String::from("see you on alpine :)")
+        };
+        execute(deps, mock_env(), mock_info(ADDRESS, &coins(1000, "uscrt")), msg).unwrap()
+    }
+
+    fn claim_escrows(deps: DepsMut<'_>, limit: Option<u32>) -> Response {
+        execute(deps, mock_env(), mock_info(CREATOR_ADDRESS, &[]), ExecuteMsg::ClaimEscrows { limit }).unwrap()
+    }
+
+    // Donate to a username nobody has registered. The funds should be held until the name is registered and
+    // its owner claims them
+    #[test]
+    fn escrow_released_on_registration() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let res = escrow_donation(deps.as_mut(), "New_Creator");
+        assert_eq!(res.messages.len(), 0);
+        assert_eq!(donation_count(&deps.storage).unwrap(), 0);
+
+        let res = register_user(deps.as_mut(), CREATOR_ADDRESS, "new_creator");
+        assert_eq!(res.messages.len(), 0);
+        assert_eq!(attribute(&res, "pending_escrows"), "1");
+
+        let res = claim_escrows(deps.as_mut(), None);
+        let sends = bank_sends(&res);
+        assert_eq!(sends, vec![(String::from(CREATOR_ADDRESS), 970)]);
+        assert_eq!(attribute(&res, "pending_escrows"), "0");
+        assert_eq!(donation_count(&deps.storage).unwrap(), 1);
+    }
+
+    // Escrow many donations to the same name. Registering it should still work, and the escrows should be claimed
+    // a page at a time, skipping any which were reclaimed
+    #[test]
+    fn escrow_claimed_in_pages() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        for _ in 0..5 {
+            escrow_donation(deps.as_mut(), "new_creator");
+        }
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(DEFAULT_ESCROW_TIMEOUT);
+        execute(deps.as_mut(), env, mock_info(ADDRESS, &[]), ExecuteMsg::ReclaimEscrow { id: 1 }).unwrap();
+
+        let res = register_user(deps.as_mut(), CREATOR_ADDRESS, "new_creator");
+        assert_eq!(attribute(&res, "pending_escrows"), "5");
+
+        let res = claim_escrows(deps.as_mut(), Some(3));
+        assert_eq!(bank_sends(&res).len(), 2);
+        assert_eq!(attribute(&res, "pending_escrows"), "2");
+        let res = claim_escrows(deps.as_mut(), Some(3));
+        assert_eq!(bank_sends(&res).len(), 2);
+        assert_eq!(attribute(&res, "pending_escrows"), "0");
+        let res = claim_escrows(deps.as_mut(), None);
+        assert_eq!(res.messages.len(), 0);
+        assert_eq!(donation_count(&deps.storage).unwrap(), 4);
+    }
+
+    // Donate through escrow to a user who is already registered. Should be forwarded immediately
+    #[test]
+    fn escrow_to_registered_user() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        register_user(deps.as_mut(), CREATOR_ADDRESS, "new_creator");
+
+        let res = escrow_donation(deps.as_mut(), "new_creator");
+        assert_eq!(bank_sends(&res)[0], (String::from(CREATOR_ADDRESS), 970));
+    }
+
+    // Donate through escrow to a registered username in different case. Should be forwarded to its owner
+    // instead of being held for a name which can't be registered again
+    #[test]
+    fn escrow_to_registered_user_any_case() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        register_user(deps.as_mut(), CREATOR_ADDRESS, "new_creator");
+
+        let res = escrow_donation(deps.as_mut(), "New_Creator");
+        assert_eq!(bank_sends(&res), vec![(String::from(CREATOR_ADDRESS), 970)]);
+        assert_eq!(donation_count(&deps.storage).unwrap(), 1);
+    }
+
+    // Donate through escrow to a lookalike of a registered username. Should be rejected, since the lookalike can
+    // never be registered
+    #[test]
+    fn escrow_to_lookalike_rejected() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        register_user(deps.as_mut(), CREATOR_ADDRESS, "new_creator");
+
+        let msg = ExecuteMsg::EscrowDonation {
+            sender: String::from("alpine_fan"),
+            recipient: String::from("new-creator"),
+            message: String::from("")
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(ADDRESS, &coins(1000, "uscrt")), msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidUsername {
+            username: String::from("new-creator"),
+            reason: String::from("too similar to an existing username")
+        });
+    }
+
+    // Reclaim an escrowed donation before and after it expires. Should only succeed after expiry, and only once
+    #[test]
+    fn reclaim_escrow_after_expiry() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        escrow_donation(deps.as_mut(), "new_creator");
+
+        let msg = ExecuteMsg::ReclaimEscrow { id: 0 };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(ADDRESS, &[]), msg.clone()).unwrap_err();
+        assert_eq!(err.code(), "escrow_not_expired");
+
+        let err = execute(deps.as_mut(), mock_env(), mock_info(CREATOR_ADDRESS, &[]), msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {  });
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(DEFAULT_ESCROW_TIMEOUT);
+        let res = execute(deps.as_mut(), env.clone(), mock_info(ADDRESS, &[]), msg.clone()).unwrap();
+        assert_eq!(bank_sends(&res), vec![(String::from(ADDRESS), 1000)]);
+
+        let err = execute(deps.as_mut(), env, mock_info(ADDRESS, &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::EscrowNotFound { id: 0 });
+
+        // The name was reclaimed, so claiming its escrows shouldn't pay anything out
+        register_user(deps.as_mut(), CREATOR_ADDRESS, "new_creator");
+        let res = claim_escrows(deps.as_mut(), None);
+        assert_eq!(res.messages.len(), 0);
+    }
+
+    // Donate to a social handle, then have the owner link it to a user. The escrow should be claimable by that user
+    #[test]
+    fn escrow_released_on_social_handle_link() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        register_user(deps.as_mut(), CREATOR_ADDRESS, "new_creator");
+        escrow_donation(deps.as_mut(), "Twitter:@New_Creator");
+
+        let msg = ExecuteMsg::LinkSocialHandle { address: Addr::unchecked(CREATOR_ADDRESS), handle: String::from("twitter:new_creator") };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(CREATOR_ADDRESS, &[]), msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {  });
+
+        let res = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        assert_eq!(attribute(&res, "pending_escrows"), "1");
+        let res = claim_escrows(deps.as_mut(), None);
+        assert_eq!(bank_sends(&res)[0], (String::from(CREATOR_ADDRESS), 970));
+
+        // Once linked, the handle can be used as a regular recipient
+        let msg = ExecuteMsg::SendDonation {
+            sender: String::from("alpine_fan"),
+            recipient: String::from("twitter:new_creator"),
+            message: String::from(""),
+            campaign_id: None
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info(ADDRESS, &coins(100, "uscrt")), msg).unwrap();
+        assert_eq!(bank_sends(&res)[0], (String::from(CREATOR_ADDRESS), 97));
+    }
+
+    // Attempt to escrow a donation for an invalid social handle. Should error out
+    #[test]
+    fn escrow_invalid_social_handle() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let msg = ExecuteMsg::EscrowDonation {
+            sender: String::from("alpine_fan"),
+            recipient: String::from("twitter:"),
+            message: String::from("")
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(ADDRESS, &coins(1000, "uscrt")), msg).unwrap_err();
+        assert_eq!(err.code(), "invalid_social_handle");
+    }
+
+    // List the sender's pending escrows, and the social handles of a user. Should return success
+    #[test]
+    fn get_escrowed_donations() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        escrow_donation(deps.as_mut(), "new_creator");
+        escrow_donation(deps.as_mut(), "tiktok:new_creator");
+
+        let msg = QueryMsg::WithPermit { permit: permit(), query: QueryWithPermitMsg::GetEscrowedDonations {  } };
+        let res: EscrowedDonationsResponse = from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        let targets: Vec<String> = res.escrows.into_iter().map(|escrow| escrow.target).collect();
+        assert_eq!(targets, vec!["new_creator", "tiktok:new_creator"]);
+
+        let msg = ExecuteMsg::LinkSocialHandle { address: Addr::unchecked(ADDRESS), handle: String::from("tiktok:alpine_fan") };
+        execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+        let msg = QueryMsg::WithPermit { permit: permit(), query: QueryWithPermitMsg::WhoAmI {  } };
+        let res: WhoAmIResponse = from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(res.social_handles, vec!["tiktok:alpine_fan"]);
+    }
+}
+
+// A set of tests for donations which are held in the contract until their recipient withdraws them
+#[cfg(test)]
+mod balance_tests {
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{coin, coins, from_binary, BankMsg, CosmosMsg, Deps, DepsMut, Response, Uint128};
+
+    use super::fixtures::{ADDRESS, bank_send, permit, send_donation, setup_users};
+    use crate::error::ContractError;
+    use crate::execute::execute;
+    use crate::msg::{ExecuteMsg, QueryMsg, QueryWithPermitMsg, BalancesResponse};
+    use crate::query::query;
+
+    const FAN_ADDRESS: &str = "secret1ysehn88p24d7769j4vj07hyndkjj7pccz3j3c9";
+
+    // Register a creator who claims their donations at the permit address, and a fan to donate to them
+    fn setup_contract(mut deps: DepsMut<'_>) {
+        setup_users(deps.branch(), "creator", &[(ADDRESS, "alpine_creator"), (FAN_ADDRESS, "alpine_fan")]);
+
+        let msg = ExecuteMsg::UpdateSettings { discoverable: None, claim_donations: Some(true), public_leaderboard: None };
+        execute(deps, mock_env(), mock_info(ADDRESS, &[]), msg).unwrap();
+    }
+
+    fn donate(deps: DepsMut<'_>, amount: u128, denom: &str) -> Response {
+        send_donation(deps, mock_env(), (FAN_ADDRESS, "alpine_fan"), "alpine_creator", coin(amount, denom))
+    }
+
+    fn query_balances(deps: Deps<'_>) -> BalancesResponse {
+        let msg = QueryMsg::WithPermit { permit: permit(), query: QueryWithPermitMsg::GetBalances {  } };
+        from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap()
+    }
+
+    // Donate to a creator who claims their donations. Nothing should be sent, and the donation credited
+    #[test]
+    fn donation_credits_balance() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let res = donate(deps.as_mut(), 1000, "uscrt");
+        assert_eq!(res.messages.len(), 0);
+        donate(deps.as_mut(), 2000, "uscrt");
+
+        assert_eq!(query_balances(deps.as_ref()).balances, coins(2910, "uscrt"));
+    }
+
+    // Withdraw part of a balance, then more than what's left. The second withdrawal should error out
+    #[test]
+    fn withdraw_partial() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        donate(deps.as_mut(), 1000, "uscrt");
+
+        let msg = ExecuteMsg::Withdraw { denom: String::from("uscrt"), amount: Some(Uint128::new(500)) };
+        let res = execute(deps.as_mut(), mock_env(), mock_info(ADDRESS, &[]), msg).unwrap();
+        assert_eq!(res.messages[0].msg, bank_send(ADDRESS, 500));
+        assert_eq!(query_balances(deps.as_ref()).balances, coins(470, "uscrt"));
+
+        let msg = ExecuteMsg::Withdraw { denom: String::from("uscrt"), amount: Some(Uint128::new(500)) };
+        let res = execute(deps.as_mut(), mock_env(), mock_info(ADDRESS, &[]), msg).unwrap_err();
+        assert_eq!(res, ContractError::InsufficientBalance { denom: String::from("uscrt"), balance: Uint128::new(470) });
+    }
+
+    // Withdraw a whole denom without giving an amount. Other denoms should be left alone
+    #[test]
+    fn withdraw_denom() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        donate(deps.as_mut(), 1000, "uscrt");
+        donate(deps.as_mut(), 100, "uatom");
+
+        let msg = ExecuteMsg::Withdraw { denom: String::from("uscrt"), amount: None };
+        let res = execute(deps.as_mut(), mock_env(), mock_info(ADDRESS, &[]), msg).unwrap();
+        assert_eq!(res.messages[0].msg, bank_send(ADDRESS, 970));
+        assert_eq!(query_balances(deps.as_ref()).balances, coins(97, "uatom"));
+    }
+
+    // Withdraw every denom at once, then try again with nothing left. The second withdrawal should error out
+    #[test]
+    fn withdraw_all() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        donate(deps.as_mut(), 1000, "uscrt");
+        donate(deps.as_mut(), 100, "uatom");
+
+        let res = execute(deps.as_mut(), mock_env(), mock_info(ADDRESS, &[]), ExecuteMsg::WithdrawAll {  }).unwrap();
+        let expected = vec![coin(970, "uscrt"), coin(97, "uatom")];
+        assert_eq!(res.messages[0].msg, CosmosMsg::Bank(BankMsg::Send { to_address: String::from(ADDRESS), amount: expected }));
+        assert_eq!(query_balances(deps.as_ref()).balances, vec![]);
+
+        let res = execute(deps.as_mut(), mock_env(), mock_info(ADDRESS, &[]), ExecuteMsg::WithdrawAll {  }).unwrap_err();
+        assert_eq!(res, ContractError::NothingToWithdraw {  });
+    }
+
+    // Withdraw from an address which has no balance. Should error out
+    #[test]
+    fn withdraw_without_balance() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let msg = ExecuteMsg::Withdraw { denom: String::from("uscrt"), amount: None };
+        let res = execute(deps.as_mut(), mock_env(), mock_info(FAN_ADDRESS, &[]), msg).unwrap_err();
+        assert_eq!(res, ContractError::NothingToWithdraw {  });
+    }
+}
+
+// A set of tests for the fee treasury and paying it out to the team
+#[cfg(test)]
+mod treasury_tests {
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{coin, from_binary, Addr, CosmosMsg, Deps, DepsMut};
+
+    use super::fixtures::{ADDRESS, bank_send, permit, send_donation, setup_users};
+    use crate::error::ContractError;
+    use crate::execute::{execute, instantiate};
+    use crate::msg::{ExecuteMsg, InstantiateMsg, QueryMsg, QueryWithPermitMsg, TreasuryResponse};
+    use crate::query::query;
+    use crate::state::{TeamWallet, DEFAULT_FEE_ADDRESS};
+
+    const FAN_ADDRESS: &str = "secret1ysehn88p24d7769j4vj07hyndkjj7pccz3j3c9";
+    const CREATOR_ADDRESS: &str = "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh";
+    const TEAM_ADDRESS: &str = "secret1ap26qrlp8mcq2pg6r47w43l0y8zkqm8a450s03";
+
+    // Instantiate the contract with the permit address as the owner, and register a fan and a creator
+    fn setup_contract(deps: DepsMut<'_>) {
+        setup_users(deps, ADDRESS, &[(CREATOR_ADDRESS, "alpine_creator"), (FAN_ADDRESS, "alpine_fan")]);
+    }
+
+    fn donate(deps: DepsMut<'_>, amount: u128, denom: &str) {
+        let res = send_donation(deps, mock_env(), (FAN_ADDRESS, "alpine_fan"), "alpine_creator", coin(amount, denom));
+        assert_eq!(res.messages.len(), 1);
+    }
+
+    fn query_treasury(deps: Deps<'_>) -> TreasuryResponse {
+        let msg = QueryMsg::WithPermit { permit: permit(), query: QueryWithPermitMsg::GetTreasury {  } };
+        from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap()
+    }
+
+    fn set_team_wallets(deps: DepsMut<'_>, team_wallets: Vec<(&str, u32)>) -> Result<(), ContractError> {
+        let team_wallets = team_wallets.into_iter()
+            .map(|(address, weight)| TeamWallet { address: Addr::unchecked(address), weight })
+            .collect();
+        let msg = ExecuteMsg::UpdateConfig { disable_address_lookup: None, escrow_timeout: None, team_wallets: Some(team_wallets), fee_schedule: None, leaderboard_min_donors: None };
+        execute(deps, mock_env(), mock_info(ADDRESS, &[]), msg).map(|_| ())
+    }
+
+    // Send donations in two denoms. The fees should accumulate in the treasury per denom
+    #[test]
+    fn fees_accumulate() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        donate(deps.as_mut(), 1000, "uscrt");
+        donate(deps.as_mut(), 2000, "uscrt");
+        donate(deps.as_mut(), 100, "uatom");
+
+        let treasury = query_treasury(deps.as_ref());
+        assert_eq!(treasury.fees, vec![coin(90, "uscrt"), coin(3, "uatom")]);
+        assert_eq!(treasury.team_wallets, vec![TeamWallet { address: Addr::unchecked(DEFAULT_FEE_ADDRESS), weight: 1 }]);
+    }
+
+    // Withdraw the fees with the default team wallet. Everything should go to it, and the treasury should be emptied
+    #[test]
+    fn withdraw_fees_default_wallet() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        donate(deps.as_mut(), 1000, "uscrt");
+
+        let res = execute(deps.as_mut(), mock_env(), mock_info(ADDRESS, &[]), ExecuteMsg::WithdrawFees {  }).unwrap();
+        assert_eq!(res.messages[0].msg, bank_send(DEFAULT_FEE_ADDRESS, 30));
+        assert_eq!(query_treasury(deps.as_ref()).fees, vec![]);
+
+        let err = execute(deps.as_mut(), mock_env(), mock_info(ADDRESS, &[]), ExecuteMsg::WithdrawFees {  }).unwrap_err();
+        assert_eq!(err, ContractError::NothingToWithdraw {  });
+    }
+
+    // Withdraw the fees split between weighted team wallets. The remainder should go to the first wallet
+    #[test]
+    fn withdraw_fees_weighted() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        set_team_wallets(deps.as_mut(), vec![(TEAM_ADDRESS, 2), (CREATOR_ADDRESS, 1)]).unwrap();
+        donate(deps.as_mut(), 3400, "uscrt");
+
+        let res = execute(deps.as_mut(), mock_env(), mock_info(ADDRESS, &[]), ExecuteMsg::WithdrawFees {  }).unwrap();
+        let sends: Vec<CosmosMsg> = res.messages.into_iter().map(|sub_msg| sub_msg.msg).collect();
+        assert_eq!(sends, vec![
+            bank_send(TEAM_ADDRESS, 68),
+            bank_send(CREATOR_ADDRESS, 34)
+        ]);
+    }
+
+    // Configure team wallets without any wallets, or with a wallet without weight. Should error out
+    #[test]
+    fn invalid_team_wallets() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let err = set_team_wallets(deps.as_mut(), vec![]).unwrap_err();
+        assert_eq!(err.code(), "invalid_team_wallets");
+        let err = set_team_wallets(deps.as_mut(), vec![(TEAM_ADDRESS, 1), (CREATOR_ADDRESS, 0)]).unwrap_err();
+        assert_eq!(err.code(), "invalid_team_wallets");
+    }
+
+    // Withdraw fees or view the treasury from an address which isn't the owner. Should error out
+    #[test]
+    fn treasury_owner_only() {
+        let mut deps = mock_dependencies();
+        instantiate(deps.as_mut(), mock_env(), mock_info("creator", &[]), InstantiateMsg {}).unwrap();
+
+        let err = execute(deps.as_mut(), mock_env(), mock_info(ADDRESS, &[]), ExecuteMsg::WithdrawFees {  }).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {  });
+
+        let msg = QueryMsg::WithPermit { permit: permit(), query: QueryWithPermitMsg::GetTreasury {  } };
+        let err = query(deps.as_ref(), mock_env(), msg).unwrap_err();
+        assert_eq!(err.0, ContractError::Unauthorized {  });
+    }
+}
+
+// A set of tests for prepaid subscriptions
+#[cfg(test)]
+mod subscription_tests {
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{coin, coins, from_binary, Addr, CosmosMsg, Deps, DepsMut, Env, Response};
+
+    use super::fixtures::{ADDRESS, bank_send, permit, setup_users};
+    use crate::error::ContractError;
+    use crate::execute::execute;
+    use crate::msg::{ExecuteMsg, QueryMsg, QueryWithPermitMsg, SubscriptionsResponse};
+    use crate::query::query;
+    use crate::state::DEFAULT_SUBSCRIPTION_PERIOD;
+
+    const CREATOR_ADDRESS: &str = "secret1ysehn88p24d7769j4vj07hyndkjj7pccz3j3c9";
+    const OTHER_ADDRESS: &str = "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh";
+
+    // Register a fan at the permit address, a creator, and another user
+    fn setup_contract(deps: DepsMut<'_>) {
+        setup_users(deps, "creator", &[(ADDRESS, "alpine_fan"), (CREATOR_ADDRESS, "alpine_creator"), (OTHER_ADDRESS, "alpine_other")]);
+    }
+
+    fn subscribe(deps: DepsMut<'_>, address: &str, creator: &str, amount: u128, periods: u32) -> Result<Response, ContractError> {
+        let msg = ExecuteMsg::Subscribe { sender: String::new(), creator: String::from(creator), periods, period: None };
+        execute(deps, mock_env(), mock_info(address, &coins(amount, "uscrt")), msg)
+    }
+
+    fn after_periods(periods: u64) -> Env {
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(DEFAULT_SUBSCRIPTION_PERIOD * periods);
+        env
+    }
+
+    fn sent(response: Response) -> Vec<CosmosMsg> {
+        response.messages.into_iter().map(|sub_msg| sub_msg.msg).collect()
+    }
+
+    fn query_subscriptions(deps: Deps<'_>, query_msg: QueryWithPermitMsg) -> SubscriptionsResponse {
+        let msg = QueryMsg::WithPermit { permit: permit(), query: query_msg };
+        from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap()
+    }
+
+    // Claim a subscription period by period. Each period should only be claimable once it has ended
+    #[test]
+    fn claim_each_period() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        subscribe(deps.as_mut(), ADDRESS, "alpine_creator", 3000, 3).unwrap();
+
+        let msg = ExecuteMsg::ClaimSubscription { id: 0 };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(OTHER_ADDRESS, &[]), msg.clone()).unwrap_err();
+        assert_eq!(err.code(), "nothing_to_claim");
+
+        for periods in 1..=3 {
+            let res = execute(deps.as_mut(), after_periods(periods), mock_info(OTHER_ADDRESS, &[]), msg.clone()).unwrap();
+            assert_eq!(sent(res), vec![bank_send(CREATOR_ADDRESS, 970)]);
+
+            let err = execute(deps.as_mut(), after_periods(periods), mock_info(OTHER_ADDRESS, &[]), msg.clone()).unwrap_err();
+            let expected = match periods {
+                3 => "subscription_not_found",
+                _ => "nothing_to_claim"
+            };
+            assert_eq!(err.code(), expected);
+        }
+    }
+
+    // Claim several ended periods at once. Should be paid out in one donation
+    #[test]
+    fn claim_multiple_periods() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        subscribe(deps.as_mut(), ADDRESS, "alpine_creator", 3000, 3).unwrap();
+
+        let msg = ExecuteMsg::ClaimSubscription { id: 0 };
+        let res = execute(deps.as_mut(), after_periods(2), mock_info(CREATOR_ADDRESS, &[]), msg).unwrap();
+        assert_eq!(sent(res), vec![bank_send(CREATOR_ADDRESS, 1940)]);
+    }
+
+    // Cancel a subscription partway through. The creator should be paid for the ended period and the rest refunded
+    #[test]
+    fn cancel_subscription() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        subscribe(deps.as_mut(), ADDRESS, "alpine_creator", 3000, 3).unwrap();
+
+        let msg = ExecuteMsg::CancelSubscription { id: 0 };
+        let err = execute(deps.as_mut(), after_periods(1), mock_info(CREATOR_ADDRESS, &[]), msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {  });
+
+        let res = execute(deps.as_mut(), after_periods(1), mock_info(ADDRESS, &[]), msg.clone()).unwrap();
+        assert_eq!(sent(res), vec![bank_send(CREATOR_ADDRESS, 970), bank_send(ADDRESS, 2000)]);
+
+        let err = execute(deps.as_mut(), after_periods(1), mock_info(ADDRESS, &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::SubscriptionNotFound { id: 0 });
+    }
+
+    // Subscribe with a deposit which doesn't split evenly, or without any periods. Should error out
+    #[test]
+    fn invalid_subscription() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let err = subscribe(deps.as_mut(), ADDRESS, "alpine_creator", 1000, 3).unwrap_err();
+        assert_eq!(err.code(), "invalid_subscription");
+        let err = subscribe(deps.as_mut(), ADDRESS, "alpine_creator", 1000, 0).unwrap_err();
+        assert_eq!(err.code(), "invalid_subscription");
+        let err = subscribe(deps.as_mut(), ADDRESS, "nobody", 1000, 1).unwrap_err();
+        assert_eq!(err.code(), "user_not_found");
+    }
+
+    // Subscribe with a period which is too long, or with too many periods in total. Should error out instead of
+    // overflowing when the periods are claimed
+    #[test]
+    fn subscription_too_long() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        for (periods, period) in [(1, u64::MAX / 2), (1, 367 * 24 * 60 * 60), (11, 365 * 24 * 60 * 60)] {
+            let msg = ExecuteMsg::Subscribe { sender: String::new(), creator: String::from("alpine_creator"), periods, period: Some(period) };
+            let err = execute(deps.as_mut(), mock_env(), mock_info(ADDRESS, &coins(1100, "uscrt")), msg).unwrap_err();
+            assert_eq!(err.code(), "invalid_subscription");
+        }
+
+        let msg = ExecuteMsg::Subscribe { sender: String::new(), creator: String::from("alpine_creator"), periods: 10, period: Some(365 * 24 * 60 * 60) };
+        execute(deps.as_mut(), mock_env(), mock_info(ADDRESS, &coins(1000, "uscrt")), msg).unwrap();
+    }
+
+    // List the subscriptions the permit signer pays for, and the ones paying out to them
+    #[test]
+    fn subscription_queries() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        subscribe(deps.as_mut(), ADDRESS, "alpine_creator", 3000, 3).unwrap();
+        subscribe(deps.as_mut(), OTHER_ADDRESS, "alpine_fan", 500, 5).unwrap();
+        subscribe(deps.as_mut(), OTHER_ADDRESS, "alpine_creator", 500, 5).unwrap();
+
+        let res = query_subscriptions(deps.as_ref(), QueryWithPermitMsg::GetSubscriptions {  });
+        assert_eq!(res.subscriptions.len(), 1);
+        assert_eq!(res.subscriptions[0].creator.username, "alpine_creator");
+        assert_eq!(res.subscriptions[0].rate, coin(1000, "uscrt"));
+
+        let res = query_subscriptions(deps.as_ref(), QueryWithPermitMsg::GetSubscribers {  });
+        assert_eq!(res.subscriptions.len(), 1);
+        assert_eq!(res.subscriptions[0].subscriber.address, Addr::unchecked(OTHER_ADDRESS));
+    }
+}
+
+// A set of tests for creator membership tiers
+#[cfg(test)]
+mod tier_tests {
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{coin, coins, from_binary, Addr, Binary, Deps, DepsMut, Env, Uint128};
+    use secret_toolkit_permit::{Permit, PermitParams, PermitSignature, PubKey};
+
+    use super::fixtures::{ADDRESS, permit, register_user, send_donation, setup_users};
+    use crate::error::{ContractError, QueryError};
+    use crate::execute::execute;
+    use crate::msg::{AlpinePermission, ExecuteMsg, QueryMsg, QueryWithPermitMsg, CreatorTiersResponse, SupporterTierResponse};
+    use crate::query::query;
+    use crate::state::{Tier, DEFAULT_SUBSCRIPTION_PERIOD, DEFAULT_TIER_WINDOW};
+
+    const CREATOR_ADDRESS: &str = "secret1ysehn88p24d7769j4vj07hyndkjj7pccz3j3c9";
+    // A second supporter, whose permit only carries the supporter_tier permission
+    const TIER_ADDRESS: &str = "secret1zke78m3psexztaqal3690vy8kusd9z773jnvxz";
+    const TIER_PUBLIC_KEY: &str = "AkqdXE227VLDopnlJExjM512wd8WM4HWEvf/XSKO9AKB";
+    const TIER_SIGNATURE: &str = "JDaiPv/WUeWwT8W2nhsDA89sz/bJsCF6Qptn8IXBgNM4wXPJeXpUbQKkSs8rUb6lQxPC/Mla32OXIoG5dt5Naw==";
+
+    fn tier_permit() -> Permit<AlpinePermission> {
+        Permit {
+            params: PermitParams {
+                allowed_tokens: vec![String::from("cosmos2contract")],
+                permit_name: String::from("tier"),
+                chain_id: String::from("secret-4"),
+                permissions: vec![AlpinePermission::SupporterTier]
+            },
+            signature: PermitSignature {
+                pub_key: PubKey {
+                    r#type: String::from("tendermint/PubKeySecp256k1"),
+                    value: Binary::from_base64(TIER_PUBLIC_KEY).unwrap(),
+                },
+                signature: Binary::from_base64(TIER_SIGNATURE).unwrap()
+            },
+        }
+    }
+
+    fn tier(name: &str, amount: u128) -> Tier {
+        Tier { name: String::from(name), denom: String::from("uscrt"), amount: Uint128::new(amount) }
+    }
+
+    // Register a fan at the permit address, and a creator with a bronze and a silver tier
+    fn setup_contract(mut deps: DepsMut<'_>) {
+        setup_users(deps.branch(), "creator", &[(ADDRESS, "alpine_fan"), (CREATOR_ADDRESS, "alpine_creator")]);
+
+        let msg = ExecuteMsg::SetTiers { tiers: vec![tier("Bronze", 1000), tier("Silver", 2000)], window: None };
+        execute(deps, mock_env(), mock_info(CREATOR_ADDRESS, &[]), msg).unwrap();
+    }
+
+    fn donate(deps: DepsMut<'_>, amount: u128) {
+        send_donation(deps, mock_env(), (ADDRESS, "alpine_fan"), "alpine_creator", coin(amount, "uscrt"));
+    }
+
+    fn supporter_tier(deps: Deps<'_>, env: Env) -> Option<String> {
+        let msg = QueryMsg::WithPermit {
+            permit: permit(),
+            query: QueryWithPermitMsg::GetSupporterTier { creator: String::from("alpine_creator") }
+        };
+        let res: SupporterTierResponse = from_binary(&query(deps, env, msg).unwrap()).unwrap();
+        assert_eq!(res.supporter, Addr::unchecked(ADDRESS));
+        res.tier.map(|tier| tier.name)
+    }
+
+    fn later(seconds: u64) -> Env {
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(seconds);
+        env
+    }
+
+    // Donate to a creator. The supporter should reach the highest tier covered by donations within the window
+    #[test]
+    fn tier_from_donations() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        assert_eq!(supporter_tier(deps.as_ref(), mock_env()), None);
+
+        donate(deps.as_mut(), 1500);
+        assert_eq!(supporter_tier(deps.as_ref(), mock_env()), Some(String::from("Bronze")));
+
+        donate(deps.as_mut(), 500);
+        assert_eq!(supporter_tier(deps.as_ref(), mock_env()), Some(String::from("Silver")));
+
+        // Once the donations fall out of the window, the tier is lost
+        assert_eq!(supporter_tier(deps.as_ref(), later(DEFAULT_TIER_WINDOW)), None);
+    }
+
+    // Hand a permit with only the supporter_tier permission to another contract. It should answer the tier
+    // query, and be refused for any other permit query
+    #[test]
+    fn tier_only_permit() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        register_user(deps.as_mut(), TIER_ADDRESS, "alpine_fan_2");
+        send_donation(deps.as_mut(), mock_env(), (TIER_ADDRESS, "alpine_fan_2"), "alpine_creator", coin(1000, "uscrt"));
+
+        let msg = QueryMsg::WithPermit {
+            permit: tier_permit(),
+            query: QueryWithPermitMsg::GetSupporterTier { creator: String::from("alpine_creator") }
+        };
+        let res: SupporterTierResponse = from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(res.supporter, Addr::unchecked(TIER_ADDRESS));
+        assert_eq!(res.tier.map(|tier| tier.name), Some(String::from("Bronze")));
+
+        let queries = [
+            QueryWithPermitMsg::WhoAmI {  },
+            QueryWithPermitMsg::GetBalances {  },
+            QueryWithPermitMsg::GetSentDonations { sender: String::from("alpine_fan_2"), filter: None, order: None, limit: None, start_after: None }
+        ];
+        for permit_query in queries {
+            let msg = QueryMsg::WithPermit { permit: tier_permit(), query: permit_query };
+            let err = query(deps.as_ref(), mock_env(), msg).unwrap_err();
+            assert_eq!(err, QueryError(ContractError::InvalidPermit { reason: String::from("permit only allows get_supporter_tier") }));
+        }
+    }
+
+    // Subscribe to a creator. The subscription should count for its rate while it's active, without
+    // counting its payouts a second time
+    #[test]
+    fn tier_from_subscription() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let msg = ExecuteMsg::Subscribe { sender: String::new(), creator: String::from("alpine_creator"), periods: 3, period: None };
+        execute(deps.as_mut(), mock_env(), mock_info(ADDRESS, &coins(3000, "uscrt")), msg).unwrap();
+        assert_eq!(supporter_tier(deps.as_ref(), mock_env()), Some(String::from("Bronze")));
+
+        let env = later(DEFAULT_SUBSCRIPTION_PERIOD);
+        execute(deps.as_mut(), env.clone(), mock_info(CREATOR_ADDRESS, &[]), ExecuteMsg::ClaimSubscription { id: 0 }).unwrap();
+        assert_eq!(supporter_tier(deps.as_ref(), env), Some(String::from("Bronze")));
+
+        assert_eq!(supporter_tier(deps.as_ref(), later(DEFAULT_SUBSCRIPTION_PERIOD * 3)), None);
+    }
+
+    // Define tiers with a repeated name, without an amount or out of order, or from an unregistered address. Should
+    // error out
+    #[test]
+    fn set_tiers_invalid() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let msg = ExecuteMsg::SetTiers { tiers: vec![tier("Gold", 1000), tier("Gold", 2000)], window: None };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(CREATOR_ADDRESS, &[]), msg).unwrap_err();
+        assert_eq!(err.code(), "invalid_tiers");
+
+        let msg = ExecuteMsg::SetTiers { tiers: vec![tier("Gold", 0)], window: None };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(CREATOR_ADDRESS, &[]), msg).unwrap_err();
+        assert_eq!(err.code(), "invalid_tiers");
+
+        // Tiers in the same denom have to go from lowest to highest
+        let msg = ExecuteMsg::SetTiers { tiers: vec![tier("Gold", 5000), tier("Bronze", 1000)], window: None };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(CREATOR_ADDRESS, &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidTiers { reason: String::from("Bronze needs more uscrt than the tiers before it") });
+
+        let msg = ExecuteMsg::SetTiers { tiers: vec![tier("Gold", 1000)], window: None };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("secret1unregistered", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::UserNotFound { user: String::from("secret1unregistered") });
+    }
+
+    // Look up a creator's tiers, then remove them. Should return the tiers, then nothing
+    #[test]
+    fn get_creator_tiers() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let msg = QueryMsg::GetCreatorTiers { creator: String::from("alpine_creator") };
+        let res: CreatorTiersResponse = from_binary(&query(deps.as_ref(), mock_env(), msg.clone()).unwrap()).unwrap();
+        assert_eq!(res.tiers, vec![tier("Bronze", 1000), tier("Silver", 2000)]);
+        assert_eq!(res.window, DEFAULT_TIER_WINDOW);
+
+        let remove = ExecuteMsg::SetTiers { tiers: vec![], window: None };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR_ADDRESS, &[]), remove).unwrap();
+        let res: CreatorTiersResponse = from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(res.tiers, vec![]);
+    }
+}
+
+// A set of tests for fundraising campaigns
+#[cfg(test)]
+mod campaign_tests {
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{coin, coins, from_binary, DepsMut, Env, Response, Timestamp, Uint128};
+
+    use super::fixtures::{attribute, bank_send, setup_users};
+    use crate::error::ContractError;
+    use crate::execute::execute;
+    use crate::msg::{ExecuteMsg, QueryMsg, CampaignResponse, CampaignsResponse};
+    use crate::query::query;
+    use crate::state::donation_count;
+
+    const FAN_ADDRESS: &str = "secret1h3jx4rjkry20pctnzfj7ek8t4v4zaev2rn0rk2";
+    const CREATOR_ADDRESS: &str = "secret1ysehn88p24d7769j4vj07hyndkjj7pccz3j3c9";
+    const OTHER_ADDRESS: &str = "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh";
+    const WEEK: u64 = 7 * 24 * 60 * 60;
+
+    // Register a fan, a creator with a campaign for 500 SCRT, and another creator
+    fn setup_contract(mut deps: DepsMut<'_>) {
+        setup_users(deps.branch(), "creator", &[(FAN_ADDRESS, "alpine_fan"), (CREATOR_ADDRESS, "alpine_creator"), (OTHER_ADDRESS, "alpine_other")]);
+        create_campaign(deps, CREATOR_ADDRESS, mock_env().block.time.plus_seconds(WEEK)).unwrap();
+    }
+
+    fn create_campaign(deps: DepsMut<'_>, address: &str, deadline: Timestamp) -> Result<Response, ContractError> {
+        let msg = ExecuteMsg::CreateCampaign {
+            description: String::from("help me buy a new camera"),
+            target: coin(500, "uscrt"),
+            deadline,
+            all_or_nothing: None
+        };
+        execute(deps, mock_env(), mock_info(address, &[]), msg)
+    }
+
+    // Create an all-or-nothing campaign for 500 SCRT, which gets ID 1
+    fn create_all_or_nothing_campaign(deps: DepsMut<'_>) {
+        let msg = ExecuteMsg::CreateCampaign {
+            description: String::from("fund my album"),
+            target: coin(500, "uscrt"),
+            deadline: mock_env().block.time.plus_seconds(WEEK),
+            all_or_nothing: Some(true)
+        };
+        execute(deps, mock_env(), mock_info(CREATOR_ADDRESS, &[]), msg).unwrap();
+    }
+
+    fn after_deadline() -> Env {
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(WEEK);
+        env
+    }
+
+    fn donate(deps: DepsMut<'_>, env: Env, recipient: &str, amount: u128, denom: &str, campaign_id: u64) -> Result<Response, ContractError> {
+        let msg = ExecuteMsg::SendDonation {
+            sender: String::from("alpine_fan"),
+            recipient: String::from(recipient),
+            message: String::from(""),
+            campaign_id: Some(campaign_id)
+        };
+        execute(deps, env, mock_info(FAN_ADDRESS, &coins(amount, denom)), msg)
+    }
+
+    // Donate toward a campaign. The campaign should show its progress without revealing the donor
+    #[test]
+    fn campaign_progress() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        donate(deps.as_mut(), mock_env(), "alpine_creator", 100, "uscrt", 0).unwrap();
+        donate(deps.as_mut(), mock_env(), "alpine_creator", 60, "uscrt", 0).unwrap();
+
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::GetCampaign { id: 0 }).unwrap();
+        let campaign: CampaignResponse = from_binary(&res).unwrap();
+        assert_eq!(campaign.creator, "alpine_creator");
+        assert_eq!(campaign.raised, Uint128::new(160));
+        assert_eq!(campaign.progress_percent, 32);
+        assert_eq!(campaign.donation_count, 2);
+
+        let json = String::from_utf8(res.to_vec()).unwrap();
+        assert!(!json.contains("alpine_fan") && !json.contains(FAN_ADDRESS));
+    }
+
+    // Donate toward a campaign of another creator, in the wrong denom, after the deadline, or toward a campaign
+    // which doesn't exist. Should error out
+    #[test]
+    fn campaign_donation_invalid() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let err = donate(deps.as_mut(), mock_env(), "alpine_other", 100, "uscrt", 0).unwrap_err();
+        assert_eq!(err.code(), "invalid_campaign");
+        let err = donate(deps.as_mut(), mock_env(), "alpine_creator", 100, "uatom", 0).unwrap_err();
+        assert_eq!(err.code(), "invalid_campaign");
+        let err = donate(deps.as_mut(), mock_env(), "alpine_creator", 100, "uscrt", 1).unwrap_err();
+        assert_eq!(err, ContractError::CampaignNotFound { id: 1 });
+
+        let err = donate(deps.as_mut(), after_deadline(), "alpine_creator", 100, "uscrt", 0).unwrap_err();
+        assert_eq!(err.code(), "campaign_ended");
+    }
+
+    // Create a campaign with a deadline in the past, or from an unregistered address. Should error out
+    #[test]
+    fn create_campaign_invalid() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let err = create_campaign(deps.as_mut(), CREATOR_ADDRESS, mock_env().block.time).unwrap_err();
+        assert_eq!(err.code(), "invalid_campaign");
+        let err = create_campaign(deps.as_mut(), "secret1unregistered", mock_env().block.time.plus_seconds(WEEK)).unwrap_err();
+        assert_eq!(err.code(), "user_not_found");
+    }
+
+    // Pledge enough to an all-or-nothing campaign to meet its target. The creator should only be able to claim
+    // the pledges after the deadline, minus the fee, and donors shouldn't get a refund
+    #[test]
+    fn all_or_nothing_claim() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        create_all_or_nothing_campaign(deps.as_mut());
+
+        let res = donate(deps.as_mut(), mock_env(), "alpine_creator", 300, "uscrt", 1).unwrap();
+        assert_eq!(res.messages.len(), 0);
+        donate(deps.as_mut(), mock_env(), "alpine_creator", 200, "uscrt", 1).unwrap();
+        assert_eq!(donation_count(&deps.storage).unwrap(), 0);
+
+        let claim = ExecuteMsg::ClaimCampaign { campaign_id: 1, limit: None };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(CREATOR_ADDRESS, &[]), claim.clone()).unwrap_err();
+        assert_eq!(err.code(), "campaign_not_ended");
+        let err = execute(deps.as_mut(), after_deadline(), mock_info(FAN_ADDRESS, &[]), claim.clone()).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {  });
+
+        let res = execute(deps.as_mut(), after_deadline(), mock_info(CREATOR_ADDRESS, &[]), claim.clone()).unwrap();
+        assert_eq!(res.messages[0].msg, bank_send(CREATOR_ADDRESS, 485));
+        assert_eq!(donation_count(&deps.storage).unwrap(), 2);
+
+        let err = execute(deps.as_mut(), after_deadline(), mock_info(CREATOR_ADDRESS, &[]), claim).unwrap_err();
+        assert_eq!(err.code(), "invalid_campaign");
+        let refund = ExecuteMsg::Refund { campaign_id: 1 };
+        let err = execute(deps.as_mut(), after_deadline(), mock_info(FAN_ADDRESS, &[]), refund).unwrap_err();
+        assert_eq!(err, ContractError::CampaignTargetMet {  });
+    }
+
+    // Pledge many small donations to an all-or-nothing campaign. The creator should be able to claim them a page
+    // at a time until none are left
+    #[test]
+    fn all_or_nothing_claim_in_pages() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        create_all_or_nothing_campaign(deps.as_mut());
+        for _ in 0..5 {
+            donate(deps.as_mut(), mock_env(), "alpine_creator", 100, "uscrt", 1).unwrap();
+        }
+
+        let claim = ExecuteMsg::ClaimCampaign { campaign_id: 1, limit: Some(2) };
+        let res = execute(deps.as_mut(), after_deadline(), mock_info(CREATOR_ADDRESS, &[]), claim.clone()).unwrap();
+        assert_eq!(res.messages[0].msg, bank_send(CREATOR_ADDRESS, 194));
+        assert_eq!(attribute(&res, "pending_pledges"), "3");
+        execute(deps.as_mut(), after_deadline(), mock_info(CREATOR_ADDRESS, &[]), claim.clone()).unwrap();
+        let res = execute(deps.as_mut(), after_deadline(), mock_info(CREATOR_ADDRESS, &[]), claim.clone()).unwrap();
+        assert_eq!(res.messages[0].msg, bank_send(CREATOR_ADDRESS, 97));
+        assert_eq!(attribute(&res, "pending_pledges"), "0");
+        assert_eq!(donation_count(&deps.storage).unwrap(), 5);
+
+        let err = execute(deps.as_mut(), after_deadline(), mock_info(CREATOR_ADDRESS, &[]), claim).unwrap_err();
+        assert_eq!(err.code(), "invalid_campaign");
+    }
+
+    // Pledge to an all-or-nothing campaign which misses its target. The donor should get their exact coins back
+    // once, and the creator shouldn't be able to claim anything
+    #[test]
+    fn all_or_nothing_refund() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        create_all_or_nothing_campaign(deps.as_mut());
+        donate(deps.as_mut(), mock_env(), "alpine_creator", 100, "uscrt", 1).unwrap();
+        donate(deps.as_mut(), mock_env(), "alpine_creator", 150, "uscrt", 1).unwrap();
+
+        let refund = ExecuteMsg::Refund { campaign_id: 1 };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(FAN_ADDRESS, &[]), refund.clone()).unwrap_err();
+        assert_eq!(err.code(), "campaign_not_ended");
+
+        let claim = ExecuteMsg::ClaimCampaign { campaign_id: 1, limit: None };
+        let err = execute(deps.as_mut(), after_deadline(), mock_info(CREATOR_ADDRESS, &[]), claim).unwrap_err();
+        assert_eq!(err, ContractError::CampaignTargetNotMet {  });
+
+        let err = execute(deps.as_mut(), after_deadline(), mock_info(OTHER_ADDRESS, &[]), refund.clone()).unwrap_err();
+        assert_eq!(err, ContractError::NothingToRefund {  });
+
+        let res = execute(deps.as_mut(), after_deadline(), mock_info(FAN_ADDRESS, &[]), refund.clone()).unwrap();
+        assert_eq!(res.messages[0].msg, bank_send(FAN_ADDRESS, 250));
+        assert_eq!(donation_count(&deps.storage).unwrap(), 0);
+
+        let err = execute(deps.as_mut(), after_deadline(), mock_info(FAN_ADDRESS, &[]), refund).unwrap_err();
+        assert_eq!(err, ContractError::NothingToRefund {  });
+    }
+
+    // Try to refund a donation to a regular campaign. Should error out, as those donations are forwarded right away
+    #[test]
+    fn refund_regular_campaign() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        donate(deps.as_mut(), mock_env(), "alpine_creator", 100, "uscrt", 0).unwrap();
+
+        let refund = ExecuteMsg::Refund { campaign_id: 0 };
+        let err = execute(deps.as_mut(), after_deadline(), mock_info(FAN_ADDRESS, &[]), refund).unwrap_err();
+        assert_eq!(err.code(), "invalid_campaign");
+    }
+
+    // List the campaigns of a creator. Should only return their own campaigns
+    #[test]
+    fn get_campaigns() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        create_campaign(deps.as_mut(), OTHER_ADDRESS, mock_env().block.time.plus_seconds(WEEK)).unwrap();
+        create_campaign(deps.as_mut(), CREATOR_ADDRESS, mock_env().block.time.plus_seconds(WEEK)).unwrap();
+
+        let msg = QueryMsg::GetCampaigns { creator: String::from("alpine_creator") };
+        let res: CampaignsResponse = from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        let ids: Vec<u64> = res.campaigns.iter().map(|campaign| campaign.id).collect();
+        assert_eq!(ids, vec![0, 2]);
+    }
+}
+
+// A set of tests for splitting donations between collaborators
+#[cfg(test)]
+mod split_tests {
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{coin, coins, from_binary, CosmosMsg, DepsMut, Response};
+
+    use super::fixtures::{ADDRESS, bank_send, permit, send_donation, setup_users};
+    use crate::error::ContractError;
+    use crate::execute::execute;
+    use crate::msg::{ExecuteMsg, PayoutShareMsg, QueryMsg, QueryWithPermitMsg, BalancesResponse};
+    use crate::query::query;
+
+    const FAN_ADDRESS: &str = "secret1ysehn88p24d7769j4vj07hyndkjj7pccz3j3c9";
+    const CREATOR_ADDRESS: &str = "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh";
+    const GUEST_ADDRESS: &str = "secret1ap26qrlp8mcq2pg6r47w43l0y8zkqm8a450s03";
+
+    // Register a fan, a podcast host and their co-host, who is at the permit address
+    fn setup_contract(deps: DepsMut<'_>) {
+        setup_users(deps, "creator", &[(FAN_ADDRESS, "alpine_fan"), (CREATOR_ADDRESS, "alpine_host"), (ADDRESS, "alpine_cohost")]);
+    }
+
+    fn set_split(deps: DepsMut<'_>, address: &str, shares: Vec<(&str, u32)>) -> Result<Response, ContractError> {
+        let shares = shares.into_iter()
+            .map(|(recipient, weight)| PayoutShareMsg { recipient: String::from(recipient), weight })
+            .collect();
+        execute(deps, mock_env(), mock_info(address, &[]), ExecuteMsg::SetPayoutSplit { shares })
+    }
+
+    fn donate(deps: DepsMut<'_>, amount: u128) -> Response {
+        send_donation(deps, mock_env(), (FAN_ADDRESS, "alpine_fan"), "alpine_host", coin(amount, "uscrt"))
+    }
+
+    // Donate to a user who splits their donations three ways. Every collaborator should get their share, with
+    // the rounding remainder going to the first one so that nothing is left in the contract
+    #[test]
+    fn split_donation() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        set_split(deps.as_mut(), CREATOR_ADDRESS, vec![("alpine_host", 5000), ("alpine_cohost", 3333), (GUEST_ADDRESS, 1667)]).unwrap();
+
+        let res = donate(deps.as_mut(), 1000);
+        let sends: Vec<CosmosMsg> = res.messages.into_iter().map(|sub_msg| sub_msg.msg).collect();
+        assert_eq!(sends, vec![bank_send(CREATOR_ADDRESS, 486), bank_send(ADDRESS, 323), bank_send(GUEST_ADDRESS, 161)]);
+    }
+
+    // Donate to a user who splits their donations and claims them. Each share should be credited to its collaborator
+    #[test]
+    fn split_claimed_donation() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        set_split(deps.as_mut(), CREATOR_ADDRESS, vec![("alpine_host", 5000), ("alpine_cohost", 5000)]).unwrap();
+        let msg = ExecuteMsg::UpdateSettings { discoverable: None, claim_donations: Some(true), public_leaderboard: None };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR_ADDRESS, &[]), msg).unwrap();
+
+        let res = donate(deps.as_mut(), 1000);
+        assert_eq!(res.messages.len(), 0);
+
+        let msg = QueryMsg::WithPermit { permit: permit(), query: QueryWithPermitMsg::GetBalances {  } };
+        let res: BalancesResponse = from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(res.balances, coins(485, "uscrt"));
+    }
+
+    // Set splits whose weights don't add up to 100%, which list a collaborator twice, or which have an empty share.
+    // Should error out
+    #[test]
+    fn set_split_invalid() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let err = set_split(deps.as_mut(), CREATOR_ADDRESS, vec![("alpine_host", 5000), ("alpine_cohost", 4000)]).unwrap_err();
+        assert_eq!(err.code(), "invalid_payout_split");
+        let err = set_split(deps.as_mut(), CREATOR_ADDRESS, vec![("alpine_cohost", 5000), (ADDRESS, 5000)]).unwrap_err();
+        assert_eq!(err.code(), "invalid_payout_split");
+        let err = set_split(deps.as_mut(), CREATOR_ADDRESS, vec![("alpine_host", 10000), ("alpine_cohost", 0)]).unwrap_err();
+        assert_eq!(err.code(), "invalid_payout_split");
+    }
+
+    // Set a split from an unregistered address, and from another user. Neither should change the host's donations
+    #[test]
+    fn split_requires_owner() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let err = set_split(deps.as_mut(), GUEST_ADDRESS, vec![(GUEST_ADDRESS, 10000)]).unwrap_err();
+        assert_eq!(err, ContractError::UserNotFound { user: String::from(GUEST_ADDRESS) });
+
+        set_split(deps.as_mut(), ADDRESS, vec![("alpine_cohost", 5000), (GUEST_ADDRESS, 5000)]).unwrap();
+        let res = donate(deps.as_mut(), 1000);
+        assert_eq!(res.messages[0].msg, bank_send(CREATOR_ADDRESS, 970));
+    }
+}
+
+// A set of tests for sending donations to several recipients at once
+#[cfg(test)]
+mod batch_tests {
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{coin, coins, BankMsg, Coin, CosmosMsg, DepsMut, Response};
+
+    use super::fixtures::setup_users;
+    use crate::error::ContractError;
+    use crate::execute::execute;
+    use crate::msg::{DonationItem, ExecuteMsg};
+    use crate::state::{donation_count, load_donations};
+
+    const FAN_ADDRESS: &str = "secret1h3jx4rjkry20pctnzfj7ek8t4v4zaev2rn0rk2";
+    const CREATOR_A_ADDRESS: &str = "secret1ysehn88p24d7769j4vj07hyndkjj7pccz3j3c9";
+    const CREATOR_B_ADDRESS: &str = "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh";
+
+    fn setup_contract(deps: DepsMut<'_>) {
+        setup_users(deps, "creator", &[(FAN_ADDRESS, "alpine_fan"), (CREATOR_A_ADDRESS, "creator_a"), (CREATOR_B_ADDRESS, "creator_b")]);
+    }
+
+    fn item(recipient: &str, amount: Coin) -> DonationItem {
+        DonationItem { recipient: String::from(recipient), amount, message: format!("shout-out to {}", recipient) }
+    }
+
+    fn send_donations(deps: DepsMut<'_>, items: Vec<DonationItem>, funds: &[Coin]) -> Result<Response, ContractError> {
+        let msg = ExecuteMsg::SendDonations { sender: String::from("alpine_fan"), items };
+        execute(deps, mock_env(), mock_info(FAN_ADDRESS, funds), msg)
+    }
+
+    // Donate to several creators at once. Each item should be recorded as its own donation, with the transfers to
+    // each creator combined
+    #[test]
+    fn batch_donation() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let items = vec![item("creator_a", coin(1000, "uscrt")), item("creator_b", coin(100, "uatom")), item("creator_a", coin(2000, "uscrt"))];
+        let res = send_donations(deps.as_mut(), items, &[coin(3000, "uscrt"), coin(100, "uatom")]).unwrap();
+
+        let sends: Vec<CosmosMsg> = res.messages.into_iter().map(|sub_msg| sub_msg.msg).collect();
+        assert_eq!(sends, vec![
+            CosmosMsg::Bank(BankMsg::Send { to_address: String::from(CREATOR_A_ADDRESS), amount: coins(2910, "uscrt") }),
+            CosmosMsg::Bank(BankMsg::Send { to_address: String::from(CREATOR_B_ADDRESS), amount: coins(97, "uatom") })
+        ]);
+        assert_eq!(res.attributes[0].value, "0,1,2");
+
+        let donations = load_donations(&deps.storage, vec![0, 1, 2]);
+        let messages: Vec<&str> = donations.iter().map(|donation| donation.message.as_str()).collect();
+        assert_eq!(messages, vec!["shout-out to creator_a", "shout-out to creator_b", "shout-out to creator_a"]);
+    }
+
+    // Send funds which don't match the items, too little or too much. Should error out
+    #[test]
+    fn batch_funds_mismatch() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        let items = vec![item("creator_a", coin(1000, "uscrt")), item("creator_b", coin(1000, "uscrt"))];
+
+        let err = send_donations(deps.as_mut(), items.clone(), &coins(1500, "uscrt")).unwrap_err();
+        assert_eq!(err, ContractError::FundsMismatch { expected: String::from("2000uscrt"), received: String::from("1500uscrt") });
+        let err = send_donations(deps.as_mut(), items.clone(), &[coin(2000, "uscrt"), coin(1, "uatom")]).unwrap_err();
+        assert_eq!(err.code(), "funds_mismatch");
+        let err = send_donations(deps.as_mut(), items, &coins(2000, "uatom")).unwrap_err();
+        assert_eq!(err.code(), "funds_mismatch");
+    }
+
+    // Include a recipient who doesn't exist in a batch. The whole batch should fail
+    #[test]
+    fn batch_fails_as_a_whole() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let items = vec![item("creator_a", coin(1000, "uscrt")), item("nobody", coin(1000, "uscrt"))];
+        let err = send_donations(deps.as_mut(), items, &coins(2000, "uscrt")).unwrap_err();
+        assert_eq!(err, ContractError::UserNotFound { user: String::from("nobody") });
+        assert_eq!(donation_count(&deps.storage).unwrap(), 0);
+
+        let err = send_donations(deps.as_mut(), vec![], &[]).unwrap_err();
+        assert_eq!(err, ContractError::NoDonation {  });
+    }
+}
+
+// A set of tests for creators passing part of their donations on to a charity
+#[cfg(test)]
+mod charity_tests {
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{coin, coins, from_binary, Addr, CosmosMsg, DepsMut, Response};
+
+    use super::fixtures::{ADDRESS, bank_send, permit, send_donation, setup_users};
+    use crate::error::ContractError;
+    use crate::execute::execute;
+    use crate::msg::{ExecuteMsg, QueryMsg, QueryWithPermitMsg, MultiDonationResponse, CharityPledgeResponse};
+    use crate::query::query;
+    use crate::state::{CharityDonation, CharityPledge, load_balances};
+
+    const CREATOR_ADDRESS: &str = "secret1ysehn88p24d7769j4vj07hyndkjj7pccz3j3c9";
+    const CHARITY_ADDRESS: &str = "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh";
+
+    // Register a fan at the permit address, a creator and a charity
+    fn setup_contract(deps: DepsMut<'_>) {
+        setup_users(deps, "creator", &[(ADDRESS, "alpine_fan"), (CREATOR_ADDRESS, "alpine_creator"), (CHARITY_ADDRESS, "alpine_charity")]);
+    }
+
+    fn set_pledge(deps: DepsMut<'_>, charity: &str, percent: u32) -> Result<Response, ContractError> {
+        let msg = ExecuteMsg::SetCharityPledge { charity: String::from(charity), percent };
+        execute(deps, mock_env(), mock_info(CREATOR_ADDRESS, &[]), msg)
+    }
+
+    fn donate(deps: DepsMut<'_>, amount: u128) -> Response {
+        send_donation(deps, mock_env(), (ADDRESS, "alpine_fan"), "alpine_creator", coin(amount, "uscrt"))
+    }
+
+    // Donate to a creator who pledged 10% to a charity. The charity should get 10% of what's left after the fee,
+    // and the donation should record it for the donor to see
+    #[test]
+    fn charity_pledge_donation() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        set_pledge(deps.as_mut(), "alpine_charity", 10).unwrap();
+
+        let res = donate(deps.as_mut(), 1000);
+        let sends: Vec<CosmosMsg> = res.messages.into_iter().map(|sub_msg| sub_msg.msg).collect();
+        assert_eq!(sends, vec![bank_send(CHARITY_ADDRESS, 97), bank_send(CREATOR_ADDRESS, 873)]);
+
+        let msg = QueryMsg::WithPermit { permit: permit(), query: QueryWithPermitMsg::GetSentDonations { sender: String::from("alpine_fan"), filter: None, order: None , limit: None, start_after: None} };
+        let res: MultiDonationResponse = from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        let charity = CharityDonation { address: Addr::unchecked(CHARITY_ADDRESS), amount: coin(97, "uscrt") };
+        assert_eq!(res.donations[0].charity, Some(charity));
+        assert_eq!(res.donations[0].net, Some(coin(873, "uscrt")));
+    }
+
+    // Donate to a creator who claims their donations. The charity's share should still be sent right away
+    #[test]
+    fn charity_pledge_claimed_donation() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        set_pledge(deps.as_mut(), CHARITY_ADDRESS, 50).unwrap();
+        let msg = ExecuteMsg::UpdateSettings { discoverable: None, claim_donations: Some(true), public_leaderboard: None };
+        execute(deps.as_mut(), mock_env(), mock_info(CREATOR_ADDRESS, &[]), msg).unwrap();
+
+        let res = donate(deps.as_mut(), 1000);
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(res.messages[0].msg, bank_send(CHARITY_ADDRESS, 485));
+        assert_eq!(load_balances(&deps.storage, &Addr::unchecked(CREATOR_ADDRESS)), coins(485, "uscrt"));
+    }
+
+    // Set pledges over 100%, to the creator themself, and to an unknown charity. Should error out
+    #[test]
+    fn set_pledge_invalid() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let err = set_pledge(deps.as_mut(), "alpine_charity", 101).unwrap_err();
+        assert_eq!(err.code(), "invalid_charity_pledge");
+        let err = set_pledge(deps.as_mut(), "alpine_creator", 10).unwrap_err();
+        assert_eq!(err.code(), "invalid_charity_pledge");
+        let err = set_pledge(deps.as_mut(), "Unknown Charity", 10).unwrap_err();
+        assert_eq!(err, ContractError::UserNotFound { user: String::from("Unknown Charity") });
+    }
+
+    // Look up a pledge, then remove it. Donations should go to the creator in full again
+    #[test]
+    fn remove_pledge() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        set_pledge(deps.as_mut(), "alpine_charity", 25).unwrap();
+
+        let msg = QueryMsg::GetCharityPledge { creator: String::from("alpine_creator") };
+        let res: CharityPledgeResponse = from_binary(&query(deps.as_ref(), mock_env(), msg.clone()).unwrap()).unwrap();
+        assert_eq!(res.pledge, Some(CharityPledge { address: Addr::unchecked(CHARITY_ADDRESS), percent: 25 }));
+
+        set_pledge(deps.as_mut(), "", 0).unwrap();
+        let res: CharityPledgeResponse = from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(res.pledge, None);
+        let res = donate(deps.as_mut(), 1000);
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(res.messages[0].msg, bank_send(CREATOR_ADDRESS, 970));
+    }
+}
+
+// A set of tests for the fees the owner agrees with particular recipients
+#[cfg(test)]
+mod fee_override_tests {
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{coin, from_binary, DepsMut, Env, Response, Timestamp};
+
+    use super::fixtures::{bank_send, send_donation, setup_users};
+    use crate::error::ContractError;
+    use crate::execute::execute;
+    use crate::msg::{ExecuteMsg, QueryMsg, FeeRateResponse};
+    use crate::query::query;
+    use crate::state::{FeeOverride, load_donations};
+
+    const FAN_ADDRESS: &str = "secret1ysehn88p24d7769j4vj07hyndkjj7pccz3j3c9";
+    const CHARITY_ADDRESS: &str = "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh";
+
+    // Register a fan and a charity. The contract owner is "creator"
+    fn setup_contract(deps: DepsMut<'_>) {
+        setup_users(deps, "creator", &[(FAN_ADDRESS, "alpine_fan"), (CHARITY_ADDRESS, "alpine_charity")]);
+    }
+
+    fn set_override(deps: DepsMut<'_>, sender: &str, basis_points: Option<u32>, expires: Option<Timestamp>) -> Result<Response, ContractError> {
+        let msg = ExecuteMsg::SetFeeOverride { recipient: String::from("alpine_charity"), basis_points, expires };
+        execute(deps, mock_env(), mock_info(sender, &[]), msg)
+    }
+
+    fn donate(deps: DepsMut<'_>, env: Env, amount: u128) -> Response {
+        send_donation(deps, env, (FAN_ADDRESS, "alpine_fan"), "alpine_charity", coin(amount, "uscrt"))
+    }
+
+    // Exempt a charity from the fee, then give it a reduced fee. The fee charged should be stored on each donation
+    #[test]
+    fn fee_override_charged() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        set_override(deps.as_mut(), "creator", Some(0), None).unwrap();
+        let res = donate(deps.as_mut(), mock_env(), 1000);
+        assert_eq!(res.messages[0].msg, bank_send(CHARITY_ADDRESS, 1000));
+
+        set_override(deps.as_mut(), "creator", Some(100), None).unwrap();
+        let res = donate(deps.as_mut(), mock_env(), 1000);
+        assert_eq!(res.messages[0].msg, bank_send(CHARITY_ADDRESS, 990));
+
+        let donations = load_donations(&deps.storage, vec![0, 1]);
+        assert_eq!(donations[0].fee, Some(coin(0, "uscrt")));
+        assert_eq!(donations[1].fee, Some(coin(10, "uscrt")));
+    }
+
+    // Donate after a fee override expires. The default 3% fee should be charged again
+    #[test]
+    fn fee_override_expires() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        let expires = mock_env().block.time.plus_seconds(60);
+        set_override(deps.as_mut(), "creator", Some(0), Some(expires)).unwrap();
+
+        let res = donate(deps.as_mut(), mock_env(), 1000);
+        assert_eq!(res.messages[0].msg, bank_send(CHARITY_ADDRESS, 1000));
+
+        let mut env = mock_env();
+        env.block.time = expires;
+        let res = donate(deps.as_mut(), env.clone(), 1000);
+        assert_eq!(res.messages[0].msg, bank_send(CHARITY_ADDRESS, 970));
+
+        let msg = QueryMsg::GetFeeRate { recipient: String::from("alpine_charity"), denom: String::from("uscrt") };
+        let res: FeeRateResponse = from_binary(&query(deps.as_ref(), env, msg).unwrap()).unwrap();
+        assert_eq!(res.fee, FeeOverride { basis_points: 300, expires: None });
+    }
+
+    // Set an override from another address, over 100%, and with an expiry in the past. Should error out
+    #[test]
+    fn set_fee_override_invalid() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let err = set_override(deps.as_mut(), FAN_ADDRESS, Some(0), None).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {  });
+        let err = set_override(deps.as_mut(), "creator", Some(10_001), None).unwrap_err();
+        assert_eq!(err.code(), "invalid_fee_override");
+        let err = set_override(deps.as_mut(), "creator", Some(0), Some(mock_env().block.time)).unwrap_err();
+        assert_eq!(err.code(), "invalid_fee_override");
+    }
+
+    // Remove an override. The recipient should pay the default fee again
+    #[test]
+    fn remove_fee_override() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        set_override(deps.as_mut(), "creator", Some(100), None).unwrap();
+
+        let msg = QueryMsg::GetFeeRate { recipient: String::from("alpine_charity"), denom: String::from("uscrt") };
+        let res: FeeRateResponse = from_binary(&query(deps.as_ref(), mock_env(), msg.clone()).unwrap()).unwrap();
+        assert_eq!(res.fee, FeeOverride { basis_points: 100, expires: None });
+
+        set_override(deps.as_mut(), "creator", None, None).unwrap();
+        let res: FeeRateResponse = from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(res.fee, FeeOverride { basis_points: 300, expires: None });
+        let res = donate(deps.as_mut(), mock_env(), 1000);
+        assert_eq!(res.messages[0].msg, bank_send(CHARITY_ADDRESS, 970));
+    }
+}
+
+// A set of tests for working out donation fees
+#[cfg(test)]
+mod fee_tests {
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{coin, coins, from_binary, Addr, DepsMut, Response, Uint128};
+
+    use super::fixtures::{ADDRESS, bank_send, permit, send_donation, setup_users};
+    use crate::error::ContractError;
+    use crate::execute::execute;
+    use crate::fee::{calculate_fee, DenomFeeLimits, FeeRounding, FeeSchedule, FeeTier};
+    use crate::msg::{ExecuteMsg, QueryMsg, QueryWithPermitMsg, MultiDonationResponse};
+    use crate::query::query;
+    use crate::state::{AlpineUser, FeeOverride};
+
+    const FAN_ADDRESS: &str = "secret1ysehn88p24d7769j4vj07hyndkjj7pccz3j3c9";
+    const CREATOR_ADDRESS: &str = "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh";
+
+    fn schedule(basis_points: u32, rounding: FeeRounding) -> FeeSchedule {
+        FeeSchedule { basis_points, limits: vec![], tiers: vec![], rounding }
+    }
+
+    fn limits(min: u128, max: Option<u128>) -> DenomFeeLimits {
+        DenomFeeLimits { denom: String::from("uscrt"), min: Uint128::from(min), max: max.map(Uint128::from) }
+    }
+
+    fn fee(schedule: &FeeSchedule, amount: u128) -> u128 {
+        calculate_fee(schedule, None, &coin(amount, "uscrt"), Uint128::zero()).unwrap().fee.amount.u128()
+    }
+
+    // Work out fees for a range of amounts, rates, limits and rounding modes. The fee and the recipient's share
+    // should always add up to the donation
+    #[test]
+    fn fee_plus_net_is_total() {
+        let amounts = [1u128, 2, 9, 10, 33, 50, 99, 100, 333, 1000, 12_345, 999_999, u128::MAX];
+        for rounding in [FeeRounding::Down, FeeRounding::Up, FeeRounding::Nearest] {
+            for basis_points in [0, 1, 250, 300, 5000, 9999, 10_000] {
+                let mut fee_schedule = schedule(basis_points, rounding.clone());
+                for fee_limits in [vec![], vec![limits(5, None)], vec![limits(0, Some(7))], vec![limits(3, Some(3))]] {
+                    fee_schedule.limits = fee_limits;
+                    for amount in amounts {
+                        let breakdown = calculate_fee(&fee_schedule, None, &coin(amount, "uscrt"), Uint128::zero()).unwrap();
+                        assert_eq!(breakdown.fee.amount + breakdown.net.amount, Uint128::from(amount));
+                    }
+                }
+            }
+        }
+    }
+
+    // Take 3% of amounts which don't divide evenly. Each rounding mode should round as configured
+    #[test]
+    fn fee_rounding() {
+        assert_eq!(fee(&schedule(300, FeeRounding::Down), 10), 0);
+        assert_eq!(fee(&schedule(300, FeeRounding::Up), 10), 1);
+        assert_eq!(fee(&schedule(300, FeeRounding::Nearest), 10), 0);
+        assert_eq!(fee(&schedule(300, FeeRounding::Down), 50), 1);
+        assert_eq!(fee(&schedule(300, FeeRounding::Up), 50), 2);
+        assert_eq!(fee(&schedule(300, FeeRounding::Nearest), 50), 2);
+        assert_eq!(fee(&schedule(300, FeeRounding::Up), 100), 3);
+    }
+
+    // Tip small and large amounts with a minimum and maximum fee. The fee should be held between them, but never
+    // take more than the tip
+    #[test]
+    fn fee_limits() {
+        let mut fee_schedule = schedule(300, FeeRounding::Down);
+        fee_schedule.limits = vec![limits(2, Some(1000))];
+
+        assert_eq!(fee(&fee_schedule, 10), 2);
+        assert_eq!(fee(&fee_schedule, 1), 1);
+        assert_eq!(fee(&fee_schedule, 10_000), 300);
+        assert_eq!(fee(&fee_schedule, 1_000_000), 1000);
+        assert_eq!(calculate_fee(&fee_schedule, None, &coin(10, "uatom"), Uint128::zero()).unwrap().fee.amount.u128(), 0);
+
+        let rate = |amount: u128| calculate_fee(&fee_schedule, None, &coin(amount, "uscrt"), Uint128::zero()).unwrap().basis_points;
+        assert_eq!(rate(10_000), 300);
+        assert_eq!(rate(10), 2000);
+        assert_eq!(rate(1), 10_000);
+        assert_eq!(rate(1_000_000), 10);
+    }
+
+    // Work out fees for recipients with different volumes. The highest tier they've reached in the denom should apply
+    #[test]
+    fn fee_tiers() {
+        let mut fee_schedule = schedule(300, FeeRounding::Down);
+        fee_schedule.tiers = vec![
+            FeeTier { denom: String::from("uscrt"), volume: Uint128::from(10_000u128), basis_points: 200 },
+            FeeTier { denom: String::from("uscrt"), volume: Uint128::from(1_000u128), basis_points: 250 }
+        ];
+
+        assert_eq!(fee_schedule.rate("uscrt", Uint128::from(999u128)), 300);
+        assert_eq!(fee_schedule.rate("uscrt", Uint128::from(1_000u128)), 250);
+        assert_eq!(fee_schedule.rate("uscrt", Uint128::from(50_000u128)), 200);
+        assert_eq!(fee_schedule.rate("uatom", Uint128::from(50_000u128)), 300);
+    }
+
+    // Work out the fee for a recipient with a fee override. The minimum fee shouldn't apply to it
+    #[test]
+    fn fee_override_skips_minimum() {
+        let mut fee_schedule = schedule(300, FeeRounding::Down);
+        fee_schedule.limits = vec![limits(5, None)];
+        let fee_override = FeeOverride { basis_points: 0, expires: None };
+
+        let breakdown = calculate_fee(&fee_schedule, Some(&fee_override), &coin(100, "uscrt"), Uint128::zero()).unwrap();
+        assert_eq!(breakdown.fee, coin(0, "uscrt"));
+        assert_eq!(breakdown.net, coin(100, "uscrt"));
+    }
+
+    // Tip a large amount to a recipient with a reduced fee override. The maximum fee should still cap it, so the
+    // override never costs more than the standard rate
+    #[test]
+    fn fee_override_capped_at_maximum() {
+        let mut fee_schedule = schedule(300, FeeRounding::Down);
+        fee_schedule.limits = vec![limits(2, Some(1000))];
+        let fee_override = FeeOverride { basis_points: 100, expires: None };
+
+        let breakdown = calculate_fee(&fee_schedule, Some(&fee_override), &coin(10_000_000, "uscrt"), Uint128::zero()).unwrap();
+        assert_eq!(breakdown.fee, coin(1000, "uscrt"));
+        assert_eq!(breakdown.basis_points, 1);
+        assert_eq!(fee(&fee_schedule, 10_000_000), 1000);
+
+        let breakdown = calculate_fee(&fee_schedule, Some(&fee_override), &coin(10_000, "uscrt"), Uint128::zero()).unwrap();
+        assert_eq!(breakdown.fee, coin(100, "uscrt"));
+        assert_eq!(breakdown.basis_points, 100);
+    }
+
+    fn setup_contract(deps: DepsMut<'_>) {
+        setup_users(deps, "creator", &[(FAN_ADDRESS, "alpine_fan"), (CREATOR_ADDRESS, "alpine_creator")]);
+    }
+
+    fn set_schedule(deps: DepsMut<'_>, sender: &str, fee_schedule: FeeSchedule) -> Result<Response, ContractError> {
+        let msg = ExecuteMsg::UpdateConfig { disable_address_lookup: None, escrow_timeout: None, team_wallets: None, fee_schedule: Some(fee_schedule), leaderboard_min_donors: None };
+        execute(deps, mock_env(), mock_info(sender, &[]), msg)
+    }
+
+    fn donate(deps: DepsMut<'_>, amount: u128) -> Response {
+        send_donation(deps, mock_env(), (FAN_ADDRESS, "alpine_fan"), "alpine_creator", coin(amount, "uscrt"))
+    }
+
+    // Configure a minimum fee and a volume tier, then donate. A small tip should pay the minimum, and the creator
+    // should move to the lower rate once they've received enough
+    #[test]
+    fn donate_with_fee_schedule() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        let mut fee_schedule = schedule(300, FeeRounding::Down);
+        fee_schedule.limits = vec![limits(1, None)];
+        fee_schedule.tiers = vec![FeeTier { denom: String::from("uscrt"), volume: Uint128::from(1000u128), basis_points: 100 }];
+        set_schedule(deps.as_mut(), "creator", fee_schedule).unwrap();
+
+        let res = donate(deps.as_mut(), 10);
+        assert_eq!(res.messages[0].msg, bank_send(CREATOR_ADDRESS, 9));
+        let res = donate(deps.as_mut(), 1000);
+        assert_eq!(res.messages[0].msg, bank_send(CREATOR_ADDRESS, 970));
+        let res = donate(deps.as_mut(), 1000);
+        assert_eq!(res.messages[0].msg, bank_send(CREATOR_ADDRESS, 990));
+    }
+
+    // Donate from the permit signer, then look the donation up. The fee, its rate and what reached the creator
+    // should be listed on it
+    #[test]
+    fn donation_fee_breakdown() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        let user = AlpineUser::new(deps.as_ref(), Addr::unchecked(ADDRESS), None).unwrap();
+        let msg = ExecuteMsg::RegisterUser { user, username: String::from("alpine_supporter") };
+        execute(deps.as_mut(), mock_env(), mock_info(ADDRESS, &[]), msg).unwrap();
+
+        let msg = ExecuteMsg::SendDonation {
+            sender: String::from("alpine_supporter"),
+            recipient: String::from("alpine_creator"),
+            message: String::from(""),
+            campaign_id: None
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADDRESS, &coins(1234, "uscrt")), msg).unwrap();
+
+        let msg = QueryMsg::WithPermit { permit: permit(), query: QueryWithPermitMsg::GetSentDonations { sender: String::from("alpine_supporter"), filter: None, order: None , limit: None, start_after: None} };
+        let res: MultiDonationResponse = from_binary(&query(deps.as_ref(), mock_env(), msg).unwrap()).unwrap();
+        assert_eq!(res.donations[0].amount, coins(1234, "uscrt"));
+        assert_eq!(res.donations[0].fee, Some(coin(37, "uscrt")));
+        assert_eq!(res.donations[0].fee_basis_points, Some(300));
+        assert_eq!(res.donations[0].net, Some(coin(1197, "uscrt")));
+    }
+
+    // Configure a fee schedule from another address, over 100%, and with a maximum below its minimum. Should error out
+    #[test]
+    fn set_fee_schedule_invalid() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let err = set_schedule(deps.as_mut(), FAN_ADDRESS, schedule(0, FeeRounding::Down)).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {  });
+        let err = set_schedule(deps.as_mut(), "creator", schedule(10_001, FeeRounding::Down)).unwrap_err();
+        assert_eq!(err.code(), "invalid_fee_schedule");
+        let mut fee_schedule = schedule(300, FeeRounding::Down);
+        fee_schedule.limits = vec![limits(10, Some(5))];
+        let err = set_schedule(deps.as_mut(), "creator", fee_schedule).unwrap_err();
+        assert_eq!(err.code(), "invalid_fee_schedule");
+    }
+}
+
+// A set of tests for looking through donation histories
+#[cfg(test)]
+mod history_tests {
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{coin, coins, from_binary, Addr, Coin, Deps, DepsMut, Env, TransactionInfo, Uint128};
+
+    use super::fixtures::{ADDRESS, permit, send_donation, setup_users};
+    use crate::error::ContractError;
+    use crate::execute::{execute, migrate};
+    use crate::msg::{DonationFilter, DonationOrder, DonationResponse, ExecuteMsg, MigrateMsg, MultiDonationResponse, QueryMsg, QueryWithPermitMsg};
+    use crate::query::query;
+    use crate::state::{AlpineUser, DonationInfo, read_state, update_state, load_user_stats, load_received_volume};
+
+    const FAN_ADDRESS: &str = "secret1ysehn88p24d7769j4vj07hyndkjj7pccz3j3c9";
+    const OTHER_FAN_ADDRESS: &str = "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh";
+
+    // Register two fans and a creator at the permit address
+    fn setup_contract(deps: DepsMut<'_>) {
+        setup_users(deps, "creator", &[(FAN_ADDRESS, "alpine_fan"), (OTHER_FAN_ADDRESS, "alpine_other_fan"), (ADDRESS, "alpine_creator")]);
+    }
+
+    fn env_at(height: u64) -> Env {
+        let mut env = mock_env();
+        env.block.height = height;
+        env.block.time = env.block.time.plus_seconds(height * 6);
+        env
+    }
+
+    fn donate(deps: DepsMut<'_>, env: Env, amount: u128) {
+        donate_from(deps, env, (FAN_ADDRESS, "alpine_fan"), coin(amount, "uscrt"));
+    }
+
+    fn donate_from(deps: DepsMut<'_>, env: Env, sender: (&str, &str), funds: Coin) {
+        send_donation(deps, env, sender, "alpine_creator", funds);
+    }
+
+    fn received(deps: Deps<'_>, filter: DonationFilter) -> Vec<DonationInfo> {
+        received_in_order(deps, filter, None)
+    }
+
+    fn received_in_order(deps: Deps<'_>, filter: DonationFilter, order: Option<DonationOrder>) -> Vec<DonationInfo> {
+        let query_msg = QueryWithPermitMsg::GetReceivedDonations { recipient: String::from("alpine_creator"), filter: Some(filter), order, limit: None, start_after: None };
+        let msg = QueryMsg::WithPermit { permit: permit(), query: query_msg };
+        let res: MultiDonationResponse = from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap();
+        res.donations
+    }
+
+    // Donate in a transaction. The block height, transaction index and chain ID should be recorded
+    #[test]
+    fn donation_block_metadata() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        let mut env = env_at(12_345);
+        env.transaction = Some(TransactionInfo { index: 3, hash: String::new() });
+        donate(deps.as_mut(), env.clone(), 1000);
+
+        let donations = received(deps.as_ref(), DonationFilter::default());
+        assert_eq!(donations[0].block_height, Some(12_345));
+        assert_eq!(donations[0].tx_index, Some(3));
+        assert_eq!(donations[0].chain_id, Some(env.block.chain_id));
+    }
+
+    // Donate in three blocks, then filter by block range. Only donations within the inclusive range should be listed
+    #[test]
+    fn filter_by_block_range() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        for height in [100, 200, 300] {
+            donate(deps.as_mut(), env_at(height), 1000);
+        }
+
+        let heights = |donations: Vec<DonationInfo>| donations.into_iter().map(|donation| donation.block_height.unwrap()).collect::<Vec<u64>>();
+        let filter = DonationFilter { from_height: Some(150), to_height: Some(300), ..Default::default() };
+        assert_eq!(heights(received(deps.as_ref(), filter)), vec![200, 300]);
+        let filter = DonationFilter { from_height: Some(200), ..Default::default() };
+        assert_eq!(heights(received(deps.as_ref(), filter)), vec![200, 300]);
+        let filter = DonationFilter { to_height: Some(199), ..Default::default() };
+        assert_eq!(heights(received(deps.as_ref(), filter)), vec![100]);
+    }
+
+    fn amounts(donations: Vec<DonationInfo>) -> Vec<u128> {
+        donations.into_iter().map(|donation| donation.amount[0].amount.u128()).collect()
+    }
+
+    // Donate over several days, then filter by time. Only donations within the inclusive range should be listed
+    #[test]
+    fn filter_by_time() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        for (height, amount) in [(100, 1), (200, 2), (300, 3)] {
+            donate(deps.as_mut(), env_at(height), amount);
+        }
+
+        let filter = DonationFilter { from: Some(env_at(200).block.time), ..Default::default() };
+        assert_eq!(amounts(received(deps.as_ref(), filter)), vec![2, 3]);
+        let filter = DonationFilter { from: Some(env_at(150).block.time), to: Some(env_at(250).block.time), ..Default::default() };
+        assert_eq!(amounts(received(deps.as_ref(), filter)), vec![2]);
+    }
+
+    // Donate in two denoms, then look for tips over an amount in one of them
+    #[test]
+    fn filter_by_denom_and_amount() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        for funds in [coin(5, "uscrt"), coin(50, "uscrt"), coin(500, "uatom"), coin(5000, "uscrt")] {
+            donate_from(deps.as_mut(), mock_env(), (FAN_ADDRESS, "alpine_fan"), funds);
+        }
+
+        let filter = DonationFilter { denom: Some(String::from("uscrt")), min_amount: Some(Uint128::from(50u128)), ..Default::default() };
+        assert_eq!(amounts(received(deps.as_ref(), filter)), vec![50, 5000]);
+        let filter = DonationFilter { min_amount: Some(Uint128::from(100u128)), ..Default::default() };
+        assert_eq!(amounts(received(deps.as_ref(), filter)), vec![500, 5000]);
+    }
+
+    // Receive donations from two fans and an anonymous one, then filter by fan. Only that fan's donations should be
+    // listed, and the anonymous donation should never match
+    #[test]
+    fn filter_by_counterparty() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        donate_from(deps.as_mut(), mock_env(), (FAN_ADDRESS, "alpine_fan"), coin(1, "uscrt"));
+        donate_from(deps.as_mut(), mock_env(), (OTHER_FAN_ADDRESS, "alpine_other_fan"), coin(2, "uscrt"));
+        donate_from(deps.as_mut(), mock_env(), (FAN_ADDRESS, ""), coin(3, "uscrt"));
+
+        let filter = DonationFilter { counterparty: Some(String::from("alpine_fan")), ..Default::default() };
+        assert_eq!(amounts(received(deps.as_ref(), filter)), vec![1]);
+        let filter = DonationFilter { counterparty: Some(String::from("")), ..Default::default() };
+        assert_eq!(amounts(received(deps.as_ref(), filter)), Vec::<u128>::new());
+    }
+
+    // List donations in each order
+    #[test]
+    fn sort_donations() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        for amount in [20, 300, 1] {
+            donate(deps.as_mut(), mock_env(), amount);
+        }
+
+        assert_eq!(amounts(received_in_order(deps.as_ref(), DonationFilter::default(), None)), vec![20, 300, 1]);
+        assert_eq!(amounts(received_in_order(deps.as_ref(), DonationFilter::default(), Some(DonationOrder::NewestFirst))), vec![1, 300, 20]);
+    }
+
+    fn received_page(deps: Deps<'_>, order: DonationOrder, limit: u32, start_after: Option<u64>) -> (Vec<u128>, Option<u64>) {
+        let query_msg = QueryWithPermitMsg::GetReceivedDonations {
+            recipient: String::from("alpine_creator"),
+            filter: None,
+            order: Some(order),
+            limit: Some(limit),
+            start_after
+        };
+        let msg = QueryMsg::WithPermit { permit: permit(), query: query_msg };
+        let res: MultiDonationResponse = from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap();
+        (amounts(res.donations), res.next)
+    }
+
+    // List donations a page at a time in each direction. Each page should continue after the last, and the last
+    // page shouldn't point to another
+    #[test]
+    fn page_donations() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        for amount in [1, 2, 3, 4, 5] {
+            donate(deps.as_mut(), mock_env(), amount);
+        }
+
+        assert_eq!(received_page(deps.as_ref(), DonationOrder::OldestFirst, 2, None), (vec![1, 2], Some(1)));
+        assert_eq!(received_page(deps.as_ref(), DonationOrder::OldestFirst, 2, Some(1)), (vec![3, 4], Some(3)));
+        assert_eq!(received_page(deps.as_ref(), DonationOrder::OldestFirst, 2, Some(3)), (vec![5], None));
+        assert_eq!(received_page(deps.as_ref(), DonationOrder::OldestFirst, 2, Some(4)), (vec![], None));
+
+        assert_eq!(received_page(deps.as_ref(), DonationOrder::NewestFirst, 3, None), (vec![5, 4, 3], Some(2)));
+        assert_eq!(received_page(deps.as_ref(), DonationOrder::NewestFirst, 3, Some(2)), (vec![2, 1], None));
+    }
+
+    // Receive more donations than one page looks at, then list only the most recent blocks and times oldest first.
+    // The first page should start at the range instead of scanning the older donations
+    #[test]
+    fn filter_recent_in_long_history() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        for height in 1..=400 {
+            donate(deps.as_mut(), env_at(height), height as u128);
+        }
+
+        let filter = DonationFilter { from_height: Some(391), ..Default::default() };
+        assert_eq!(amounts(received(deps.as_ref(), filter)), (391..=400).collect::<Vec<u128>>());
+        let filter = DonationFilter { from: Some(env_at(396).block.time), to: Some(env_at(398).block.time), ..Default::default() };
+        assert_eq!(amounts(received(deps.as_ref(), filter)), vec![396, 397, 398]);
+        let filter = DonationFilter { to_height: Some(2), ..Default::default() };
+        assert_eq!(amounts(received_in_order(deps.as_ref(), filter, Some(DonationOrder::NewestFirst))), vec![2, 1]);
+    }
+
+    // Migrate a contract which kept its donations in the state. They should be moved into the indexes
+    #[test]
+    fn migrate_legacy_donations() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        let fan = AlpineUser { username: String::from("alpine_fan"), address: Addr::unchecked(FAN_ADDRESS) };
+        let creator = AlpineUser { username: String::from("alpine_creator"), address: Addr::unchecked(ADDRESS) };
+        let mut state = read_state(&deps.storage).load().unwrap();
+        for id in 0..2 {
+            let mut donation = DonationInfo::new(fan.clone(), creator.clone(), coins(10 + id as u128, "uscrt"), String::new(), mock_env().block.time);
+            donation.id = id;
+            state.donations.push(donation);
+        }
+        state.donation_count = 2;
+        update_state(&mut deps.storage).save(&state).unwrap();
+
+        migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+        assert_eq!(read_state(&deps.storage).load().unwrap().donations.len(), 0);
+        assert_eq!(amounts(received(deps.as_ref(), DonationFilter::default())), vec![10, 11]);
+        // The creator's totals, which the volume fee tiers go by, include the migrated donations
+        let stats = load_user_stats(&deps.storage, &creator.address);
+        assert_eq!((stats.received, stats.donation_count, stats.supporter_count), (coins(21, "uscrt"), 2, 1));
+        assert_eq!(load_received_volume(&deps.storage, &creator.address, "uscrt"), Uint128::from(21u128));
+        let unread = DonationFilter { unread_only: true, ..Default::default() };
+        assert_eq!(amounts(received(deps.as_ref(), unread.clone())), Vec::<u128>::new());
+
+        donate(deps.as_mut(), mock_env(), 12);
+        assert_eq!(amounts(received(deps.as_ref(), DonationFilter::default())), vec![10, 11, 12]);
+        assert_eq!(amounts(received(deps.as_ref(), unread)), vec![12]);
+    }
+
+    fn get_donation(deps: Deps<'_>, id: u64) -> Result<DonationInfo, ContractError> {
+        let msg = QueryMsg::WithPermit { permit: permit(), query: QueryWithPermitMsg::GetDonation { id } };
+        match query(deps, mock_env(), msg) {
+            Ok(binary) => Ok(from_binary::<DonationResponse>(&binary).unwrap().donation),
+            Err(err) => Err(err.0)
+        }
+    }
+
+    // Look up a donation to the permit signer, and an anonymous one. The anonymous donor's address shouldn't be shown
+    // to the recipient, here or in their history
+    #[test]
+    fn get_donation_as_recipient() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        donate(deps.as_mut(), mock_env(), 10);
+        donate_from(deps.as_mut(), mock_env(), (FAN_ADDRESS, ""), coin(20, "uscrt"));
+
+        let donation = get_donation(deps.as_ref(), 0).unwrap();
+        assert_eq!(donation.sender.address, Addr::unchecked(FAN_ADDRESS));
+        assert_eq!(donation.amount, coins(10, "uscrt"));
+
+        let donation = get_donation(deps.as_ref(), 1).unwrap();
+        assert_eq!(donation.sender, AlpineUser::empty());
+        assert_eq!(received(deps.as_ref(), DonationFilter::default())[1].sender, AlpineUser::empty());
+    }
+
+    // Send an anonymous donation from the permit signer, then look it up. The sender should still see it in full
+    #[test]
+    fn get_donation_as_sender() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        let msg = ExecuteMsg::SendDonation {
+            sender: String::new(),
+            recipient: String::from("alpine_fan"),
+            message: String::from("thanks!"),
+            campaign_id: None
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADDRESS, &coins(10, "uscrt")), msg).unwrap();
+
+        let donation = get_donation(deps.as_ref(), 0).unwrap();
+        assert_eq!(donation.sender.address, Addr::unchecked(ADDRESS));
+        assert_eq!(donation.message, "thanks!");
+    }
+
+    // Look up a donation between two other users, and one which doesn't exist. Should error out
+    #[test]
+    fn get_donation_not_allowed() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        let msg = ExecuteMsg::SendDonation {
+            sender: String::from("alpine_fan"),
+            recipient: String::from("alpine_other_fan"),
+            message: String::new(),
+            campaign_id: None
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(FAN_ADDRESS, &coins(10, "uscrt")), msg).unwrap();
+
+        assert_eq!(get_donation(deps.as_ref(), 0).unwrap_err(), ContractError::Unauthorized {  });
+        assert_eq!(get_donation(deps.as_ref(), 1).unwrap_err(), ContractError::DonationNotFound { id: 1 });
+    }
+}
+
+// A set of tests for the running totals kept for each user
+#[cfg(test)]
+mod stats_tests {
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{coin, from_binary, Addr, Coin, Deps, DepsMut, Env, Timestamp};
+
+    use super::fixtures::{ADDRESS, env_at, permit, send_donation, setup_users};
+    use crate::execute::execute;
+    use crate::msg::{ExecuteMsg, QueryMsg, QueryWithPermitMsg, TopSupporter, TopSupportersResponse, UserStatsResponse};
+    use crate::query::query;
+    use crate::state::{AlpineUser, month_index, MAX_TOP_SUPPORTERS};
+
+    const FAN_ADDRESS: &str = "secret1ysehn88p24d7769j4vj07hyndkjj7pccz3j3c9";
+    const OTHER_FAN_ADDRESS: &str = "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh";
+
+    // Register two fans and a creator at the permit address
+    fn setup_contract(deps: DepsMut<'_>) {
+        setup_users(deps, "creator", &[(FAN_ADDRESS, "alpine_fan"), (OTHER_FAN_ADDRESS, "alpine_other_fan"), (ADDRESS, "alpine_creator")]);
+    }
+
+    fn donate(deps: DepsMut<'_>, env: Env, sender: (&str, &str), funds: Coin) {
+        send_donation(deps, env, sender, "alpine_creator", funds);
+    }
+
+    fn user_stats(deps: Deps<'_>, env: Env) -> UserStatsResponse {
+        let msg = QueryMsg::WithPermit { permit: permit(), query: QueryWithPermitMsg::GetUserStats {  } };
+        from_binary(&query(deps, env, msg).unwrap()).unwrap()
+    }
+
+    // Receive donations in two denoms from two fans, one of them anonymously. The totals should add up, and the
+    // anonymous donation should count toward the same supporter
+    #[test]
+    fn user_stats_totals() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        donate(deps.as_mut(), mock_env(), (FAN_ADDRESS, "alpine_fan"), coin(1000, "uscrt"));
+        donate(deps.as_mut(), mock_env(), (FAN_ADDRESS, "alpine_fan"), coin(500, "uscrt"));
+        donate(deps.as_mut(), mock_env(), (OTHER_FAN_ADDRESS, "alpine_other_fan"), coin(200, "uatom"));
+        donate(deps.as_mut(), mock_env(), (FAN_ADDRESS, ""), coin(2000, "uscrt"));
+
+        let stats = user_stats(deps.as_ref(), mock_env());
+        assert_eq!(stats.received, vec![coin(3500, "uscrt"), coin(200, "uatom")]);
+        assert_eq!(stats.earned, vec![coin(3395, "uscrt"), coin(194, "uatom")]);
+        assert_eq!(stats.donation_count, 4);
+        assert_eq!(stats.supporter_count, 2);
+        assert_eq!(stats.largest_donations, vec![coin(2000, "uscrt"), coin(200, "uatom")]);
+        assert_eq!(stats.month_received, stats.received);
+    }
+
+    // Receive a donation in October, then look at the stats in November. The month's totals should start over
+    #[test]
+    fn user_stats_new_month() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        // 2019-10-31 23:59:59 and 2019-11-01 00:00:00 UTC
+        let october = env_at(1_572_566_399);
+        let november = env_at(1_572_566_400);
+        donate(deps.as_mut(), october.clone(), (FAN_ADDRESS, "alpine_fan"), coin(1000, "uscrt"));
+
+        assert_eq!(user_stats(deps.as_ref(), october).month_received, vec![coin(1000, "uscrt")]);
+        assert_eq!(user_stats(deps.as_ref(), november.clone()).month_received, Vec::<Coin>::new());
+
+        donate(deps.as_mut(), november.clone(), (FAN_ADDRESS, "alpine_fan"), coin(500, "uscrt"));
+        let stats = user_stats(deps.as_ref(), november);
+        assert_eq!(stats.month_received, vec![coin(500, "uscrt")]);
+        assert_eq!(stats.received, vec![coin(1500, "uscrt")]);
+    }
+
+    // Count the months to dates around year ends and leap days
+    #[test]
+    fn count_months() {
+        assert_eq!(month_index(Timestamp::from_seconds(0)), 0);
+        // 1970-03-01, 2000-12-31, 2020-02-29 and 2020-03-01
+        assert_eq!(month_index(Timestamp::from_seconds(5_097_600)), 2);
+        assert_eq!(month_index(Timestamp::from_seconds(978_220_800)), 30 * 12 + 11);
+        assert_eq!(month_index(Timestamp::from_seconds(1_582_934_400)), 50 * 12 + 1);
+        assert_eq!(month_index(Timestamp::from_seconds(1_583_020_800)), 50 * 12 + 2);
+    }
+
+    fn top_supporters(deps: Deps<'_>, limit: Option<u32>, denom: &str) -> Vec<TopSupporter> {
+        let msg = QueryMsg::WithPermit { permit: permit(), query: QueryWithPermitMsg::TopSupporters { limit, denom: String::from(denom) } };
+        let res: TopSupportersResponse = from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap();
+        res.supporters
+    }
+
+    // Receive donations from two fans, one of whom also donates anonymously. The anonymous donations should be
+    // listed as their own entry without a name or address, and only the given denom should count
+    #[test]
+    fn top_supporters_list() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        donate(deps.as_mut(), mock_env(), (FAN_ADDRESS, "alpine_fan"), coin(100, "uscrt"));
+        donate(deps.as_mut(), mock_env(), (OTHER_FAN_ADDRESS, "alpine_other_fan"), coin(300, "uscrt"));
+        donate(deps.as_mut(), mock_env(), (FAN_ADDRESS, "alpine_fan"), coin(50, "uscrt"));
+        donate(deps.as_mut(), mock_env(), (FAN_ADDRESS, ""), coin(400, "uscrt"));
+        donate(deps.as_mut(), mock_env(), (OTHER_FAN_ADDRESS, "alpine_other_fan"), coin(1000, "uatom"));
+
+        let fan = AlpineUser { username: String::from("alpine_fan"), address: Addr::unchecked(FAN_ADDRESS) };
+        let other_fan = AlpineUser { username: String::from("alpine_other_fan"), address: Addr::unchecked(OTHER_FAN_ADDRESS) };
+        let supporters = top_supporters(deps.as_ref(), None, "uscrt");
+        assert_eq!(supporters, vec![
+            TopSupporter { supporter: AlpineUser::empty(), anonymous: true, total: coin(400, "uscrt"), donation_count: 1 },
+            TopSupporter { supporter: other_fan.clone(), anonymous: false, total: coin(300, "uscrt"), donation_count: 2 },
+            TopSupporter { supporter: fan, anonymous: false, total: coin(150, "uscrt"), donation_count: 2 }
+        ]);
+
+        assert_eq!(top_supporters(deps.as_ref(), Some(2), "uscrt").len(), 2);
+        let supporters = top_supporters(deps.as_ref(), None, "uatom");
+        assert_eq!(supporters.len(), 1);
+        assert_eq!(supporters[0].supporter, other_fan);
+    }
+
+    // Receive donations from more supporters than are kept. The smallest should drop off the list, and come back
+    // once they give enough to overtake the others
+    #[test]
+    fn top_supporters_bounded() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        let supporters: Vec<String> = (0..=MAX_TOP_SUPPORTERS).map(|i| format!("secret1supporter{}", i)).collect();
+        for (i, address) in supporters.iter().enumerate() {
+            donate(deps.as_mut(), mock_env(), (address, ""), coin(100 + i as u128, "uscrt"));
+        }
+
+        let listed = top_supporters(deps.as_ref(), Some(MAX_TOP_SUPPORTERS as u32), "uscrt");
+        assert_eq!(listed.len(), MAX_TOP_SUPPORTERS);
+        assert_eq!(listed[0].total, coin(100 + MAX_TOP_SUPPORTERS as u128, "uscrt"));
+        assert_eq!(listed[MAX_TOP_SUPPORTERS - 1].total, coin(101, "uscrt"));
+
+        donate(deps.as_mut(), mock_env(), (&supporters[0], ""), coin(1000, "uscrt"));
+        let listed = top_supporters(deps.as_ref(), Some(MAX_TOP_SUPPORTERS as u32), "uscrt");
+        assert_eq!(listed.len(), MAX_TOP_SUPPORTERS);
+        assert_eq!((listed[0].total.clone(), listed[0].donation_count), (coin(1100, "uscrt"), 2));
+        assert_eq!(listed[MAX_TOP_SUPPORTERS - 1].total, coin(102, "uscrt"));
+    }
+
+    // Look up the top supporters of a creator nobody has donated to. The list should be empty
+    #[test]
+    fn top_supporters_empty() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        let msg = ExecuteMsg::SendDonation {
+            sender: String::from("alpine_creator"),
+            recipient: String::from("alpine_fan"),
+            message: String::from(""),
+            campaign_id: None
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADDRESS, &[coin(100, "uscrt")]), msg).unwrap();
+
+        assert_eq!(top_supporters(deps.as_ref(), None, "uscrt"), vec![]);
+    }
+}
+
+#[cfg(test)]
+mod leaderboard_tests {
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{coin, from_binary, Coin, Deps, DepsMut, Env};
+
+    use super::fixtures::{env_at, send_donation, setup_users};
+    use crate::error::ContractError;
+    use crate::execute::execute;
+    use crate::msg::{ExecuteMsg, LeaderboardEntry, LeaderboardResponse, QueryMsg};
+    use crate::query::query;
+
+    const DONORS: [&str; 3] = ["alpine_fan_one", "alpine_fan_two", "alpine_fan_three"];
+
+    // Register three donors and two creators, and lower the leaderboard threshold to two donors
+    fn setup_contract(mut deps: DepsMut<'_>) {
+        let users: Vec<(&str, &str)> = DONORS.iter().chain(["alpine_creator", "alpine_other_creator"].iter())
+            .map(|username| (*username, *username))
+            .collect();
+        setup_users(deps.branch(), "creator", &users);
+        let msg = ExecuteMsg::UpdateConfig {
+            disable_address_lookup: None,
+            escrow_timeout: None,
+            team_wallets: None,
+            fee_schedule: None,
+            leaderboard_min_donors: Some(2)
+        };
+        execute(deps.branch(), mock_env(), mock_info("creator", &[]), msg).unwrap();
+    }
+
+    fn opt_in(deps: DepsMut<'_>, creator: &str, public_leaderboard: bool) {
+        let msg = ExecuteMsg::UpdateSettings { discoverable: None, claim_donations: None, public_leaderboard: Some(public_leaderboard) };
+        execute(deps, mock_env(), mock_info(creator, &[]), msg).unwrap();
+    }
+
+    fn donate(deps: DepsMut<'_>, env: Env, donor: &str, recipient: &str, funds: Coin) {
+        send_donation(deps, env, (donor, donor), recipient, funds);
+    }
+
+    fn leaderboard(deps: Deps<'_>, env: Env) -> Vec<LeaderboardEntry> {
+        let msg = QueryMsg::GetLeaderboard { limit: None };
+        from_binary::<LeaderboardResponse>(&query(deps, env, msg).unwrap()).unwrap().creators
+    }
+
+    // Both creators opt in, but only one of them has enough distinct donors. Repeat donations from the same donor
+    // count toward the number of donations but not the number of donors
+    #[test]
+    fn leaderboard_threshold() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        opt_in(deps.as_mut(), "alpine_creator", true);
+        opt_in(deps.as_mut(), "alpine_other_creator", true);
+        donate(deps.as_mut(), mock_env(), DONORS[0], "alpine_creator", coin(1234, "uscrt"));
+        donate(deps.as_mut(), mock_env(), DONORS[1], "alpine_creator", coin(100, "uscrt"));
+        donate(deps.as_mut(), mock_env(), DONORS[0], "alpine_other_creator", coin(1000, "uscrt"));
+        donate(deps.as_mut(), mock_env(), DONORS[0], "alpine_other_creator", coin(1000, "uscrt"));
+        donate(deps.as_mut(), mock_env(), DONORS[0], "alpine_other_creator", coin(1000, "uscrt"));
+
+        // 1334uscrt is rounded down to 1000uscrt
+        let entry = LeaderboardEntry {
+            username: String::from("alpine_creator"),
+            donation_count: 2,
+            received_at_least: vec![coin(1000, "uscrt")]
+        };
+        assert_eq!(leaderboard(deps.as_ref(), mock_env()), vec![entry]);
+    }
+
+    // Creators who haven't opted in, or opted back out, aren't listed however many donors they have
+    #[test]
+    fn leaderboard_opt_in() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        for donor in DONORS {
+            donate(deps.as_mut(), mock_env(), donor, "alpine_creator", coin(50, "uscrt"));
+        }
+        assert_eq!(leaderboard(deps.as_ref(), mock_env()), vec![]);
+
+        opt_in(deps.as_mut(), "alpine_creator", true);
+        assert_eq!(leaderboard(deps.as_ref(), mock_env()).len(), 1);
+
+        opt_in(deps.as_mut(), "alpine_creator", false);
+        assert_eq!(leaderboard(deps.as_ref(), mock_env()), vec![]);
+    }
+
+    // Creators are ranked by this week's donations, and last week's donations don't count
+    #[test]
+    fn leaderboard_weekly() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        opt_in(deps.as_mut(), "alpine_creator", true);
+        opt_in(deps.as_mut(), "alpine_other_creator", true);
+        // Sunday 2019-10-27 23:59:59 and Monday 2019-10-28 00:00:00 UTC
+        let last_week = env_at(1_572_220_799);
+        let this_week = env_at(1_572_220_800);
+        for donor in DONORS {
+            donate(deps.as_mut(), last_week.clone(), donor, "alpine_creator", coin(50, "uscrt"));
+        }
+        assert_eq!(leaderboard(deps.as_ref(), last_week).len(), 1);
+        assert_eq!(leaderboard(deps.as_ref(), this_week.clone()), vec![]);
+
+        donate(deps.as_mut(), this_week.clone(), DONORS[0], "alpine_creator", coin(50, "uscrt"));
+        donate(deps.as_mut(), this_week.clone(), DONORS[1], "alpine_creator", coin(50, "uscrt"));
+        for donor in DONORS {
+            donate(deps.as_mut(), this_week.clone(), donor, "alpine_other_creator", coin(7, "uatom"));
+        }
+        let creators = leaderboard(deps.as_ref(), this_week);
+        let usernames: Vec<&str> = creators.iter().map(|entry| entry.username.as_str()).collect();
+        assert_eq!(usernames, vec!["alpine_other_creator", "alpine_creator"]);
+        assert_eq!(creators[0].donation_count, 3);
+        assert_eq!(creators[0].received_at_least, vec![coin(10, "uatom")]);
+        assert_eq!(creators[1].received_at_least, vec![coin(100, "uscrt")]);
+    }
+
+    // Only the owner can change the threshold, and it can't be zero
+    #[test]
+    fn leaderboard_threshold_config() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        let msg = |leaderboard_min_donors| ExecuteMsg::UpdateConfig {
+            disable_address_lookup: None,
+            escrow_timeout: None,
+            team_wallets: None,
+            fee_schedule: None,
+            leaderboard_min_donors
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("alpine_creator", &[]), msg(Some(1))).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {  });
+        let err = execute(deps.as_mut(), mock_env(), mock_info("creator", &[]), msg(Some(0))).unwrap_err();
+        assert_eq!(err, ContractError::InvalidLeaderboardThreshold {  });
+    }
+}
+
+#[cfg(test)]
+mod platform_stats_tests {
+    use cosmwasm_std::testing::{mock_dependencies, mock_env};
+    use cosmwasm_std::{coin, from_binary, Coin, Deps, DepsMut, Env};
+
+    use super::fixtures::{ADDRESS, env_at, permit, send_donation, setup_users};
+    use crate::error::ContractError;
+    use crate::msg::{PlatformStatsResponse, QueryMsg, QueryWithPermitMsg};
+    use crate::query::query;
+    use crate::state::LargestDonation;
+
+    const FAN_ADDRESS: &str = "secret1ysehn88p24d7769j4vj07hyndkjj7pccz3j3c9";
+    const CREATOR_ADDRESS: &str = "secret1409ep5zmpxyrh5jpxc8tcw4c0wppkvlqpya9jh";
+    const OTHER_CREATOR_ADDRESS: &str = "secret1ap26qrlp8mcq2pg6r47w43l0y8zkqm8a450s03";
+    const DAY: u64 = 86_400;
+
+    // Instantiate the contract with the permit address as the owner, and register a fan and two creators
+    fn setup_contract(deps: DepsMut<'_>, owner: &str) {
+        let users = [(FAN_ADDRESS, "alpine_fan"), (CREATOR_ADDRESS, "alpine_creator"), (OTHER_CREATOR_ADDRESS, "alpine_other_creator")];
+        setup_users(deps, owner, &users);
+    }
+
+    fn donate(deps: DepsMut<'_>, env: Env, recipient: &str, funds: Coin) {
+        send_donation(deps, env, (FAN_ADDRESS, "alpine_fan"), recipient, funds);
+    }
+
+    fn platform_stats(deps: Deps<'_>, env: Env, window_days: Option<u32>) -> Result<PlatformStatsResponse, ContractError> {
+        let msg = QueryMsg::WithPermit { permit: permit(), query: QueryWithPermitMsg::GetPlatformStats { window_days } };
+        query(deps, env, msg).map(|res| from_binary(&res).unwrap()).map_err(|err| err.0)
+    }
+
+    // Send donations in two denoms. The volume and fees should add up per denom, and the largest donation in each
+    // denom should be kept with its ID
+    #[test]
+    fn platform_stats_totals() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut(), ADDRESS);
+        donate(deps.as_mut(), mock_env(), "alpine_creator", coin(1000, "uscrt"));
+        donate(deps.as_mut(), mock_env(), "alpine_other_creator", coin(3000, "uscrt"));
+        donate(deps.as_mut(), mock_env(), "alpine_creator", coin(2000, "uscrt"));
+        donate(deps.as_mut(), mock_env(), "alpine_creator", coin(100, "uatom"));
+
+        let stats = platform_stats(deps.as_ref(), mock_env(), None).unwrap();
+        assert_eq!(stats.registered_users, 3);
+        assert_eq!(stats.active_creators, 2);
+        assert_eq!(stats.window_days, 30);
+        assert_eq!(stats.volume, vec![coin(6000, "uscrt"), coin(100, "uatom")]);
+        assert_eq!(stats.fees, vec![coin(180, "uscrt"), coin(3, "uatom")]);
+        assert_eq!(stats.largest_donations, vec![
+            LargestDonation { id: 1, amount: coin(3000, "uscrt") },
+            LargestDonation { id: 3, amount: coin(100, "uatom") }
+        ]);
+    }
+
+    // Creators only count as active within the window after their latest donation, and only once however many
+    // donations they received
+    #[test]
+    fn platform_stats_active_creators() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut(), ADDRESS);
+        let start = 1_571_797_419;
+        donate(deps.as_mut(), env_at(start), "alpine_creator", coin(1000, "uscrt"));
+        donate(deps.as_mut(), env_at(start + 10 * DAY), "alpine_other_creator", coin(1000, "uscrt"));
+
+        let now = env_at(start + 10 * DAY);
+        assert_eq!(platform_stats(deps.as_ref(), now.clone(), Some(1)).unwrap().active_creators, 1);
+        assert_eq!(platform_stats(deps.as_ref(), now.clone(), Some(11)).unwrap().active_creators, 2);
+
+        donate(deps.as_mut(), now.clone(), "alpine_creator", coin(1000, "uscrt"));
+        donate(deps.as_mut(), now.clone(), "alpine_creator", coin(1000, "uscrt"));
+        assert_eq!(platform_stats(deps.as_ref(), now.clone(), Some(1)).unwrap().active_creators, 2);
+        assert_eq!(platform_stats(deps.as_ref(), now, Some(30)).unwrap().active_creators, 2);
+
+        let later = env_at(start + 40 * DAY);
+        assert_eq!(platform_stats(deps.as_ref(), later, None).unwrap().active_creators, 0);
+    }
+
+    // Only the owner can see the platform stats
+    #[test]
+    fn platform_stats_unauthorized() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut(), "creator");
+        let err = platform_stats(deps.as_ref(), mock_env(), None).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {  });
+    }
+}
+
+#[cfg(test)]
+mod inbox_tests {
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{coin, from_binary, Addr, Deps, DepsMut};
+
+    use super::fixtures::{ADDRESS, permit, send_donation, setup_users};
+    use crate::error::ContractError;
+    use crate::execute::execute;
+    use crate::msg::{DonationFilter, ExecuteMsg, MultiDonationResponse, QueryMsg, QueryWithPermitMsg, UnreadCountResponse};
+    use crate::query::query;
+    use crate::state::{unread_counts, unread_donations};
+
+    const FAN_ADDRESS: &str = "secret1ysehn88p24d7769j4vj07hyndkjj7pccz3j3c9";
+
+    // Register a fan and a creator at the permit address, and send the creator three donations
+    fn setup_contract(mut deps: DepsMut<'_>) {
+        setup_users(deps.branch(), "creator", &[(FAN_ADDRESS, "alpine_fan"), (ADDRESS, "alpine_creator")]);
+        for _ in 0..3 {
+            send_donation(deps.branch(), mock_env(), (FAN_ADDRESS, "alpine_fan"), "alpine_creator", coin(1000, "uscrt"));
+        }
+    }
+
+    fn acknowledge(deps: DepsMut<'_>, address: &str, ids: Vec<u64>) -> Result<(), ContractError> {
+        execute(deps, mock_env(), mock_info(address, &[]), ExecuteMsg::AcknowledgeDonations { ids }).map(|_| ())
+    }
+
+    fn unread_count(deps: Deps<'_>) -> u64 {
+        let msg = QueryMsg::WithPermit { permit: permit(), query: QueryWithPermitMsg::GetUnreadCount {  } };
+        from_binary::<UnreadCountResponse>(&query(deps, mock_env(), msg).unwrap()).unwrap().count
+    }
+
+    fn received_ids(deps: Deps<'_>, unread_only: bool) -> Vec<u64> {
+        let filter = DonationFilter { unread_only, ..Default::default() };
+        let query_msg = QueryWithPermitMsg::GetReceivedDonations { recipient: String::from("alpine_creator"), filter: Some(filter), order: None , limit: None, start_after: None};
+        let msg = QueryMsg::WithPermit { permit: permit(), query: query_msg };
+        let res: MultiDonationResponse = from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap();
+        res.donations.into_iter().map(|donation| donation.id).collect()
+    }
+
+    // New donations start out unread. Acknowledging some of them should take them out of the unread count and the
+    // unread filter, but not out of the full history
+    #[test]
+    fn acknowledge_donations() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        assert_eq!(unread_count(deps.as_ref()), 3);
+        assert_eq!(received_ids(deps.as_ref(), true), vec![0, 1, 2]);
+
+        acknowledge(deps.as_mut(), ADDRESS, vec![0, 2]).unwrap();
+        assert_eq!(unread_count(deps.as_ref()), 1);
+        assert_eq!(received_ids(deps.as_ref(), true), vec![1]);
+        assert_eq!(received_ids(deps.as_ref(), false), vec![0, 1, 2]);
+
+        // Acknowledging a donation again does nothing
+        acknowledge(deps.as_mut(), ADDRESS, vec![0, 1]).unwrap();
+        assert_eq!(unread_count(deps.as_ref()), 0);
+        assert_eq!(received_ids(deps.as_ref(), true), Vec::<u64>::new());
+        assert!(!unread_counts().contains(&deps.storage, &Addr::unchecked(ADDRESS)));
+        assert!(!unread_donations().contains(&deps.storage, &(Addr::unchecked(ADDRESS), 1)));
+    }
+
+    // Only the recipient can acknowledge a donation, and every ID has to exist. Nothing is acknowledged if any ID
+    // is rejected
+    #[test]
+    fn acknowledge_donations_invalid() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+
+        let err = acknowledge(deps.as_mut(), FAN_ADDRESS, vec![0]).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {  });
+        let err = acknowledge(deps.as_mut(), ADDRESS, vec![1, 7]).unwrap_err();
+        assert_eq!(err, ContractError::DonationNotFound { id: 7 });
+        assert_eq!(unread_count(deps.as_ref()), 3);
+    }
+}
+
+// Define a set of integration tests that use our entry points instead of internal calls
+#[cfg(test)]
+mod integration_tests {
+    use cosmwasm_std::{
+        testing::{mock_dependencies, mock_info, mock_env}, 
+        Addr, 
+        from_binary
+    };
+
+    use crate::{
+        msg::{InstantiateMsg, MigrateMsg, ExecuteMsg, QueryMsg, MultiUserResponse, UsernameAvailableResponse},
+        state::{AlpineUser, read_state, update_state}, execute::{instantiate, migrate, execute}, query::query
+    };
+    // use entry::{ instantiate, migrate, query, execute };
+
+    // Validate that instantiation works from the client's perspective
+    #[test]
+    fn proper_instantiation() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {};
+        let info = mock_info("creator", &[]);
+
+        let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(0, res.messages.len());
     }
 
     // Validate that migration works from the client's perspective
@@ -1291,6 +3967,32 @@ mod integration_tests {
         assert_eq!(0, res.messages.len())
     }
 
+    // Validate that users saved before the username index existed are indexed during migration
+    #[test]
+    fn migration_indexes_usernames() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {};
+        let info = mock_info("creator", &[]);
+        instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let mut state = read_state(&deps.storage).load().unwrap();
+        let alpine_user_a: AlpineUser = AlpineUser::new(
+            deps.as_ref(),
+            Addr::unchecked("secret1tpsscvhaddf36gjvnyjhtwsyempptupypngxzs"),
+            Some(String::from("a_tester"))
+        ).unwrap();
+        state.users.push(alpine_user_a);
+        update_state(&mut deps.storage).save(&state).unwrap();
+
+        let msg = MigrateMsg {};
+        migrate(deps.as_mut(), mock_env(), msg).unwrap();
+
+        let msg = QueryMsg::IsUsernameAvailable { username: String::from("A-TESTER") };
+        let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+        let username_response: UsernameAvailableResponse = from_binary(&res).unwrap();
+        assert!(!username_response.is_available);
+    }
+
     // Validate that execution works from the client's perspective
     #[test]
     fn successful_execute() {
@@ -1333,7 +4035,10 @@ mod integration_tests {
         let info = mock_info(alpine_user_a.address.as_str(), &[]);
         execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
 
-        let msg = QueryMsg::GetAllUsers { };
+        let msg = ExecuteMsg::UpdateSettings { discoverable: Some(true), claim_donations: None, public_leaderboard: None };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let msg = QueryMsg::SearchUsers { prefix: String::from("a_"), limit: None, start_after: None };
         let res = query(deps.as_ref(), mock_env(), msg).unwrap();
         let users: MultiUserResponse  = from_binary(&res).unwrap();
         assert_eq!(users.users.len(), 1)
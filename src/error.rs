@@ -1,4 +1,7 @@
-use cosmwasm_std::StdError;
+use std::fmt;
+
+use cosmwasm_std::{StdError, Uint128, to_vec};
+use serde::Serialize;
 use thiserror::Error;
 
 #[derive(Error, Debug, PartialEq)]
@@ -25,4 +28,141 @@ pub enum ContractError {
     NoDonation {},
     #[error("Your donation message can't be more than 250 characters")]
     DonationMessageTooLong {},
+    #[error("Looking up users by address is disabled")]
+    AddressLookupDisabled {},
+    #[error("Search prefix cannot be empty")]
+    EmptySearchPrefix {},
+    #[error("Invalid permit - {reason:?}")]
+    InvalidPermit { reason: String },
+    #[error("Invalid social handle ({handle:?}) - {reason:?}")]
+    InvalidSocialHandle { handle: String, reason: String },
+    #[error("Escrowed donation not found ({id})")]
+    EscrowNotFound { id: u64 },
+    #[error("Escrowed donation can't be reclaimed until {expires}")]
+    EscrowNotExpired { expires: String },
+    #[error("Insufficient balance ({balance}{denom})")]
+    InsufficientBalance { denom: String, balance: Uint128 },
+    #[error("Nothing to withdraw")]
+    NothingToWithdraw {},
+    #[error("Invalid team wallets - {reason:?}")]
+    InvalidTeamWallets { reason: String },
+    #[error("Subscription not found ({id})")]
+    SubscriptionNotFound { id: u64 },
+    #[error("Invalid subscription - {reason:?}")]
+    InvalidSubscription { reason: String },
+    #[error("Nothing to claim until {next_claim}")]
+    NothingToClaim { next_claim: String },
+    #[error("Invalid tiers - {reason:?}")]
+    InvalidTiers { reason: String },
+    #[error("Campaign not found ({id})")]
+    CampaignNotFound { id: u64 },
+    #[error("Invalid campaign - {reason:?}")]
+    InvalidCampaign { reason: String },
+    #[error("Campaign ended at {deadline}")]
+    CampaignEnded { deadline: String },
+    #[error("Campaign is running until {deadline}")]
+    CampaignNotEnded { deadline: String },
+    #[error("Campaign didn't reach its target")]
+    CampaignTargetNotMet {},
+    #[error("Campaign reached its target, so donations aren't refunded")]
+    CampaignTargetMet {},
+    #[error("Nothing to refund")]
+    NothingToRefund {},
+    #[error("Invalid payout split - {reason:?}")]
+    InvalidPayoutSplit { reason: String },
+    #[error("Sent funds ({received}) don't match the donations ({expected})")]
+    FundsMismatch { expected: String, received: String },
+    #[error("A batch can have at most {max} donations")]
+    TooManyDonations { max: u32 },
+    #[error("Donation not found ({id})")]
+    DonationNotFound { id: u64 },
+    #[error("Invalid charity pledge - {reason:?}")]
+    InvalidCharityPledge { reason: String },
+    #[error("Invalid fee override - {reason:?}")]
+    InvalidFeeOverride { reason: String },
+    #[error("Invalid fee schedule - {reason:?}")]
+    InvalidFeeSchedule { reason: String },
+    #[error("The leaderboard needs a threshold of at least one donor")]
+    InvalidLeaderboardThreshold {},
+    #[error("A donation has to be sent in a single denom")]
+    MultipleDenoms {},
+}
+
+impl ContractError {
+    // A stable, machine-readable code for each error. Clients should match on this instead of the message
+    pub fn code(&self) -> &'static str {
+        match self {
+            ContractError::Std(_) => "std_error",
+            ContractError::IncorrectContractName { .. } => "incorrect_contract_name",
+            ContractError::Unauthorized {  } => "unauthorized",
+            ContractError::InvalidWalletAddress { .. } => "invalid_wallet_address",
+            ContractError::UserAlreadyExists {  } => "user_already_exists",
+            ContractError::UsernameNotAvailable { .. } => "username_not_available",
+            ContractError::UserNotFound { .. } => "user_not_found",
+            ContractError::EmptyUsername {  } => "empty_username",
+            ContractError::InvalidUsername { .. } => "invalid_username",
+            ContractError::NoDonation {  } => "no_donation",
+            ContractError::DonationMessageTooLong {  } => "donation_message_too_long",
+            ContractError::AddressLookupDisabled {  } => "address_lookup_disabled",
+            ContractError::EmptySearchPrefix {  } => "empty_search_prefix",
+            ContractError::InvalidPermit { .. } => "invalid_permit",
+            ContractError::InvalidSocialHandle { .. } => "invalid_social_handle",
+            ContractError::EscrowNotFound { .. } => "escrow_not_found",
+            ContractError::EscrowNotExpired { .. } => "escrow_not_expired",
+            ContractError::InsufficientBalance { .. } => "insufficient_balance",
+            ContractError::NothingToWithdraw {  } => "nothing_to_withdraw",
+            ContractError::InvalidTeamWallets { .. } => "invalid_team_wallets",
+            ContractError::SubscriptionNotFound { .. } => "subscription_not_found",
+            ContractError::InvalidSubscription { .. } => "invalid_subscription",
+            ContractError::NothingToClaim { .. } => "nothing_to_claim",
+            ContractError::InvalidTiers { .. } => "invalid_tiers",
+            ContractError::CampaignNotFound { .. } => "campaign_not_found",
+            ContractError::InvalidCampaign { .. } => "invalid_campaign",
+            ContractError::CampaignEnded { .. } => "campaign_ended",
+            ContractError::CampaignNotEnded { .. } => "campaign_not_ended",
+            ContractError::CampaignTargetNotMet {  } => "campaign_target_not_met",
+            ContractError::CampaignTargetMet {  } => "campaign_target_met",
+            ContractError::NothingToRefund {  } => "nothing_to_refund",
+            ContractError::InvalidPayoutSplit { .. } => "invalid_payout_split",
+            ContractError::FundsMismatch { .. } => "funds_mismatch",
+            ContractError::TooManyDonations { .. } => "too_many_donations",
+            ContractError::DonationNotFound { .. } => "donation_not_found",
+            ContractError::InvalidCharityPledge { .. } => "invalid_charity_pledge",
+            ContractError::InvalidFeeOverride { .. } => "invalid_fee_override",
+            ContractError::InvalidFeeSchedule { .. } => "invalid_fee_schedule",
+            ContractError::InvalidLeaderboardThreshold {  } => "invalid_leaderboard_threshold",
+            ContractError::MultipleDenoms {  } => "multiple_denoms",
+        }
+    }
+}
+
+// The error returned by the query entry point. It's serialized as `{"code":"...","message":"..."}`
+// so that the frontend can tell errors apart by their code
+#[derive(Debug, PartialEq)]
+pub struct QueryError(pub ContractError);
+
+#[derive(Serialize)]
+struct SerializedError<'a> {
+    code: &'a str,
+    message: String
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let serialized = SerializedError { code: self.0.code(), message: self.0.to_string() };
+        let json = to_vec(&serialized).map_err(|_| fmt::Error)?;
+        f.write_str(&String::from_utf8_lossy(&json))
+    }
+}
+
+impl From<ContractError> for QueryError {
+    fn from(err: ContractError) -> Self {
+        QueryError(err)
+    }
+}
+
+impl From<StdError> for QueryError {
+    fn from(err: StdError) -> Self {
+        QueryError(ContractError::Std(err))
+    }
 }
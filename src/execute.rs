@@ -1,12 +1,15 @@
 use cosmwasm_std::{
     Addr,
-    coins,
-    Decimal,
+    Coin,
+    Deps,
     DepsMut,
     MessageInfo,
     Env,
     StdResult,
+    Storage,
     Response,
+    Timestamp,
+    Uint128,
     entry_point,
     BankMsg
 };
@@ -16,9 +19,12 @@ use crate::{
     msg::{
         InstantiateMsg,
         MigrateMsg,
-        ExecuteMsg
+        ExecuteMsg,
+        DonationItem,
+        PayoutShareMsg
     }, 
     error::ContractError,
+    fee::{FeeSchedule, calculate_fee, TOTAL_FEE_BASIS_POINTS},
     state::{
         AlpineUser,
         DonationInfo,
@@ -26,10 +32,39 @@ use crate::{
         update_donations,
         get_user_by_address,
         State, 
-        update_state, read_state, clear_data
+        DEFAULT_ESCROW_TIMEOUT, DEFAULT_LEADERBOARD_MIN_DONORS,
+        update_state, read_state, clear_data,
+        rebuild_username_index, migrate_donations, save_user, username_skeleton, username_skeletons, fold_digit,
+        load_user_settings, save_user_settings,
+        EscrowedDonation, escrows, escrows_by_target, save_escrow, remove_escrow, take_escrows,
+        find_social_handle, link_social_handle, user_social_handles,
+        load_balances, credit_balance, debit_balance,
+        TeamWallet, default_team_wallets, add_coin, credit_fees,
+        Subscription, DEFAULT_SUBSCRIPTION_PERIOD, subscriptions, save_subscription, remove_subscription,
+        Tier, CreatorTiers, DEFAULT_TIER_WINDOW, creator_tiers,
+        Campaign, campaigns, save_campaign, campaign_pledges, campaign_refunds, save_pledge,
+        PayoutShare, payout_splits, load_payout_split,
+        CharityPledge, CharityDonation, charity_pledges,
+        FeeOverride, fee_overrides, load_fee_override, load_received_volume,
+        record_donation_stats, record_platform_stats, donations, mark_donations_read
     }
 };
 
+const MAX_TIERS: usize = 10;
+const MAX_PAYOUT_SHARES: usize = 10;
+const MAX_BATCH_DONATIONS: u32 = 20;
+const DEFAULT_ESCROW_CLAIM_LIMIT: u32 = 10;
+const MAX_ESCROW_CLAIM_LIMIT: u32 = 30;
+// Subscriptions can pay out at most yearly, and run for at most ten years, in seconds
+const MAX_SUBSCRIPTION_PERIOD: u64 = 366 * 24 * 60 * 60;
+const MAX_SUBSCRIPTION_LENGTH: u64 = 10 * MAX_SUBSCRIPTION_PERIOD;
+const DEFAULT_PLEDGE_CLAIM_LIMIT: u32 = 10;
+const MAX_PLEDGE_CLAIM_LIMIT: u32 = 30;
+const DEFAULT_CLEAR_LIMIT: u32 = 100;
+const MAX_CLEAR_LIMIT: u32 = 300;
+// Payout split weights are in basis points
+const TOTAL_PAYOUT_WEIGHT: u32 = 10_000;
+
 // #[cfg(not(feature = "library"))]
 // version info for migration info
 // const CONTRACT_NAME: &str = "crates.io:alpine-pay";
@@ -48,7 +83,16 @@ pub fn instantiate(
         users: vec![],
         donations: vec![],
         contract_address: env.contract.address.to_string(),
-        owner: info.sender.clone().to_string()
+        owner: info.sender.clone().to_string(),
+        disable_address_lookup: false,
+        escrow_count: 0,
+        subscription_count: 0,
+        campaign_count: 0,
+        escrow_timeout: DEFAULT_ESCROW_TIMEOUT,
+        fee_treasury: vec![],
+        team_wallets: default_team_wallets(),
+        fee_schedule: FeeSchedule::default(),
+        leaderboard_min_donors: DEFAULT_LEADERBOARD_MIN_DONORS
     };
     update_state(deps.storage).save(&state)?;
     Ok(Response::default())
@@ -56,13 +100,15 @@ pub fn instantiate(
 
 #[entry_point]
 pub fn migrate(
-    _deps: DepsMut,
+    deps: DepsMut,
     _env: Env,
     _msg: MigrateMsg
 ) -> Result<Response, ContractError> {
     // let ver = get_contract_version(deps.storage);
     // ensure_eq!(ver.contract, CONTRACT_NAME, ContractError::IncorrectContractName { contract_name: String::from(CONTRACT_NAME) });
     // set_contract_version(deps.storage, ver.contract, ver.version.clone())?;
+    rebuild_username_index(deps.storage)?;
+    migrate_donations(deps.storage)?;
 
     Ok(Response::default())
 }
@@ -75,7 +121,8 @@ pub fn execute(
     msg: ExecuteMsg
 ) -> Result<Response, ContractError> {
     match msg {
-        ExecuteMsg::SendDonation { sender, recipient, message } => send_donation(deps, env, info, sender, recipient, message),
+        ExecuteMsg::SendDonation { sender, recipient, message, campaign_id } => send_donation(deps, env, info, sender, recipient, message, campaign_id),
+        ExecuteMsg::SendDonations { sender, items } => send_donations(deps, env, info, sender, items),
         // With register we can authenticate the user here, whereas with SendDonation it's a bit more complex and done later
         ExecuteMsg::RegisterUser { user, username } => {
             if info.sender != user.address {
@@ -83,20 +130,89 @@ pub fn execute(
             }
             register_user(deps, env, user, username)
         },
-        ExecuteMsg::ClearContract {  } => clear_contract(deps, info)
+        ExecuteMsg::UpdateSettings { discoverable, claim_donations, public_leaderboard } => {
+            update_settings(deps, info, discoverable, claim_donations, public_leaderboard)
+        },
+        ExecuteMsg::UpdateConfig { disable_address_lookup, escrow_timeout, team_wallets, fee_schedule, leaderboard_min_donors } => {
+            update_config(deps, info, disable_address_lookup, escrow_timeout, team_wallets, fee_schedule, leaderboard_min_donors)
+        },
+        ExecuteMsg::EscrowDonation { sender, recipient, message } => escrow_donation(deps, env, info, sender, recipient, message),
+        ExecuteMsg::ReclaimEscrow { id } => reclaim_escrow(deps, env, info, id),
+        ExecuteMsg::ClaimEscrows { limit } => claim_escrows(deps, env, info, limit),
+        ExecuteMsg::LinkSocialHandle { address, handle } => link_handle(deps, env, info, address, handle),
+        ExecuteMsg::Withdraw { denom, amount } => withdraw(deps, info, denom, amount),
+        ExecuteMsg::WithdrawAll {  } => withdraw_all(deps, info),
+        ExecuteMsg::WithdrawFees {  } => withdraw_fees(deps, info),
+        ExecuteMsg::Subscribe { sender, creator, periods, period } => subscribe(deps, env, info, sender, creator, periods, period),
+        ExecuteMsg::ClaimSubscription { id } => claim_subscription(deps, env, id),
+        ExecuteMsg::CancelSubscription { id } => cancel_subscription(deps, env, info, id),
+        ExecuteMsg::SetTiers { tiers, window } => set_tiers(deps, info, tiers, window),
+        ExecuteMsg::SetPayoutSplit { shares } => set_payout_split(deps, info, shares),
+        ExecuteMsg::SetCharityPledge { charity, percent } => set_charity_pledge(deps, info, charity, percent),
+        ExecuteMsg::AcknowledgeDonations { ids } => acknowledge_donations(deps, info, ids),
+        ExecuteMsg::SetFeeOverride { recipient, basis_points, expires } => set_fee_override(deps, env, info, recipient, basis_points, expires),
+        ExecuteMsg::CreateCampaign { description, target, deadline, all_or_nothing } => {
+            create_campaign(deps, env, info, description, target, deadline, all_or_nothing.unwrap_or(false))
+        },
+        ExecuteMsg::ClaimCampaign { campaign_id, limit } => claim_campaign(deps, env, info, campaign_id, limit),
+        ExecuteMsg::Refund { campaign_id } => refund(deps, env, info, campaign_id),
+        ExecuteMsg::ClearContract { limit } => clear_contract(deps, info, limit)
     }
 }
 
 fn clear_contract(
     deps: DepsMut,
-    info: MessageInfo
+    info: MessageInfo,
+    limit: Option<u32>
 ) -> Result<Response, ContractError> {
     let state = read_state(deps.storage).load()?;
-    if info.sender.clone().to_string() != state.owner {
+    if info.sender.as_str() != state.owner {
         return Err(ContractError::Unauthorized {  })
     }
-    clear_data(deps.storage)?;
-    Ok(Response::default())
+    let limit = limit.unwrap_or(DEFAULT_CLEAR_LIMIT).clamp(1, MAX_CLEAR_LIMIT) as u64;
+    let remaining = clear_data(deps.storage, limit)?;
+    Ok(Response::new().add_attribute("remaining_donations", remaining.to_string()))
+}
+
+// Update the contract-wide configuration. Only the owner can do this
+fn update_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    disable_address_lookup: Option<bool>,
+    escrow_timeout: Option<u64>,
+    team_wallets: Option<Vec<TeamWallet>>,
+    fee_schedule: Option<FeeSchedule>,
+    leaderboard_min_donors: Option<u32>
+) -> Result<Response, ContractError> {
+    let mut state = read_state(deps.storage).load()?;
+    if info.sender.as_str() != state.owner {
+        return Err(ContractError::Unauthorized {  })
+    }
+
+    if let Some(disable_address_lookup) = disable_address_lookup {
+        state.disable_address_lookup = disable_address_lookup;
+    }
+    if let Some(escrow_timeout) = escrow_timeout {
+        state.escrow_timeout = escrow_timeout;
+    }
+    if let Some(team_wallets) = team_wallets {
+        state.team_wallets = validate_team_wallets(deps.as_ref(), team_wallets)?;
+    }
+    if let Some(fee_schedule) = fee_schedule {
+        fee_schedule.validate()?;
+        state.fee_schedule = fee_schedule;
+    }
+    if let Some(leaderboard_min_donors) = leaderboard_min_donors {
+        if leaderboard_min_donors == 0 {
+            return Err(ContractError::InvalidLeaderboardThreshold {  })
+        }
+        state.leaderboard_min_donors = leaderboard_min_donors;
+    }
+
+    update_state(deps.storage).save(&state)?;
+    Ok(Response::new()
+        .add_attribute("disable_address_lookup", state.disable_address_lookup.to_string())
+        .add_attribute("escrow_timeout", state.escrow_timeout.to_string()))
 }
 
 fn send_donation(
@@ -105,68 +221,910 @@ fn send_donation(
     info: MessageInfo,
     sender: String,
     recipient: String, 
-    message: String
+    message: String,
+    campaign_id: Option<u64>
 ) -> Result<Response, ContractError> {
-    let state = read_state(deps.storage).load()?;
     if recipient.is_empty() {
         return Err(ContractError::EmptyUsername {})
     }
 
-    if info.funds.is_empty() || info.funds[0].amount.to_string() == String::from("0") {
+    validate_donation(&info.funds, &message)?;
+    let sender_user = donation_sender(deps.as_ref(), &info, sender)?;
+    let recipient_user = find_recipient(deps.storage, recipient)?;
+
+    let mut donation = DonationInfo::new(sender_user, recipient_user, info.funds.clone(), message, env.block.time);
+    if let Some(campaign_id) = campaign_id {
+        let campaign = contribute_to_campaign(deps.storage, &env, campaign_id, &donation)?;
+        donation.campaign_id = Some(campaign_id);
+
+        // Donations to all-or-nothing campaigns are held until the deadline
+        if campaign.all_or_nothing {
+            save_pledge(deps.storage, campaign, &donation)?;
+            return Ok(Response::new()
+                .add_attribute("campaign_id", campaign_id.to_string())
+                .add_attribute("pledged", info.funds[0].to_string()))
+        }
+    }
+    let (donation, tx_messages) = complete_donation(deps.storage, &env, donation)?;
+
+    let attributes = vec![("sender_address", donation.sender.address.to_string()), ("sender_username", donation.sender.username.to_string()), 
+                    ("recipient_address", donation.recipient.address.to_string()), ("recipient_username", donation.recipient.username.to_string()),
+                    ("amount", donation.amount[0].amount.to_string()), ("message", donation.message), ("timestamp", env.block.time.to_string()),
+                    ("id", donation.id.to_string()) ].into_iter();
+
+    Ok(Response::new().add_messages(tx_messages).add_attributes(attributes))
+}
+
+// Send a batch of donations. Each item is recorded as its own donation, and the bank messages to each
+// address are combined. If any item fails, the whole batch fails
+fn send_donations(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    sender: String,
+    items: Vec<DonationItem>
+) -> Result<Response, ContractError> {
+    if items.is_empty() {
+        return Err(ContractError::NoDonation {  })
+    }
+    if items.len() > MAX_BATCH_DONATIONS as usize {
+        return Err(ContractError::TooManyDonations { max: MAX_BATCH_DONATIONS })
+    }
+
+    let mut expected = vec![];
+    items.iter().for_each(|item| add_coin(&mut expected, &item.amount));
+    let mut received = vec![];
+    info.funds.iter().for_each(|coin| add_coin(&mut received, coin));
+    if expected.len() != received.len() || !expected.iter().all(|coin| received.contains(coin)) {
+        let list = |coins: &Vec<Coin>| coins.iter().map(|coin| coin.to_string()).collect::<Vec<String>>().join(",");
+        return Err(ContractError::FundsMismatch { expected: list(&expected), received: list(&received) })
+    }
+
+    // Validate every item before recording any of them
+    let sender_user = donation_sender(deps.as_ref(), &info, sender)?;
+    let mut donations = vec![];
+    for item in items {
+        if item.recipient.is_empty() {
+            return Err(ContractError::EmptyUsername {})
+        }
+        let funds = vec![item.amount];
+        validate_donation(&funds, &item.message)?;
+        let recipient_user = find_recipient(deps.storage, item.recipient)?;
+        donations.push(DonationInfo::new(sender_user.clone(), recipient_user, funds, item.message, env.block.time));
+    }
+
+    let mut bank_msgs = vec![];
+    let mut ids = vec![];
+    for donation in donations {
+        let (donation, tx_messages) = complete_donation(deps.storage, &env, donation)?;
+        bank_msgs.extend(tx_messages);
+        ids.push(donation.id.to_string());
+    }
+
+    Ok(Response::new()
+        .add_messages(merge_bank_msgs(bank_msgs))
+        .add_attribute("ids", ids.join(",")))
+}
+
+// Validate the team wallets which fees are paid out to. There has to be at least one, and every wallet needs a weight
+fn validate_team_wallets(deps: Deps, team_wallets: Vec<TeamWallet>) -> Result<Vec<TeamWallet>, ContractError> {
+    if team_wallets.is_empty() {
+        return Err(ContractError::InvalidTeamWallets { reason: String::from("at least one wallet is required") })
+    }
+
+    for wallet in &team_wallets {
+        if deps.api.addr_validate(wallet.address.as_str()).is_err() {
+            return Err(ContractError::InvalidWalletAddress { address: wallet.address.to_string() })
+        }
+        if wallet.weight == 0 {
+            return Err(ContractError::InvalidTeamWallets { reason: format!("{} has no weight", wallet.address) })
+        }
+    }
+
+    Ok(team_wallets)
+}
+
+// Validate the funds and message attached to a donation. Fees and payouts only cover the first coin, so a
+// donation can't be sent in more than one denom
+fn validate_donation(funds: &[Coin], message: &str) -> Result<(), ContractError> {
+    if funds.is_empty() || funds[0].amount.is_zero() {
         return Err(ContractError::NoDonation{})
     }
+    if funds.len() != 1 {
+        return Err(ContractError::MultipleDenoms {  })
+    }
+
+    if message.len() > 250 {
+        return Err(ContractError::DonationMessageTooLong {  })
+    }
+
+    Ok(())
+}
 
-    // Get an Alpine user for the sender. This technically allows a user to send if they're unregistered
+// Get an Alpine user for the sender of a donation, and authenticate them.
+// This technically allows a user to send if they're unregistered
+fn donation_sender(deps: Deps, info: &MessageInfo, sender: String) -> Result<AlpineUser, ContractError> {
     let sender_user = match sender.is_empty() {
-        true => AlpineUser::new(deps.as_ref(), info.sender.clone(), None)?,
+        true => AlpineUser::new(deps, info.sender.clone(), None)?,
         false => find_alpine_username(deps.storage, sender)?
     };
 
-    // Authenticate the sender
     if info.sender != sender_user.address {
         return Err(ContractError::InvalidWalletAddress { address: sender_user.address.to_string() })
     }
 
-    if message.len() > 250 {
-        return Err(ContractError::DonationMessageTooLong {  })
+    Ok(sender_user)
+}
+
+// Find the user a donation is addressed to. Recipients are either a username or a verified "platform:handle"
+fn find_recipient(storage: &dyn Storage, recipient: String) -> Result<AlpineUser, ContractError> {
+    match recipient.contains(':') {
+        true => find_social_handle(storage, &normalize_social_handle(&recipient)?),
+        false => find_alpine_username(storage, recipient)
+    }
+}
+
+// Record a donation, add the donation fee to the treasury, and build the bank messages which forward the rest
+// to the recipient, or to each of their collaborators if they split their donations. Recipients who claim their
+// donations have them credited to the balances instead
+fn complete_donation(
+    storage: &mut dyn Storage,
+    env: &Env,
+    mut donation: DonationInfo
+) -> Result<(DonationInfo, Vec<BankMsg>), ContractError> {
+    let state = read_state(storage).load()?;
+    donation.id = state.donation_count;
+    // Pledges and escrows are recorded once they're paid out, and dated then, so that the donations in an index
+    // are in the order of their time as well as their height
+    donation.timestamp = Some(env.block.time);
+    donation.block_height = Some(env.block.height);
+    donation.tx_index = env.transaction.as_ref().map(|transaction| transaction.index);
+    donation.chain_id = Some(env.block.chain_id.clone());
+
+    let recipient = donation.recipient.address.clone();
+    let fee_override = load_fee_override(storage, &recipient, env.block.time);
+    let volume = load_received_volume(storage, &recipient, &donation.amount[0].denom);
+    let breakdown = calculate_fee(&state.fee_schedule, fee_override.as_ref(), &donation.amount[0], volume)?;
+    donation.fee = Some(breakdown.fee.clone());
+
+    // The recipient's charity gets its share straight away, even if the recipient claims their donations
+    let mut bank_msgs = vec![];
+    let mut net_amount = breakdown.net.amount;
+    if let Some(pledge) = charity_pledges().get(storage, &recipient) {
+        let charity_amount = net_amount.multiply_ratio(pledge.percent, 100u128);
+        if !charity_amount.is_zero() {
+            let funds = Coin { denom: breakdown.net.denom.clone(), amount: charity_amount };
+            bank_msgs.push(BankMsg::Send { to_address: pledge.address.to_string(), amount: vec![funds.clone()] });
+            donation.charity = Some(CharityDonation { address: pledge.address, amount: funds });
+            net_amount -= charity_amount;
+        }
+    }
+    donation.fee_basis_points = Some(breakdown.basis_points);
+    donation.net = Some(Coin { denom: breakdown.net.denom.clone(), amount: net_amount });
+    update_donations(storage, donation.clone())?;
+    record_donation_stats(storage, &donation, env.block.time)?;
+    record_platform_stats(storage, &donation, env.block.time)?;
+
+    let split = match load_payout_split(storage, &recipient) {
+        split if split.is_empty() => vec![PayoutShare { address: recipient.clone(), weight: TOTAL_PAYOUT_WEIGHT }],
+        split => split
+    };
+    let weights: Vec<u128> = split.iter().map(|share| share.weight as u128).collect();
+    let amounts = split_by_weight(net_amount, &weights);
+    let claim_donations = load_user_settings(storage, &recipient).claim_donations;
+
+    for (share, amount) in split.into_iter().zip(amounts) {
+        let funds = Coin { denom: breakdown.net.denom.clone(), amount };
+        if funds.amount.is_zero() {
+            continue
+        }
+        if claim_donations {
+            credit_balance(storage, &share.address, &funds)?;
+        } else {
+            // Forward the funds to the relevant wallet address
+            bank_msgs.push(BankMsg::Send {
+                to_address: share.address.to_string(),
+                amount: vec![funds]
+            });
+        }
+    }
+
+    // Keep the donation fee in the treasury until the owner withdraws it
+    credit_fees(storage, &breakdown.fee)?;
+
+    Ok((donation, bank_msgs))
+}
+
+// Split an amount by weight, rounding each share down. Whatever is left over from rounding goes to the first
+// share, so the shares always add up to the amount
+fn split_by_weight(amount: Uint128, weights: &[u128]) -> Vec<Uint128> {
+    let total_weight: u128 = weights.iter().sum();
+    let mut shares: Vec<Uint128> = weights.iter().map(|weight| amount.multiply_ratio(*weight, total_weight)).collect();
+    let remainder = amount - shares.iter().fold(Uint128::zero(), |total, share| total + share);
+    if let Some(first) = shares.first_mut() {
+        *first += remainder;
+    }
+    shares
+}
+
+// Combine bank messages to the same address into one
+fn merge_bank_msgs(bank_msgs: Vec<BankMsg>) -> Vec<BankMsg> {
+    let mut merged: Vec<(String, Vec<Coin>)> = vec![];
+    for bank_msg in bank_msgs {
+        if let BankMsg::Send { to_address, amount } = bank_msg {
+            let index = match merged.iter().position(|(address, _)| *address == to_address) {
+                Some(index) => index,
+                None => {
+                    merged.push((to_address, vec![]));
+                    merged.len() - 1
+                }
+            };
+            amount.iter().for_each(|coin| add_coin(&mut merged[index].1, coin));
+        }
+    }
+    merged.into_iter().map(|(to_address, amount)| BankMsg::Send { to_address, amount }).collect()
+}
+
+// Count a donation toward a campaign. The campaign has to belong to the recipient, still be running,
+// and be raising the denom which was donated
+fn contribute_to_campaign(storage: &mut dyn Storage, env: &Env, campaign_id: u64, donation: &DonationInfo) -> Result<Campaign, ContractError> {
+    let mut campaign = match campaigns().get(storage, &campaign_id) {
+        Some(campaign) => campaign,
+        None => return Err(ContractError::CampaignNotFound { id: campaign_id })
+    };
+
+    if campaign.creator.address != donation.recipient.address {
+        return Err(ContractError::InvalidCampaign { reason: String::from("the campaign belongs to another creator") })
+    }
+    if env.block.time >= campaign.deadline {
+        return Err(ContractError::CampaignEnded { deadline: campaign.deadline.to_string() })
+    }
+    if donation.amount[0].denom != campaign.target.denom {
+        return Err(ContractError::InvalidCampaign { reason: format!("the campaign only accepts {}", campaign.target.denom) })
+    }
+
+    campaign.raised += donation.amount[0].amount;
+    campaign.donation_count += 1;
+    save_campaign(storage, &campaign)?;
+    Ok(campaign)
+}
+
+// Load a campaign which has ended, for claiming or refunding its held donations
+fn load_ended_campaign(storage: &dyn Storage, env: &Env, campaign_id: u64) -> Result<Campaign, ContractError> {
+    let campaign = match campaigns().get(storage, &campaign_id) {
+        Some(campaign) => campaign,
+        None => return Err(ContractError::CampaignNotFound { id: campaign_id })
+    };
+
+    if !campaign.all_or_nothing {
+        return Err(ContractError::InvalidCampaign { reason: String::from("only all-or-nothing campaigns hold donations") })
+    }
+    if env.block.time < campaign.deadline {
+        return Err(ContractError::CampaignNotEnded { deadline: campaign.deadline.to_string() })
+    }
+
+    Ok(campaign)
+}
+
+// Pay out the donations held by an all-or-nothing campaign which met its target. They're recorded as donations
+// from each donor, and paid out in a single transfer per receiving address
+fn claim_campaign(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    campaign_id: u64,
+    limit: Option<u32>
+) -> Result<Response, ContractError> {
+    let mut campaign = load_ended_campaign(deps.storage, &env, campaign_id)?;
+    if info.sender != campaign.creator.address {
+        return Err(ContractError::Unauthorized {  })
+    }
+    if campaign.raised < campaign.target.amount {
+        return Err(ContractError::CampaignTargetNotMet {  })
+    }
+    if campaign.claimed {
+        return Err(ContractError::InvalidCampaign { reason: String::from("the campaign was already claimed") })
+    }
+
+    // Pledges are claimed a page at a time, in the order they were made, so that any number of them can be claimed
+    let limit = limit.unwrap_or(DEFAULT_PLEDGE_CLAIM_LIMIT).min(MAX_PLEDGE_CLAIM_LIMIT) as u64;
+    let end = campaign.pledge_count.min(campaign.claimed_pledges + limit);
+    let mut bank_msgs = vec![];
+    for index in campaign.claimed_pledges..end {
+        let key = (campaign_id, index);
+        let pledge = match campaign_pledges().get(deps.storage, &key) {
+            Some(pledge) => pledge,
+            None => continue
+        };
+        campaign_pledges().remove(deps.storage, &key)?;
+        let refund_key = (campaign_id, pledge.sender.address.clone());
+        if campaign_refunds().contains(deps.storage, &refund_key) {
+            campaign_refunds().remove(deps.storage, &refund_key)?;
+        }
+        bank_msgs.extend(complete_donation(deps.storage, &env, pledge)?.1);
+    }
+    campaign.claimed_pledges = end;
+    campaign.claimed = end == campaign.pledge_count;
+    save_campaign(deps.storage, &campaign)?;
+
+    Ok(Response::new()
+        .add_messages(merge_bank_msgs(bank_msgs))
+        .add_attribute("campaign_id", campaign_id.to_string())
+        .add_attribute("pending_pledges", (campaign.pledge_count - end).to_string()))
+}
+
+// Return the donations the sender made to an all-or-nothing campaign which missed its target. No fee is taken
+fn refund(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    campaign_id: u64
+) -> Result<Response, ContractError> {
+    let campaign = load_ended_campaign(deps.storage, &env, campaign_id)?;
+    if campaign.raised >= campaign.target.amount {
+        return Err(ContractError::CampaignTargetMet {  })
+    }
+
+    // The pledges themselves are left behind, since a campaign which missed its target is never claimed
+    let key = (campaign_id, info.sender.clone());
+    let amount = match campaign_refunds().get(deps.storage, &key) {
+        Some(amount) => amount,
+        None => return Err(ContractError::NothingToRefund {  })
+    };
+    campaign_refunds().remove(deps.storage, &key)?;
+
+    let refund_bank_msg = BankMsg::Send { to_address: info.sender.to_string(), amount };
+    Ok(Response::new().add_message(refund_bank_msg).add_attribute("campaign_id", campaign_id.to_string()))
+}
+
+// Start a fundraising campaign for the registered user who sent the message
+fn create_campaign(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    description: String,
+    target: Coin,
+    deadline: Timestamp,
+    all_or_nothing: bool
+) -> Result<Response, ContractError> {
+    let creator = get_user_by_address(deps.storage, info.sender)?;
+
+    let invalid = |reason: &str| ContractError::InvalidCampaign { reason: reason.to_string() };
+    if description.is_empty() || description.len() > 500 {
+        return Err(invalid("the description must be between 1 and 500 characters"))
+    }
+    if target.denom.is_empty() || target.amount.is_zero() {
+        return Err(invalid("the target can't be empty"))
+    }
+    if deadline <= env.block.time {
+        return Err(invalid("the deadline has to be in the future"))
+    }
+
+    let mut state = read_state(deps.storage).load()?;
+    let campaign = Campaign {
+        id: state.campaign_count,
+        creator,
+        description,
+        target,
+        deadline,
+        raised: Uint128::zero(),
+        donation_count: 0,
+        all_or_nothing,
+        claimed: false,
+        pledge_count: 0,
+        claimed_pledges: 0
+    };
+    save_campaign(deps.storage, &campaign)?;
+    state.campaign_count += 1;
+    update_state(deps.storage).save(&state)?;
+
+    Ok(Response::new().add_attribute("campaign_id", campaign.id.to_string()))
+}
+
+// Prepay a subscription to a creator. The deposit has to split evenly between the periods
+fn subscribe(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    sender: String,
+    creator: String,
+    periods: u32,
+    period: Option<u64>
+) -> Result<Response, ContractError> {
+    if creator.is_empty() {
+        return Err(ContractError::EmptyUsername {})
+    }
+
+    validate_donation(&info.funds, "")?;
+    let invalid = |reason: &str| ContractError::InvalidSubscription { reason: reason.to_string() };
+    let period = period.unwrap_or(DEFAULT_SUBSCRIPTION_PERIOD);
+    if periods == 0 || period == 0 {
+        return Err(invalid("must last at least one period"))
+    }
+    if period > MAX_SUBSCRIPTION_PERIOD {
+        return Err(invalid("a period can be at most a year"))
+    }
+    if periods as u64 * period > MAX_SUBSCRIPTION_LENGTH {
+        return Err(invalid("can run for at most ten years"))
+    }
+    let deposit = &info.funds[0];
+    if !(deposit.amount % Uint128::from(periods)).is_zero() {
+        return Err(invalid("the deposit must split evenly between the periods"))
+    }
+
+    let subscriber = donation_sender(deps.as_ref(), &info, sender)?;
+    let creator = find_recipient(deps.storage, creator)?;
+
+    let mut state = read_state(deps.storage).load()?;
+    let subscription = Subscription {
+        id: state.subscription_count,
+        subscriber,
+        creator,
+        rate: Coin { denom: deposit.denom.clone(), amount: deposit.amount / Uint128::from(periods) },
+        period,
+        periods,
+        claimed_periods: 0,
+        start: env.block.time
+    };
+    save_subscription(deps.storage, &subscription)?;
+    state.subscription_count += 1;
+    update_state(deps.storage).save(&state)?;
+
+    Ok(Response::new()
+        .add_attribute("subscription_id", subscription.id.to_string())
+        .add_attribute("rate", subscription.rate.to_string())
+        .add_attribute("periods", periods.to_string()))
+}
+
+// Pay the creator of a subscription for every period which has ended and hasn't been paid for yet
+fn pay_out_subscription(
+    storage: &mut dyn Storage,
+    env: &Env,
+    subscription: &mut Subscription
+) -> Result<Vec<BankMsg>, ContractError> {
+    let due = subscription.elapsed_periods(env.block.time) - subscription.claimed_periods;
+    if due == 0 {
+        return Ok(vec![])
+    }
+
+    let amount = Coin { denom: subscription.rate.denom.clone(), amount: subscription.rate.amount * Uint128::from(due) };
+    let mut donation = DonationInfo::new(subscription.subscriber.clone(), subscription.creator.clone(), vec![amount], String::new(), env.block.time);
+    donation.subscription_id = Some(subscription.id);
+    let (_, bank_msgs) = complete_donation(storage, env, donation)?;
+    subscription.claimed_periods += due;
+    Ok(bank_msgs)
+}
+
+// Pay the creator for the periods of a subscription which have ended. Anyone can do this
+fn claim_subscription(
+    deps: DepsMut,
+    env: Env,
+    id: u64
+) -> Result<Response, ContractError> {
+    let mut subscription = match subscriptions().get(deps.storage, &id) {
+        Some(subscription) => subscription,
+        None => return Err(ContractError::SubscriptionNotFound { id })
+    };
+
+    // Stored subscriptions always have periods left, so the next one to end is the first unpaid one
+    if subscription.elapsed_periods(env.block.time) == subscription.claimed_periods {
+        let next_claim = subscription.start.plus_seconds(subscription.period * (subscription.claimed_periods as u64 + 1));
+        return Err(ContractError::NothingToClaim { next_claim: next_claim.to_string() })
+    }
+
+    let bank_msgs = pay_out_subscription(deps.storage, &env, &mut subscription)?;
+
+    // Once every period has been paid for, the subscription is over
+    match subscription.claimed_periods == subscription.periods {
+        true => remove_subscription(deps.storage, &subscription)?,
+        false => save_subscription(deps.storage, &subscription)?
+    }
+
+    Ok(Response::new()
+        .add_messages(bank_msgs)
+        .add_attribute("subscription_id", id.to_string())
+        .add_attribute("claimed_periods", subscription.claimed_periods.to_string()))
+}
+
+// Cancel a subscription. The creator is paid for the periods which have ended, and the rest is refunded
+fn cancel_subscription(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64
+) -> Result<Response, ContractError> {
+    let mut subscription = match subscriptions().get(deps.storage, &id) {
+        Some(subscription) => subscription,
+        None => return Err(ContractError::SubscriptionNotFound { id })
+    };
+
+    if info.sender != subscription.subscriber.address {
+        return Err(ContractError::Unauthorized {  })
     }
 
-    let recipient_user = find_alpine_username(deps.storage, recipient)?;
+    let mut bank_msgs = pay_out_subscription(deps.storage, &env, &mut subscription)?;
+    remove_subscription(deps.storage, &subscription)?;
 
-    let donation = DonationInfo {
-        id: state.donation_count.clone(),
+    let unused_periods = subscription.periods - subscription.claimed_periods;
+    let refund = subscription.rate.amount * Uint128::from(unused_periods);
+    if !refund.is_zero() {
+        bank_msgs.push(BankMsg::Send {
+            to_address: subscription.subscriber.address.to_string(),
+            amount: vec![Coin { denom: subscription.rate.denom.clone(), amount: refund }]
+        });
+    }
+
+    Ok(Response::new()
+        .add_messages(bank_msgs)
+        .add_attribute("subscription_id", id.to_string())
+        .add_attribute("refunded_periods", unused_periods.to_string()))
+}
+
+// Replace the membership tiers of the registered user who sent the message
+fn set_tiers(
+    deps: DepsMut,
+    info: MessageInfo,
+    tiers: Vec<Tier>,
+    window: Option<u64>
+) -> Result<Response, ContractError> {
+    let user = get_user_by_address(deps.storage, info.sender)?;
+    if tiers.is_empty() {
+        if creator_tiers().contains(deps.storage, &user.address) {
+            creator_tiers().remove(deps.storage, &user.address)?;
+        }
+        return Ok(Response::new().add_attribute("tiers", "0"))
+    }
+
+    let window = window.unwrap_or(DEFAULT_TIER_WINDOW);
+    let tiers = validate_tiers(tiers, window)?;
+    let count = tiers.len();
+    creator_tiers().insert(deps.storage, &user.address, &CreatorTiers { tiers, window })?;
+
+    Ok(Response::new().add_attribute("tiers", count.to_string()))
+}
+
+// Replace how the donations of the registered user who sent the message are split between collaborators.
+// Collaborators can be given by username or by wallet address
+fn set_payout_split(
+    deps: DepsMut,
+    info: MessageInfo,
+    shares: Vec<PayoutShareMsg>
+) -> Result<Response, ContractError> {
+    let user = get_user_by_address(deps.storage, info.sender)?;
+    if shares.is_empty() {
+        if payout_splits().contains(deps.storage, &user.address) {
+            payout_splits().remove(deps.storage, &user.address)?;
+        }
+        return Ok(Response::new().add_attribute("payout_shares", "0"))
+    }
+
+    let invalid = |reason: String| ContractError::InvalidPayoutSplit { reason };
+    if shares.len() > MAX_PAYOUT_SHARES {
+        return Err(invalid(format!("a split can have at most {} collaborators", MAX_PAYOUT_SHARES)))
+    }
+
+    let mut split: Vec<PayoutShare> = vec![];
+    for share in shares {
+        let address = find_user_or_address(deps.as_ref(), share.recipient.clone())?;
+        if share.weight == 0 {
+            return Err(invalid(format!("{} has no weight", share.recipient)))
+        }
+        if split.iter().any(|existing| existing.address == address) {
+            return Err(invalid(format!("{} is listed more than once", share.recipient)))
+        }
+        split.push(PayoutShare { address, weight: share.weight });
+    }
+
+    let total_weight: u64 = split.iter().map(|share| share.weight as u64).sum();
+    if total_weight != TOTAL_PAYOUT_WEIGHT as u64 {
+        return Err(invalid(format!("weights add up to {} instead of {}", total_weight, TOTAL_PAYOUT_WEIGHT)))
+    }
+
+    payout_splits().insert(deps.storage, &user.address, &split)?;
+    Ok(Response::new().add_attribute("payout_shares", split.len().to_string()))
+}
+
+// Resolve a username to its address, falling back to treating it as a wallet address
+fn find_user_or_address(deps: Deps, user: String) -> Result<Addr, ContractError> {
+    match find_alpine_username(deps.storage, user.clone()) {
+        Ok(found) => Ok(found.address),
+        Err(_) => deps.api.addr_validate(&user).map_err(|_| ContractError::UserNotFound { user })
+    }
+}
+
+fn set_charity_pledge(
+    deps: DepsMut,
+    info: MessageInfo,
+    charity: String,
+    percent: u32
+) -> Result<Response, ContractError> {
+    let user = get_user_by_address(deps.storage, info.sender)?;
+    if percent == 0 {
+        if charity_pledges().contains(deps.storage, &user.address) {
+            charity_pledges().remove(deps.storage, &user.address)?;
+        }
+        return Ok(Response::new().add_attribute("charity_percent", "0"))
+    }
+
+    let invalid = |reason: String| ContractError::InvalidCharityPledge { reason };
+    if percent > 100 {
+        return Err(invalid(format!("can't pledge {}% of donations", percent)))
+    }
+    let address = find_user_or_address(deps.as_ref(), charity)?;
+    if address == user.address {
+        return Err(invalid("the charity can't be yourself".to_string()))
+    }
+
+    charity_pledges().insert(deps.storage, &user.address, &CharityPledge { address: address.clone(), percent })?;
+    Ok(Response::new()
+        .add_attribute("charity", address)
+        .add_attribute("charity_percent", percent.to_string()))
+}
+
+// Mark donations as read. Every ID has to be a donation the sender received, but acknowledging one twice is fine
+fn acknowledge_donations(deps: DepsMut, info: MessageInfo, ids: Vec<u64>) -> Result<Response, ContractError> {
+    for id in &ids {
+        match donations().get(deps.storage, id) {
+            Some(donation) if donation.recipient.address == info.sender => {},
+            Some(_) => return Err(ContractError::Unauthorized {  }),
+            None => return Err(ContractError::DonationNotFound { id: *id })
+        }
+    }
+
+    let unread_count = mark_donations_read(deps.storage, &info.sender, &ids)?;
+    Ok(Response::new().add_attribute("unread_count", unread_count.to_string()))
+}
+
+// Set the donation fee for a recipient given by username or address, until an optional expiry. No fee removes
+// the override, so the recipient pays the default fee again. Only the owner can do this
+fn set_fee_override(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    recipient: String,
+    basis_points: Option<u32>,
+    expires: Option<Timestamp>
+) -> Result<Response, ContractError> {
+    let state = read_state(deps.storage).load()?;
+    if info.sender.as_str() != state.owner {
+        return Err(ContractError::Unauthorized {  })
+    }
+
+    let address = find_user_or_address(deps.as_ref(), recipient)?;
+    let basis_points = match basis_points {
+        Some(basis_points) => basis_points,
+        None => {
+            if fee_overrides().contains(deps.storage, &address) {
+                fee_overrides().remove(deps.storage, &address)?;
+            }
+            return Ok(Response::new().add_attribute("fee_override", "removed"))
+        }
+    };
+
+    let invalid = |reason: String| ContractError::InvalidFeeOverride { reason };
+    if basis_points > TOTAL_FEE_BASIS_POINTS {
+        return Err(invalid(format!("the fee can be at most {} basis points", TOTAL_FEE_BASIS_POINTS)))
+    }
+    if matches!(expires, Some(expires) if expires <= env.block.time) {
+        return Err(invalid("the expiry has already passed".to_string()))
+    }
+
+    fee_overrides().insert(deps.storage, &address, &FeeOverride { basis_points, expires })?;
+    Ok(Response::new()
+        .add_attribute("recipient", address)
+        .add_attribute("fee_basis_points", basis_points.to_string()))
+}
+
+fn validate_tiers(tiers: Vec<Tier>, window: u64) -> Result<Vec<Tier>, ContractError> {
+    let invalid = |reason: String| ContractError::InvalidTiers { reason };
+    if tiers.len() > MAX_TIERS {
+        return Err(invalid(format!("a creator can have at most {} tiers", MAX_TIERS)))
+    }
+    if window == 0 {
+        return Err(invalid(String::from("the window can't be empty")))
+    }
+
+    for (i, tier) in tiers.iter().enumerate() {
+        if tier.name.is_empty() || tier.name.len() > 32 {
+            return Err(invalid(String::from("names must be between 1 and 32 characters")))
+        }
+        if tiers[..i].iter().any(|other| other.name == tier.name) {
+            return Err(invalid(format!("{} is used more than once", tier.name)))
+        }
+        if tier.denom.is_empty() || tier.amount.is_zero() {
+            return Err(invalid(format!("{} needs an amount", tier.name)))
+        }
+        // Supporters get the highest tier they qualify for, so within a denom each tier has to ask for more
+        if tiers[..i].iter().any(|other| other.denom == tier.denom && other.amount >= tier.amount) {
+            return Err(invalid(format!("{} needs more {} than the tiers before it", tier.name, tier.denom)))
+        }
+    }
+
+    Ok(tiers)
+}
+
+// Pay out the fee treasury to the team wallets, split by weight. Whatever can't be split evenly goes to the first wallet
+fn withdraw_fees(
+    deps: DepsMut,
+    info: MessageInfo
+) -> Result<Response, ContractError> {
+    let mut state = read_state(deps.storage).load()?;
+    if info.sender.as_str() != state.owner {
+        return Err(ContractError::Unauthorized {  })
+    }
+    if state.fee_treasury.is_empty() {
+        return Err(ContractError::NothingToWithdraw {  })
+    }
+
+    let weights: Vec<u128> = state.team_wallets.iter().map(|wallet| wallet.weight as u128).collect();
+    let mut payouts: Vec<Vec<Coin>> = vec![vec![]; state.team_wallets.len()];
+    for fees in &state.fee_treasury {
+        for (i, share) in split_by_weight(fees.amount, &weights).into_iter().enumerate() {
+            add_coin(&mut payouts[i], &Coin { denom: fees.denom.clone(), amount: share });
+        }
+    }
+
+    let mut bank_msgs = vec![];
+    for (wallet, mut amount) in state.team_wallets.iter().zip(payouts) {
+        amount.retain(|coin| !coin.amount.is_zero());
+        if !amount.is_empty() {
+            bank_msgs.push(BankMsg::Send { to_address: wallet.address.to_string(), amount });
+        }
+    }
+
+    state.fee_treasury = vec![];
+    update_state(deps.storage).save(&state)?;
+    Ok(Response::new().add_messages(bank_msgs))
+}
+
+// Donate to a username or social handle which may not be registered yet. If it is registered, this is the same
+// as a regular donation. Otherwise the contract holds the funds until the name or handle is registered
+fn escrow_donation(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    sender: String,
+    recipient: String,
+    message: String
+) -> Result<Response, ContractError> {
+    if recipient.is_empty() {
+        return Err(ContractError::EmptyUsername {})
+    }
+
+    validate_donation(&info.funds, &message)?;
+    let sender_user = donation_sender(deps.as_ref(), &info, sender)?;
+
+    let (target, registered_user) = match recipient.contains(':') {
+        true => {
+            let handle = normalize_social_handle(&recipient)?;
+            let user = find_social_handle(deps.storage, &handle).ok();
+            (handle, user)
+        },
+        false => {
+            let username = validate_username(recipient)?;
+            let user = find_username_owner(deps.storage, &username)?;
+            (username.to_lowercase(), user)
+        }
+    };
+
+    if let Some(recipient_user) = registered_user {
+        let donation = DonationInfo::new(sender_user, recipient_user, info.funds, message, env.block.time);
+        let (donation, tx_messages) = complete_donation(deps.storage, &env, donation)?;
+        return Ok(Response::new()
+            .add_messages(tx_messages)
+            .add_attribute("id", donation.id.to_string())
+            .add_attribute("recipient_username", donation.recipient.username))
+    }
+
+    let mut state = read_state(deps.storage).load()?;
+    let escrow = EscrowedDonation {
+        id: state.escrow_count,
         sender: sender_user,
-        recipient: recipient_user,
+        target,
         amount: info.funds,
         message,
-        timestamp: Some(env.block.time)
+        timestamp: env.block.time,
+        expires: env.block.time.plus_seconds(state.escrow_timeout)
     };
+    save_escrow(deps.storage, &escrow)?;
+    state.escrow_count += 1;
+    update_state(deps.storage).save(&state)?;
 
-    update_donations(deps.storage, donation.clone())?;
-    let total_donation_amount = donation.amount.clone()[0].amount;
-    let donation_fee = Decimal::percent(3) * donation.amount.clone()[0].amount;
-    let recipient_donation = &coins((total_donation_amount - donation_fee).u128(), donation.amount.clone()[0].denom.clone());
-    let commission = &coins(donation_fee.u128(), donation.amount.clone()[0].denom.clone());
+    Ok(Response::new()
+        .add_attribute("escrow_id", escrow.id.to_string())
+        .add_attribute("target", escrow.target)
+        .add_attribute("expires", escrow.expires.to_string()))
+}
 
-    // Forward the funds to the relevant wallet address
-    let recipient_bank_msg = BankMsg::Send {
-        to_address: donation.recipient.address.to_string(),
-        amount: recipient_donation.clone()
+// Return an expired escrowed donation to its sender
+fn reclaim_escrow(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64
+) -> Result<Response, ContractError> {
+    let escrow = match escrows().get(deps.storage, &id) {
+        Some(escrow) => escrow,
+        None => return Err(ContractError::EscrowNotFound { id })
     };
 
-    // Take 3% donation fee to Alpine admin address
-    let fee_bank_msg = BankMsg::Send { 
-        to_address: Addr::unchecked("secret1225mx24unphqw8sdwndz8jmplmuzxg74dzjg0c").into_string(), 
-        amount: commission.clone()
+    if info.sender != escrow.sender.address {
+        return Err(ContractError::Unauthorized {  })
+    }
+
+    if env.block.time < escrow.expires {
+        return Err(ContractError::EscrowNotExpired { expires: escrow.expires.to_string() })
+    }
+
+    remove_escrow(deps.storage, &escrow)?;
+    let refund_bank_msg = BankMsg::Send {
+        to_address: escrow.sender.address.to_string(),
+        amount: escrow.amount
     };
 
-    let attributes = vec![("sender_address", donation.sender.address.to_string()), ("sender_username", donation.sender.username.to_string()), 
-                    ("recipient_address", donation.recipient.address.to_string()), ("recipient_username", donation.recipient.username.to_string()),
-                    ("amount", donation.amount[0].amount.to_string()), ("message", donation.message), ("timestamp", env.block.time.to_string()),
-                    ("id", donation.id.to_string()) ].into_iter();
-    let tx_messages = vec![recipient_bank_msg, fee_bank_msg].into_iter();
+    Ok(Response::new().add_message(refund_bank_msg).add_attribute("escrow_id", id.to_string()))
+}
 
-    Ok(Response::new().add_messages(tx_messages).add_attributes(attributes))
+// Pay out the escrowed donations which were waiting for the sender's username or one of their social handles,
+// oldest first. Escrows are claimed a page at a time, so that no number of them can stop a name being claimed
+fn claim_escrows(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    limit: Option<u32>
+) -> Result<Response, ContractError> {
+    let user = get_user_by_address(deps.storage, info.sender)?;
+    let mut remaining = limit.unwrap_or(DEFAULT_ESCROW_CLAIM_LIMIT).min(MAX_ESCROW_CLAIM_LIMIT) as u64;
+
+    let mut response = Response::new();
+    for target in escrow_targets(deps.storage, &user) {
+        let (taken, looked_at) = take_escrows(deps.storage, &target, remaining)?;
+        remaining -= looked_at;
+        for escrow in taken {
+            let donation = DonationInfo::new(escrow.sender, user.clone(), escrow.amount, escrow.message, escrow.timestamp);
+            let (donation, tx_messages) = complete_donation(deps.storage, &env, donation)?;
+            response = response
+                .add_messages(tx_messages)
+                .add_attribute("released_escrow_id", escrow.id.to_string())
+                .add_attribute("donation_id", donation.id.to_string());
+        }
+    }
+    Ok(response.add_attribute("pending_escrows", pending_escrows(deps.storage, &user).to_string()))
+}
+
+// The names escrowed donations can be waiting for to reach a user: their username and their social handles
+fn escrow_targets(storage: &dyn Storage, user: &AlpineUser) -> Vec<String> {
+    let mut targets = vec![user.username.to_lowercase()];
+    targets.extend(user_social_handles().get(storage, &user.address).unwrap_or_default());
+    targets
+}
+
+// How many escrowed donations a user can claim. Includes reclaimed escrows until a claim gets to them
+fn pending_escrows(storage: &dyn Storage, user: &AlpineUser) -> u64 {
+    escrow_targets(storage, user).iter().map(|target| escrows_by_target().bounds(storage, target).len()).sum()
+}
+
+// Link a verified social handle to a registered user. Alpine verifies ownership of the handle off-chain,
+// so only the owner can do this
+fn link_handle(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    address: Addr,
+    handle: String
+) -> Result<Response, ContractError> {
+    let state = read_state(deps.storage).load()?;
+    if info.sender.as_str() != state.owner {
+        return Err(ContractError::Unauthorized {  })
+    }
+
+    let user = get_user_by_address(deps.storage, address)?;
+    let handle = normalize_social_handle(&handle)?;
+    link_social_handle(deps.storage, &handle, &user.address)?;
+
+    Ok(Response::new()
+        .add_attribute("social_handle", handle)
+        .add_attribute("pending_escrows", pending_escrows(deps.storage, &user).to_string()))
 }
 
 // Register a new Alpine user
@@ -176,8 +1134,6 @@ fn register_user(
     mut user: AlpineUser,
     username: String
 ) -> Result<Response, ContractError> {
-    let mut state = read_state(deps.storage).load()?;
-    
     match validate_username(username.clone()) {
         Ok(_u) => (),
         Err(e) => return Err(e)
@@ -196,18 +1152,136 @@ fn register_user(
         false => return Err(ContractError::UserAlreadyExists {  } )
     };
 
-    let searched_username = match find_alpine_username(deps.storage, username.clone()) {
-        Ok(_alpine_user) => Err(ContractError::UsernameNotAvailable { username: username.clone() }),
-        Err(_e) => Ok(username)
-    }?;
+    if find_username_owner(deps.storage, &username)?.is_some() {
+        return Err(ContractError::UsernameNotAvailable { username })
+    }
 
-    user.username = searched_username.clone();
+    user.username = username;
+    let user = save_user(deps.storage, user)?;
 
-    state.users.append(&mut vec![user.clone()]);
-    update_state(deps.storage).save(&state).unwrap();
+    Ok(Response::new()
+        .add_attribute("username", user.username.clone())
+        .add_attribute("pending_escrows", pending_escrows(deps.storage, &user).to_string()))
+}
+
+// Update the settings of the registered user who sent the message. Settings which aren't provided are left as-is
+fn update_settings(
+    deps: DepsMut,
+    info: MessageInfo,
+    discoverable: Option<bool>,
+    claim_donations: Option<bool>,
+    public_leaderboard: Option<bool>
+) -> Result<Response, ContractError> {
+    let user = get_user_by_address(deps.storage, info.sender)?;
+    let mut settings = load_user_settings(deps.storage, &user.address);
+
+    if let Some(discoverable) = discoverable {
+        settings.discoverable = discoverable;
+    }
+    if let Some(claim_donations) = claim_donations {
+        settings.claim_donations = claim_donations;
+    }
+    if let Some(public_leaderboard) = public_leaderboard {
+        settings.public_leaderboard = public_leaderboard;
+    }
+
+    let settings = save_user_settings(deps.storage, &user, settings)?;
+    Ok(Response::new()
+        .add_attribute("discoverable", settings.discoverable.to_string())
+        .add_attribute("claim_donations", settings.claim_donations.to_string())
+        .add_attribute("public_leaderboard", settings.public_leaderboard.to_string()))
+}
+
+// Withdraw donations held for the sender in one denom
+fn withdraw(
+    deps: DepsMut,
+    info: MessageInfo,
+    denom: String,
+    amount: Option<Uint128>
+) -> Result<Response, ContractError> {
+    let amount = match amount {
+        Some(amount) => amount,
+        None => load_balances(deps.storage, &info.sender)
+            .into_iter()
+            .find(|balance| balance.denom == denom)
+            .map_or(Uint128::zero(), |balance| balance.amount)
+    };
+    if amount.is_zero() {
+        return Err(ContractError::NothingToWithdraw {  })
+    }
+
+    let funds = Coin { denom, amount };
+    debit_balance(deps.storage, &info.sender, &funds)?;
+
+    let withdraw_bank_msg = BankMsg::Send {
+        to_address: info.sender.to_string(),
+        amount: vec![funds.clone()]
+    };
+    Ok(Response::new().add_message(withdraw_bank_msg).add_attribute("withdrawn", funds.to_string()))
+}
 
-    
-    Ok(Response::new().add_attribute("username", user.username))
+// Withdraw every donation held for the sender
+fn withdraw_all(
+    deps: DepsMut,
+    info: MessageInfo
+) -> Result<Response, ContractError> {
+    let funds = load_balances(deps.storage, &info.sender);
+    if funds.is_empty() {
+        return Err(ContractError::NothingToWithdraw {  })
+    }
+
+    for balance in &funds {
+        debit_balance(deps.storage, &info.sender, balance)?;
+    }
+
+    let withdraw_bank_msg = BankMsg::Send {
+        to_address: info.sender.to_string(),
+        amount: funds
+    };
+    Ok(Response::new().add_message(withdraw_bank_msg))
+}
+
+// Normalize a social handle to "platform:handle", in lowercase and without a leading "@" on the handle
+fn normalize_social_handle(handle: &str) -> Result<String, ContractError> {
+    let invalid = |reason: &str| ContractError::InvalidSocialHandle { handle: handle.to_string(), reason: reason.to_string() };
+
+    let (platform, name) = match handle.split_once(':') {
+        Some((platform, name)) => (platform.trim().to_lowercase(), name.trim().trim_start_matches('@').to_lowercase()),
+        None => return Err(invalid("must be formatted as platform:handle"))
+    };
+
+    if platform.is_empty() || name.is_empty() {
+        return Err(invalid("must be formatted as platform:handle"))
+    }
+
+    if platform.len() + name.len() > 64 {
+        return Err(invalid("must be shorter than 65 characters"))
+    }
+
+    let allowed = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-';
+    if !platform.chars().all(allowed) || !name.chars().all(allowed) {
+        return Err(invalid("only alphanumeric, underscores, dots, and dashes are allowed"))
+    }
+
+    Ok(format!("{}:{}", platform, name))
+}
+
+// Find the user who registered a username, ignoring case. Usernames are unique by skeleton, so a lookalike of an
+// existing name is rejected, so that users can't be impersonated and nothing is held for a name which can never
+// be registered
+fn find_username_owner(storage: &dyn Storage, username: &str) -> Result<Option<AlpineUser>, ContractError> {
+    let owner = match username_skeletons().get(storage, &username_skeleton(username)) {
+        Some(owner) => owner,
+        None => return Ok(None)
+    };
+    let existing_user = get_user_by_address(storage, owner)?;
+    if existing_user.username.to_lowercase() != username.to_lowercase() {
+        return Err(ContractError::InvalidUsername {
+            username: username.to_string(),
+            reason: String::from("too similar to an existing username")
+        })
+    }
+    Ok(Some(existing_user))
 }
 
 fn validate_username(username: String) -> Result<String, ContractError> {
@@ -223,7 +1297,8 @@ fn validate_username(username: String) -> Result<String, ContractError> {
     }
 
     for c in username.chars() {
-        if !(c.is_ascii_alphabetic() || c.is_numeric() || c == '-' || c == '_') {
+        // Non-ASCII digits are only allowed when the skeleton can fold them, so they can't slip past lookalike checks
+        if !(c.is_ascii_alphanumeric() || fold_digit(c).is_ascii_digit() || c == '-' || c == '_') {
             return Err(ContractError::InvalidUsername { 
                 username,
                 reason: String::from("only alphanumeric, underscores, and dashes are allowed")
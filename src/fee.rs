@@ -0,0 +1,170 @@
+use std::cmp::{max, min};
+use std::convert::TryFrom;
+
+use cosmwasm_std::{Coin, StdError, Uint128, Uint256};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::error::ContractError;
+use crate::state::FeeOverride;
+
+// Fees are in basis points, so 10000 is 100%
+pub const TOTAL_FEE_BASIS_POINTS: u32 = 10_000;
+
+// The donation fee charged to recipients without a fee override or a volume tier, in basis points
+pub const DEFAULT_FEE_BASIS_POINTS: u32 = 300;
+
+const MAX_FEE_LIMITS: usize = 20;
+const MAX_FEE_TIERS: usize = 20;
+
+// How the donation fee is worked out. Configured by the owner
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FeeSchedule {
+    pub basis_points: u32,
+    // Minimum and maximum fees, per denom
+    #[serde(default)]
+    pub limits: Vec<DenomFeeLimits>,
+    // Lower fees for recipients who have received more in a denom
+    #[serde(default)]
+    pub tiers: Vec<FeeTier>,
+    #[serde(default)]
+    pub rounding: FeeRounding
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        FeeSchedule { basis_points: DEFAULT_FEE_BASIS_POINTS, limits: vec![], tiers: vec![], rounding: FeeRounding::Down }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DenomFeeLimits {
+    pub denom: String,
+    #[serde(default)]
+    pub min: Uint128,
+    pub max: Option<Uint128>
+}
+
+// A fee which applies to recipients once they've received at least `volume` of a denom, before fees
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FeeTier {
+    pub denom: String,
+    pub volume: Uint128,
+    pub basis_points: u32
+}
+
+// How a fee which doesn't come out to a whole amount is rounded. Nearest rounds halves up
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FeeRounding {
+    #[default]
+    Down,
+    Up,
+    Nearest
+}
+
+// How a donation is split between the fee and the recipient. `fee` and `net` always add up to the donation
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FeeBreakdown {
+    pub fee: Coin,
+    pub net: Coin,
+    pub basis_points: u32
+}
+
+impl FeeSchedule {
+    // The fee in basis points for a recipient who has received `volume` of a denom so far
+    pub fn rate(&self, denom: &str, volume: Uint128) -> u32 {
+        self.tiers.iter()
+            .filter(|tier| tier.denom == denom && tier.volume <= volume)
+            .max_by_key(|tier| tier.volume)
+            .map_or(self.basis_points, |tier| tier.basis_points)
+    }
+
+    pub fn validate(&self) -> Result<(), ContractError> {
+        let invalid = |reason: String| ContractError::InvalidFeeSchedule { reason };
+        if self.basis_points > TOTAL_FEE_BASIS_POINTS {
+            return Err(invalid(format!("the fee can be at most {} basis points", TOTAL_FEE_BASIS_POINTS)))
+        }
+        if self.limits.len() > MAX_FEE_LIMITS {
+            return Err(invalid(format!("at most {} denoms can have limits", MAX_FEE_LIMITS)))
+        }
+        if self.tiers.len() > MAX_FEE_TIERS {
+            return Err(invalid(format!("there can be at most {} tiers", MAX_FEE_TIERS)))
+        }
+
+        for (i, limits) in self.limits.iter().enumerate() {
+            if self.limits[..i].iter().any(|other| other.denom == limits.denom) {
+                return Err(invalid(format!("{} has limits more than once", limits.denom)))
+            }
+            if matches!(limits.max, Some(max) if max < limits.min) {
+                return Err(invalid(format!("the maximum fee for {} is below its minimum", limits.denom)))
+            }
+        }
+        for (i, tier) in self.tiers.iter().enumerate() {
+            if tier.basis_points > TOTAL_FEE_BASIS_POINTS {
+                return Err(invalid(format!("the fee can be at most {} basis points", TOTAL_FEE_BASIS_POINTS)))
+            }
+            if self.tiers[..i].iter().any(|other| other.denom == tier.denom && other.volume == tier.volume) {
+                return Err(invalid(format!("{} has more than one tier at {}", tier.denom, tier.volume)))
+            }
+        }
+        Ok(())
+    }
+}
+
+// Work out the fee on a donation to a recipient who has received `volume` of its denom so far. An active fee
+// override is charged as agreed without the minimum fee, but is still capped at the maximum. The fee never takes
+// more than the donation. When a limit changed the fee, `basis_points` is the rate which was effectively charged,
+// rounded down
+pub fn calculate_fee(
+    schedule: &FeeSchedule,
+    fee_override: Option<&FeeOverride>,
+    amount: &Coin,
+    volume: Uint128
+) -> Result<FeeBreakdown, ContractError> {
+    let basis_points = match fee_override {
+        Some(fee_override) => fee_override.basis_points,
+        None => schedule.rate(&amount.denom, volume)
+    };
+    let nominal_fee = apply_rate(amount.amount, basis_points, &schedule.rounding)?;
+    let mut fee = nominal_fee;
+    if let Some(limits) = schedule.limits.iter().find(|limits| limits.denom == amount.denom) {
+        if fee_override.is_none() {
+            fee = max(fee, limits.min);
+        }
+        if let Some(max_fee) = limits.max {
+            fee = min(fee, max_fee);
+        }
+    }
+
+    fee = min(fee, amount.amount);
+    let basis_points = match fee == nominal_fee {
+        true => basis_points,
+        // The fee is at most the donation, so this is at most TOTAL_FEE_BASIS_POINTS
+        false => fee.multiply_ratio(TOTAL_FEE_BASIS_POINTS, amount.amount).u128() as u32
+    };
+    let net = amount.amount.checked_sub(fee).map_err(StdError::from)?;
+    Ok(FeeBreakdown {
+        fee: Coin { denom: amount.denom.clone(), amount: fee },
+        net: Coin { denom: amount.denom.clone(), amount: net },
+        basis_points
+    })
+}
+
+// Take a number of basis points of an amount, rounded as configured
+fn apply_rate(amount: Uint128, basis_points: u32, rounding: &FeeRounding) -> Result<Uint128, ContractError> {
+    let product = amount.full_mul(basis_points);
+    let denominator = Uint256::from(TOTAL_FEE_BASIS_POINTS);
+    let quotient = product / denominator;
+    let remainder = product % denominator;
+    let round_up = match rounding {
+        FeeRounding::Down => false,
+        FeeRounding::Up => !remainder.is_zero(),
+        FeeRounding::Nearest => remainder + remainder >= denominator
+    };
+    let fee = match round_up {
+        true => quotient + Uint256::one(),
+        false => quotient
+    };
+    Ok(Uint128::try_from(fee).map_err(StdError::from)?)
+}
@@ -1,6 +1,7 @@
 mod error;
 pub mod msg;
 pub mod state;
+pub mod fee;
 pub mod execute;
 pub mod query;
 pub mod contract_tests;
@@ -1,8 +1,9 @@
 use schemars::JsonSchema;
 use secret_toolkit_permit::Permit;
 use serde::{Deserialize, Serialize};
-use crate::state::{DonationInfo, AlpineUser};
-use cosmwasm_std::Addr;
+use crate::state::{DonationInfo, AlpineUser, UserSettings, EscrowedDonation, TeamWallet, Subscription, Tier, PayoutShare, CharityPledge, FeeOverride, LargestDonation};
+use crate::fee::FeeSchedule;
+use cosmwasm_std::{Addr, Coin, Timestamp, Uint128};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InstantiateMsg { }
@@ -13,34 +14,187 @@ pub struct MigrateMsg { }
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
-    SendDonation { sender: String, recipient: String, message: String },
+    SendDonation { sender: String, recipient: String, message: String, campaign_id: Option<u64> },
+    // Donate to several recipients at once. The funds sent have to match the total of the items exactly
+    SendDonations { sender: String, items: Vec<DonationItem> },
     RegisterUser { user: AlpineUser, username: String },
-    ClearContract {  }
+    UpdateSettings { discoverable: Option<bool>, claim_donations: Option<bool>, public_leaderboard: Option<bool> },
+    UpdateConfig {
+        disable_address_lookup: Option<bool>,
+        escrow_timeout: Option<u64>,
+        team_wallets: Option<Vec<TeamWallet>>,
+        fee_schedule: Option<FeeSchedule>,
+        leaderboard_min_donors: Option<u32>
+    },
+    EscrowDonation { sender: String, recipient: String, message: String },
+    ReclaimEscrow { id: u64 },
+    // Pay out escrowed donations waiting for the sender's username or social handles, up to `limit` at a time
+    ClaimEscrows { limit: Option<u32> },
+    LinkSocialHandle { address: Addr, handle: String },
+    // Withdraw from the sender's balance. Withdraws the whole balance of the denom if no amount is given
+    Withdraw { denom: String, amount: Option<Uint128> },
+    WithdrawAll {  },
+    // Prepay a subscription to a creator for a number of periods. The funds are split evenly between the periods,
+    // which last 30 days unless another `period` in seconds is given
+    Subscribe { sender: String, creator: String, periods: u32, period: Option<u64> },
+    ClaimSubscription { id: u64 },
+    CancelSubscription { id: u64 },
+    // All-or-nothing campaigns hold their donations until the deadline. Then the creator claims them if the target
+    // was met, and otherwise each donor can get a refund
+    CreateCampaign { description: String, target: Coin, deadline: Timestamp, all_or_nothing: Option<bool> },
+    // Pledges are claimed up to `limit` at a time, oldest first
+    ClaimCampaign { campaign_id: u64, limit: Option<u32> },
+    Refund { campaign_id: u64 },
+    // Split the sender's donations between collaborators, given by username or address with weights in basis
+    // points which add up to 10000. An empty list removes the split
+    SetPayoutSplit { shares: Vec<PayoutShareMsg> },
+    // Pass a percentage of every donation the sender receives, after the fee, on to a charity given by username
+    // or address. A percentage of 0 removes the pledge
+    SetCharityPledge { charity: String, percent: u32 },
+    // Replace the sender's membership tiers, ordered from lowest to highest. An empty list removes them
+    SetTiers { tiers: Vec<Tier>, window: Option<u64> },
+    // Charge a recipient given by username or address a different donation fee in basis points, such as 0 for
+    // charities, until an optional expiry. No fee removes the override. Only the owner can do this
+    SetFeeOverride { recipient: String, basis_points: Option<u32>, expires: Option<Timestamp> },
+    // Pay out the fee treasury to the team wallets. Only the owner can do this
+    WithdrawFees {  },
+    // Mark donations the sender received as read, such as once they've been read out on stream
+    AcknowledgeDonations { ids: Vec<u64> },
+    // Donations are cleared up to `limit` at a time, newest first. Users are cleared along with the last of them
+    ClearContract { limit: Option<u32> }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct DonationItem {
+    pub recipient: String,
+    pub amount: Coin,
+    pub message: String
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PayoutShareMsg {
+    pub recipient: String,
+    pub weight: u32
+}
+
+// Narrows down a donation history. Every field is optional and the bounds are inclusive
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct DonationFilter {
+    #[serde(default)]
+    pub from_height: Option<u64>,
+    #[serde(default)]
+    pub to_height: Option<u64>,
+    #[serde(default)]
+    pub from: Option<Timestamp>,
+    #[serde(default)]
+    pub to: Option<Timestamp>,
+    #[serde(default)]
+    pub denom: Option<String>,
+    // Compared against the amount sent, before fees
+    #[serde(default)]
+    pub min_amount: Option<Uint128>,
+    // The username on the other side of the donation: the recipient of sent donations, or the sender of
+    // received ones
+    #[serde(default)]
+    pub counterparty: Option<String>,
+    // Only donations the recipient hasn't acknowledged yet. Ignored for sent donations
+    #[serde(default)]
+    pub unread_only: bool
+}
+
+// Donations are listed oldest first unless another order is given. Largest first compares amounts without
+// regard to denom, so it's best combined with a denom filter. It pages through the history oldest first and
+// sorts each page, so it only ranks the donations on the same page
+// The permissions a permit can be signed with. The first four are the SNIP-20 ones, so existing permits keep
+// working. A permit which only carries `SupporterTier` can be handed to other contracts, since it only
+// answers `GetSupporterTier`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AlpinePermission {
+    Allowance,
+    Balance,
+    History,
+    Owner,
+    SupporterTier
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DonationOrder {
+    #[default]
+    OldestFirst,
+    NewestFirst
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryWithPermitMsg {
-    GetSentDonations{ sender: String },
-    GetReceivedDonations { recipient: String },
+    // Donation histories are listed a page at a time, continuing after the donation ID in `start_after`
+    GetSentDonations{ sender: String, filter: Option<DonationFilter>, order: Option<DonationOrder>, limit: Option<u32>, start_after: Option<u64> },
+    GetReceivedDonations { recipient: String, filter: Option<DonationFilter>, order: Option<DonationOrder>, limit: Option<u32>, start_after: Option<u64> },
+    // A single donation, which only its sender and recipient can look up
+    GetDonation { id: u64 },
+    WhoAmI { },
+    GetEscrowedDonations { },
+    GetBalances { },
+    GetTreasury { },
+    GetSubscriptions { },
+    GetSubscribers { },
+    // The tier the permit signer has reached with a creator. Other contracts can ask supporters for a permit
+    // with only the `supporter_tier` permission and call this to gate content by tier
+    GetSupporterTier { creator: String },
+    // Running totals of the donations the permit signer has received
+    GetUserStats { },
+    // The supporters who have given the permit signer the most of a denom. Anonymous supporters are listed
+    // without their name or address
+    TopSupporters { limit: Option<u32>, denom: String },
+    // Platform-wide totals, for the owner. Creators count as active if they received a donation in the last
+    // `window_days` days, including today
+    GetPlatformStats { window_days: Option<u32> },
+    // How many donations the permit signer has received and not acknowledged yet
+    GetUnreadCount { },
 }
 
+// Permit queries make up most of the size, but the message is only deserialized once per query
+#[allow(clippy::large_enum_variant)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
     GetDonationCount {  },
     IsUsernameAvailable { username: String },
-    GetAllUsers { },
+    SearchUsers { prefix: String, limit: Option<u32>, start_after: Option<String> },
     GetUserByAddr { address: Addr },
     GetUserByName { username: String },
-    WithPermit { permit: Permit, query: QueryWithPermitMsg }
+    GetCreatorTiers { creator: String },
+    GetCampaign { id: u64 },
+    GetCampaigns { creator: String },
+    GetCharityPledge { creator: String },
+    // The donation fee in basis points which a recipient is charged on a denom right now
+    GetFeeRate { recipient: String, denom: String },
+    GetFeeSchedule {  },
+    // This week's creators by number of donations. Only creators who opted in and had enough distinct donors
+    // are listed, with their totals rounded down to a power of ten
+    GetLeaderboard { limit: Option<u32> },
+    WithPermit { permit: Permit<AlpinePermission>, query: QueryWithPermitMsg }
 }
 
 // Return a list of donation IDs mapped to the data stored in the donation
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct MultiDonationResponse {
-    pub donations: Vec<DonationInfo>
+    pub donations: Vec<DonationInfo>,
+    // Where the next page starts, to pass as `start_after`. Missing once the whole history was listed. A page
+    // can have fewer donations than the limit, or none, when few donations match the filter
+    pub next: Option<u64>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct DonationResponse {
+    pub donation: DonationInfo
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -65,4 +219,147 @@ pub struct UsernameAvailableResponse {
 #[serde(rename_all = "snake_case")]
 pub struct AlpineUserResponse {
     pub user: AlpineUser,
-}
\ No newline at end of file
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct WhoAmIResponse {
+    pub user: AlpineUser,
+    pub settings: UserSettings,
+    pub social_handles: Vec<String>,
+    pub payout_split: Vec<PayoutShare>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct EscrowedDonationsResponse {
+    pub escrows: Vec<EscrowedDonation>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct BalancesResponse {
+    pub balances: Vec<Coin>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct TreasuryResponse {
+    pub fees: Vec<Coin>,
+    pub team_wallets: Vec<TeamWallet>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SubscriptionsResponse {
+    pub subscriptions: Vec<Subscription>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct CreatorTiersResponse {
+    pub tiers: Vec<Tier>,
+    pub window: u64
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SupporterTierResponse {
+    pub supporter: Addr,
+    pub tier: Option<Tier>
+}
+
+// The public view of a campaign. Donors aren't included
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct CampaignResponse {
+    pub id: u64,
+    pub creator: String,
+    pub description: String,
+    pub target: Coin,
+    pub deadline: Timestamp,
+    pub raised: Uint128,
+    // How much of the target has been raised, rounded down. Goes past 100 if the target is exceeded
+    pub progress_percent: u64,
+    pub donation_count: u64,
+    pub all_or_nothing: bool,
+    pub claimed: bool
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct CampaignsResponse {
+    pub campaigns: Vec<CampaignResponse>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct CharityPledgeResponse {
+    pub pledge: Option<CharityPledge>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct FeeRateResponse {
+    pub fee: FeeOverride
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct UserStatsResponse {
+    pub received: Vec<Coin>,
+    pub earned: Vec<Coin>,
+    pub donation_count: u64,
+    pub supporter_count: u64,
+    pub largest_donations: Vec<Coin>,
+    // Received before fees since the start of the current calendar month
+    pub month_received: Vec<Coin>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct TopSupporter {
+    pub supporter: AlpineUser,
+    pub anonymous: bool,
+    pub total: Coin,
+    pub donation_count: u64
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct TopSupportersResponse {
+    pub supporters: Vec<TopSupporter>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct LeaderboardEntry {
+    pub username: String,
+    pub donation_count: u64,
+    // At least this much was received in each denom, before fees
+    pub received_at_least: Vec<Coin>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct LeaderboardResponse {
+    pub creators: Vec<LeaderboardEntry>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PlatformStatsResponse {
+    pub registered_users: u64,
+    pub active_creators: u64,
+    pub window_days: u32,
+    // Before fees
+    pub volume: Vec<Coin>,
+    pub fees: Vec<Coin>,
+    pub largest_donations: Vec<LargestDonation>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct UnreadCountResponse {
+    pub count: u64
+}
@@ -1,129 +1,559 @@
-use cosmwasm_std::{entry_point, StdError, CanonicalAddr};
+use std::collections::HashMap;
+use std::ops::Range;
+
+use cosmwasm_std::{entry_point, StdError};
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::{
     Binary, 
     Deps, 
     Env, 
-    StdResult, 
     to_binary,
-    Addr
+    Addr,
+    Coin,
+    Uint128,
+    Storage
 };
 use secret_toolkit_permit::{Permit, validate};
 
 use crate::msg::{
-    QueryMsg, 
-    MultiDonationResponse, 
+    QueryMsg, AlpinePermission,
+    MultiDonationResponse, DonationResponse, DonationFilter, DonationOrder,
     UsernameAvailableResponse,
     MultiUserResponse,
     AlpineUserResponse, 
-    DonationCountResponse, QueryWithPermitMsg
+    DonationCountResponse, QueryWithPermitMsg,
+    WhoAmIResponse, EscrowedDonationsResponse, BalancesResponse, TreasuryResponse,
+    SubscriptionsResponse, CreatorTiersResponse, SupporterTierResponse,
+    CampaignResponse, CampaignsResponse, CharityPledgeResponse, FeeRateResponse,
+    UserStatsResponse, TopSupporter, TopSupportersResponse, LeaderboardEntry, LeaderboardResponse,
+    PlatformStatsResponse, UnreadCountResponse
 };
 use crate::state::{ 
-    AlpineUser, 
-    DonationInfo, 
+    DonationInfo, AlpineUser, donations, donations_by_sender, donations_by_recipient,
     donation_count,
     find_alpine_username, 
-    contains_username, get_user_by_address, read_state
+    contains_username, get_user_by_address, read_state, search_directory,
+    load_user_settings, user_social_handles, escrows, escrows_by_sender,
+    load_balances, load_subscriptions, subscriptions_by_subscriber, subscriptions_by_creator,
+    load_creator_tiers, recent_support, Campaign, campaigns, campaigns_by_creator,
+    load_payout_split, charity_pledges, load_fee_override,
+    load_received_volume, FeeOverride, load_user_stats, month_index,
+    top_supporters, supporter_totals, MAX_TOP_SUPPORTERS, load_leaderboard_creators, weekly_stats, week_index,
+    load_platform_stats, count_active_creators, is_unread, load_unread_count
 };
+use crate::error::{ContractError, QueryError};
+
+const DEFAULT_SEARCH_LIMIT: u32 = 10;
+const MAX_SEARCH_LIMIT: u32 = 30;
+const DEFAULT_SUPPORTERS_LIMIT: u32 = 10;
+const MAX_SUPPORTERS_LIMIT: u32 = MAX_TOP_SUPPORTERS as u32;
+const DEFAULT_LEADERBOARD_LIMIT: u32 = 10;
+const MAX_LEADERBOARD_LIMIT: u32 = 50;
+const DEFAULT_ACTIVE_WINDOW_DAYS: u32 = 30;
+const MAX_ACTIVE_WINDOW_DAYS: u32 = 365;
+const DEFAULT_DONATIONS_LIMIT: u32 = 30;
+const MAX_DONATIONS_LIMIT: u32 = 100;
+// How many donations one page of a history looks at, so that a narrow filter over a long history still fits
+// in a query
+const MAX_DONATIONS_SCANNED: u64 = 300;
 
 #[entry_point]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
-    match msg {
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> Result<Binary, QueryError> {
+    let response = match msg {
         QueryMsg::GetDonationCount {  } => to_binary(&get_donation_count(deps)?),
         QueryMsg::IsUsernameAvailable { username } => to_binary(&is_username_available(deps, username)?),
-        QueryMsg::GetAllUsers {  } => to_binary(&get_all_users(deps)?),
+        QueryMsg::SearchUsers { prefix, limit, start_after } => to_binary(&search_users(deps, prefix, limit, start_after)?),
         QueryMsg::GetUserByAddr { address } => to_binary(&get_user_by_addr(deps, address)?),
         QueryMsg::GetUserByName { username } => to_binary(&get_user_by_name(deps, username)?),
-        QueryMsg::WithPermit { permit, query } => to_binary(&permit_query(deps, permit, query)?)
-    }
+        QueryMsg::GetCreatorTiers { creator } => to_binary(&get_creator_tiers(deps, creator)?),
+        QueryMsg::GetCampaign { id } => to_binary(&get_campaign(deps, id)?),
+        QueryMsg::GetCampaigns { creator } => to_binary(&get_campaigns(deps, creator)?),
+        QueryMsg::GetCharityPledge { creator } => to_binary(&get_charity_pledge(deps, creator)?),
+        QueryMsg::GetFeeRate { recipient, denom } => to_binary(&get_fee_rate(deps, env, recipient, denom)?),
+        QueryMsg::GetFeeSchedule {  } => to_binary(&read_state(deps.storage).load()?.fee_schedule),
+        QueryMsg::GetLeaderboard { limit } => to_binary(&get_leaderboard(deps, env, limit)?),
+        QueryMsg::WithPermit { permit, query } => Ok(permit_query(deps, env, permit, query)?)
+    }?;
+    Ok(response)
 }
 
-fn permit_query(deps: Deps, permit: Permit, query: QueryWithPermitMsg) -> StdResult<MultiDonationResponse> {
-    // Validate permit
+fn permit_query(deps: Deps, env: Env, permit: Permit<AlpinePermission>, query: QueryWithPermitMsg) -> Result<Binary, ContractError> {
+    // Validate permit. This gives us the address of the account which signed it
     let state = read_state(deps.storage).load()?;
-    validate(
+    let signer_address = match validate(
         deps,
         "revoked_permits",
         &permit,
         state.contract_address, 
         None
-    )?;
-    let public_key = permit.signature.pub_key;
-    let signer_address = public_key.canonical_address();
+    ) {
+        Ok(address) => Addr::unchecked(address),
+        Err(StdError::GenericErr { msg, .. }) => return Err(ContractError::InvalidPermit { reason: msg }),
+        Err(e) => return Err(ContractError::InvalidPermit { reason: e.to_string() })
+    };
+
+    // Permits shared with other contracts for tier checks can't read anything else of the signer's
+    let permissions = &permit.params.permissions;
+    let tier_only = !permissions.is_empty() && permissions.iter().all(|permission| *permission == AlpinePermission::SupporterTier);
+    if tier_only && !matches!(query, QueryWithPermitMsg::GetSupporterTier { .. }) {
+        return Err(ContractError::InvalidPermit { reason: String::from("permit only allows get_supporter_tier") })
+    }
+
+    let response = match query {
+        QueryWithPermitMsg::GetReceivedDonations { recipient, filter, order, limit, start_after } => {
+            let page = DonationPage { order: order.unwrap_or_default(), limit, start_after };
+            to_binary(&get_received_donations(deps, recipient, filter.unwrap_or_default(), page, signer_address)?)
+        },
+        QueryWithPermitMsg::GetDonation { id } => to_binary(&get_donation(deps, id, signer_address)?),
+        QueryWithPermitMsg::GetSentDonations { sender, filter, order, limit, start_after } => {
+            let page = DonationPage { order: order.unwrap_or_default(), limit, start_after };
+            to_binary(&get_sent_donations(deps, sender, filter.unwrap_or_default(), page, signer_address)?)
+        },
+        QueryWithPermitMsg::WhoAmI {  } => to_binary(&who_am_i(deps, signer_address)?),
+        QueryWithPermitMsg::GetEscrowedDonations {  } => to_binary(&get_escrowed_donations(deps, signer_address)?),
+        QueryWithPermitMsg::GetBalances {  } => to_binary(&get_balances(deps, signer_address)),
+        QueryWithPermitMsg::GetTreasury {  } => to_binary(&get_treasury(deps, signer_address)?),
+        QueryWithPermitMsg::GetSubscriptions {  } => to_binary(&get_subscriptions(deps, signer_address)),
+        QueryWithPermitMsg::GetSubscribers {  } => to_binary(&get_subscribers(deps, signer_address)),
+        QueryWithPermitMsg::GetSupporterTier { creator } => to_binary(&get_supporter_tier(deps, env, creator, signer_address)?),
+        QueryWithPermitMsg::GetUserStats {  } => to_binary(&get_user_stats(deps, env, signer_address)),
+        QueryWithPermitMsg::TopSupporters { limit, denom } => to_binary(&get_top_supporters(deps, limit, denom, signer_address)),
+        QueryWithPermitMsg::GetPlatformStats { window_days } => to_binary(&get_platform_stats(deps, env, window_days, signer_address)?),
+        QueryWithPermitMsg::GetUnreadCount {  } => to_binary(&get_unread_count(deps, signer_address))
+    }?;
+    Ok(response)
+}
+
+fn get_user_stats(deps: Deps, env: Env, signer_address: Addr) -> UserStatsResponse {
+    let stats = load_user_stats(deps.storage, &signer_address);
+    // The month's totals are only reset by the next donation, so they may belong to an earlier month
+    let month_received = match stats.month == month_index(env.block.time) {
+        true => stats.month_received,
+        false => vec![]
+    };
+    UserStatsResponse {
+        received: stats.received,
+        earned: stats.earned,
+        donation_count: stats.donation_count,
+        supporter_count: stats.supporter_count,
+        largest_donations: stats.largest_donations,
+        month_received
+    }
+}
+
+// Ties are listed in the order the supporters reached their total
+fn get_top_supporters(deps: Deps, limit: Option<u32>, denom: String, signer_address: Addr) -> TopSupportersResponse {
+    let limit = limit.unwrap_or(DEFAULT_SUPPORTERS_LIMIT).min(MAX_SUPPORTERS_LIMIT) as usize;
+    let ranked = top_supporters().get(deps.storage, &(signer_address.clone(), denom.clone())).unwrap_or_default();
+    let supporters = ranked.into_iter()
+        .take(limit)
+        .filter_map(|entry| {
+            let anonymous = entry.key.anonymous;
+            let total = supporter_totals().get(deps.storage, &(signer_address.clone(), entry.key))?;
+            let amount = Coin { denom: denom.clone(), amount: entry.amount };
+            Some(TopSupporter { supporter: total.supporter, anonymous, total: amount, donation_count: total.donation_count })
+        })
+        .collect();
+    TopSupportersResponse { supporters }
+}
 
-    match query {
-        QueryWithPermitMsg::GetReceivedDonations { recipient } => get_received_donations(deps, recipient, signer_address),
-        QueryWithPermitMsg::GetSentDonations { sender } => get_sent_donations(deps, sender, signer_address)
+fn get_platform_stats(deps: Deps, env: Env, window_days: Option<u32>, signer_address: Addr) -> Result<PlatformStatsResponse, ContractError> {
+    let state = read_state(deps.storage).load()?;
+    if signer_address.as_str() != state.owner {
+        return Err(ContractError::Unauthorized {  })
     }
+
+    let window_days = window_days.unwrap_or(DEFAULT_ACTIVE_WINDOW_DAYS).clamp(1, MAX_ACTIVE_WINDOW_DAYS);
+    let stats = load_platform_stats(deps.storage)?;
+    Ok(PlatformStatsResponse {
+        registered_users: state.users.len() as u64,
+        active_creators: count_active_creators(deps.storage, env.block.time, window_days),
+        window_days,
+        volume: stats.volume,
+        fees: stats.fees,
+        largest_donations: stats.largest_donations
+    })
 }
 
-fn get_donation_count(deps: Deps) -> StdResult<DonationCountResponse> {
+fn get_unread_count(deps: Deps, signer_address: Addr) -> UnreadCountResponse {
+    UnreadCountResponse { count: load_unread_count(deps.storage, &signer_address) }
+}
+
+fn get_donation_count(deps: Deps) -> Result<DonationCountResponse, ContractError> {
     let count = donation_count(deps.storage)?;
     Ok(DonationCountResponse { count })
 }
 
-fn get_sent_donations(deps: Deps, sender: String, signer_address: CanonicalAddr) -> StdResult<MultiDonationResponse> {
-    let state = read_state(deps.storage).load()?;
-    let sender_user = find_alpine_username(deps.storage, sender).unwrap();
+fn get_sent_donations(deps: Deps, sender: String, filter: DonationFilter, page: DonationPage, signer_address: Addr) -> Result<MultiDonationResponse, ContractError> {
+    let sender_user = find_alpine_username(deps.storage, sender)?;
+
+    // Validate that permit signer is the same as the queried address
+    if signer_address != sender_user.address {
+        return Err(ContractError::Unauthorized {  });
+    }
+
+    let index = donations_by_sender();
+    let bounds = index.bounds(deps.storage, &sender_user.address);
+    let id_at = |position| index.get(deps.storage, &sender_user.address, position);
+    let positions = filter_positions(deps.storage, bounds.start..bounds.end, &id_at, &filter);
+    let (donations, next) = page_donations(deps.storage, positions, id_at, page, |donation| {
+        donation.sender == sender_user && matches_filter(donation, &donation.recipient, &filter)
+    });
+
+    Ok(MultiDonationResponse { donations, next })
+}
+
+fn get_received_donations(deps: Deps, recipient: String, filter: DonationFilter, page: DonationPage, signer_address: Addr) -> Result<MultiDonationResponse, ContractError> {
+    let recipient_user = find_alpine_username(deps.storage, recipient)?;
 
     // Validate that permit signer is the same as the queried address
-    if signer_address != deps.api.addr_canonicalize(sender_user.address.as_str())? {
-        return Err(StdError::GenericErr { msg: "Address mismatch".to_string() });
+    if signer_address != recipient_user.address {
+        return Err(ContractError::Unauthorized {  });
     }
 
-    let mut sent_donations: Vec<DonationInfo> = vec![];
+    let index = donations_by_recipient();
+    let bounds = index.bounds(deps.storage, &recipient_user.address);
+    let id_at = |position| index.get(deps.storage, &recipient_user.address, position);
+    let positions = filter_positions(deps.storage, bounds.start..bounds.end, &id_at, &filter);
+    let (donations, next) = page_donations(deps.storage, positions, id_at, page, |donation| {
+        donation.recipient == recipient_user
+            && matches_filter(donation, &donation.sender, &filter)
+            && (!filter.unread_only || is_unread(deps.storage, &recipient_user.address, donation.id))
+    });
 
-    for donation in state.donations {
-        if donation.sender == sender_user {
-            sent_donations.append(&mut vec![donation]);
+    Ok(MultiDonationResponse { donations: donations.into_iter().map(hide_anonymous_sender).collect(), next })
+}
+
+struct DonationPage {
+    order: DonationOrder,
+    limit: Option<u32>,
+    start_after: Option<u64>
+}
+
+// Go through the donation IDs at `positions` of an index, which ascend, from just past `start_after` in the
+// direction of the order. Stops once `limit` donations match or MAX_DONATIONS_SCANNED were looked at, and
+// returns the ID to continue after if the index goes on
+fn page_donations(
+    storage: &dyn Storage,
+    positions: Range<u64>,
+    id_at: impl Fn(u64) -> Option<u64>,
+    page: DonationPage,
+    matches: impl Fn(&DonationInfo) -> bool
+) -> (Vec<DonationInfo>, Option<u64>) {
+    let limit = page.limit.unwrap_or(DEFAULT_DONATIONS_LIMIT).clamp(1, MAX_DONATIONS_LIMIT) as usize;
+    let newest_first = page.order == DonationOrder::NewestFirst;
+    let mut positions = match (page.start_after, newest_first) {
+        (None, _) => positions,
+        (Some(after), false) => first_position(&positions, |position| !matches!(id_at(position), Some(id) if id <= after))..positions.end,
+        (Some(before), true) => positions.start..first_position(&positions, |position| !matches!(id_at(position), Some(id) if id < before))
+    };
+
+    let mut matched = vec![];
+    let mut last_id = None;
+    let mut scanned = 0;
+    while matched.len() < limit && scanned < MAX_DONATIONS_SCANNED {
+        let position = match newest_first {
+            true => positions.next_back(),
+            false => positions.next()
+        };
+        let id = match position.and_then(&id_at) {
+            Some(id) => id,
+            None => break
+        };
+        scanned += 1;
+        last_id = Some(id);
+        if let Some(donation) = donations().get(storage, &id) {
+            if matches(&donation) {
+                matched.push(donation);
+            }
         }
     }
 
-    Ok(MultiDonationResponse { donations: sent_donations })
+    let next = match positions.is_empty() {
+        true => None,
+        false => last_id
+    };
+    (matched, next)
 }
 
-fn get_received_donations(deps: Deps, recipient: String, signer_address: CanonicalAddr) -> StdResult<MultiDonationResponse> {
-    let state = read_state(deps.storage).load()?;
-    let recipient_user = find_alpine_username(deps.storage, recipient).unwrap();
+// Narrow `positions` of an index down to the donations within the filter's block and time range. Donations are
+// indexed as they're recorded, so their heights and timestamps rise with their position. Donations recorded
+// before these were kept count as the oldest
+fn filter_positions(storage: &dyn Storage, positions: Range<u64>, id_at: &impl Fn(u64) -> Option<u64>, filter: &DonationFilter) -> Range<u64> {
+    if filter.from_height.is_none() && filter.to_height.is_none() && filter.from.is_none() && filter.to.is_none() {
+        return positions
+    }
 
-    // Validate that permit signer is the same as the queried address
-    if signer_address != deps.api.addr_canonicalize(recipient_user.address.as_str())? {
-        return Err(StdError::GenericErr { msg: "Address mismatch".to_string() });
+    let donation_at = |position| id_at(position).and_then(|id| donations().get(storage, &id));
+    let reached_start = |donation: &DonationInfo| {
+        !matches!(filter.from_height, Some(from) if donation.block_height.unwrap_or_default() < from)
+            && !matches!(filter.from, Some(from) if donation.timestamp.unwrap_or_default() < from)
+    };
+    let past_end = |donation: &DonationInfo| {
+        matches!(filter.to_height, Some(to) if donation.block_height.unwrap_or_default() > to)
+            || matches!(filter.to, Some(to) if donation.timestamp.unwrap_or_default() > to)
+    };
+
+    let start = first_position(&positions, |position| match donation_at(position) {
+        Some(donation) => reached_start(&donation),
+        None => true
+    });
+    let end = first_position(&(start..positions.end), |position| match donation_at(position) {
+        Some(donation) => past_end(&donation),
+        None => true
+    });
+    start..end
+}
+
+// The first of `positions` which is past a point, found by a binary search since everything after it is too
+fn first_position(positions: &Range<u64>, is_past: impl Fn(u64) -> bool) -> u64 {
+    let (mut low, mut high) = (positions.start, positions.end);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        match is_past(middle) {
+            true => high = middle,
+            false => low = middle + 1
+        }
+    }
+    low
+}
+
+// Look up one donation. Recipients don't see who sent anonymous donations
+fn get_donation(deps: Deps, id: u64, signer_address: Addr) -> Result<DonationResponse, ContractError> {
+    let donation = match donations().get(deps.storage, &id) {
+        Some(donation) => donation,
+        None => return Err(ContractError::DonationNotFound { id })
+    };
+
+    if donation.sender.address == signer_address {
+        Ok(DonationResponse { donation })
+    } else if donation.recipient.address == signer_address {
+        Ok(DonationResponse { donation: hide_anonymous_sender(donation) })
+    } else {
+        Err(ContractError::Unauthorized {  })
     }
+}
 
-    let mut received_donations: Vec<DonationInfo> = vec![];
+// Donations sent without a username only show their sender to the sender themself
+fn hide_anonymous_sender(mut donation: DonationInfo) -> DonationInfo {
+    if donation.sender.username.is_empty() {
+        donation.sender = AlpineUser::empty();
+    }
+    donation
+}
 
-    for donation in state.donations {
-        if donation.recipient == recipient_user {
-            received_donations.append(&mut vec![donation]);
+// Donations recorded before block heights were stored don't match a block range. Anonymous donations never
+// match a counterparty
+fn matches_filter(donation: &DonationInfo, counterparty: &AlpineUser, filter: &DonationFilter) -> bool {
+    let in_block_range = match donation.block_height {
+        Some(height) => !matches!(filter.from_height, Some(from) if height < from) && !matches!(filter.to_height, Some(to) if height > to),
+        None => filter.from_height.is_none() && filter.to_height.is_none()
+    };
+    let in_time_range = match donation.timestamp {
+        Some(time) => !matches!(filter.from, Some(from) if time < from) && !matches!(filter.to, Some(to) if time > to),
+        None => filter.from.is_none() && filter.to.is_none()
+    };
+    let amount = &donation.amount[0];
+    in_block_range
+        && in_time_range
+        && !matches!(&filter.denom, Some(denom) if denom != &amount.denom)
+        && !matches!(filter.min_amount, Some(min_amount) if amount.amount < min_amount)
+        && !matches!(&filter.counterparty, Some(username) if counterparty.username.is_empty() || username != &counterparty.username)
+}
+
+// Look up the user who signed the permit, along with their private settings
+fn who_am_i(deps: Deps, signer_address: Addr) -> Result<WhoAmIResponse, ContractError> {
+    let user = get_user_by_address(deps.storage, signer_address)?;
+    let settings = load_user_settings(deps.storage, &user.address);
+    let social_handles = user_social_handles().get(deps.storage, &user.address).unwrap_or_default();
+    let payout_split = load_payout_split(deps.storage, &user.address);
+
+    Ok(WhoAmIResponse { user, settings, social_handles, payout_split })
+}
+
+// Get the donations the permit signer sent which are still held in escrow
+fn get_escrowed_donations(deps: Deps, signer_address: Addr) -> Result<EscrowedDonationsResponse, ContractError> {
+    let mut pending = vec![];
+    for id in escrows_by_sender().get(deps.storage, &signer_address).unwrap_or_default() {
+        if let Some(escrow) = escrows().get(deps.storage, &id) {
+            pending.push(escrow);
         }
     }
+    pending.sort_by_key(|escrow| escrow.id);
 
-    Ok(MultiDonationResponse { donations: received_donations })
+    Ok(EscrowedDonationsResponse { escrows: pending })
 }
 
-fn is_username_available(deps: Deps, username: String) -> StdResult<UsernameAvailableResponse> {
-    let is_available = !contains_username(deps.storage, username).unwrap();
-    Ok(UsernameAvailableResponse { is_available })
+// Get the donations held for the permit signer which they haven't withdrawn yet
+fn get_balances(deps: Deps, signer_address: Addr) -> BalancesResponse {
+    BalancesResponse { balances: load_balances(deps.storage, &signer_address) }
 }
 
-fn get_all_users(deps: Deps) -> StdResult<MultiUserResponse> {
+// Get the fees accrued since the last withdrawal, and who they'll be paid out to. Only the owner can see this
+fn get_treasury(deps: Deps, signer_address: Addr) -> Result<TreasuryResponse, ContractError> {
     let state = read_state(deps.storage).load()?;
-    Ok(MultiUserResponse { users: state.users })
+    if signer_address.as_str() != state.owner {
+        return Err(ContractError::Unauthorized {  })
+    }
+
+    Ok(TreasuryResponse { fees: state.fee_treasury, team_wallets: state.team_wallets })
+}
+
+// Get the active subscriptions the permit signer is paying for
+fn get_subscriptions(deps: Deps, signer_address: Addr) -> SubscriptionsResponse {
+    let ids = subscriptions_by_subscriber().get(deps.storage, &signer_address).unwrap_or_default();
+    SubscriptionsResponse { subscriptions: load_subscriptions(deps.storage, ids) }
+}
+
+// Get the active subscriptions paying out to the permit signer
+fn get_subscribers(deps: Deps, signer_address: Addr) -> SubscriptionsResponse {
+    let ids = subscriptions_by_creator().get(deps.storage, &signer_address).unwrap_or_default();
+    SubscriptionsResponse { subscriptions: load_subscriptions(deps.storage, ids) }
 }
 
-fn get_user_by_addr(deps: Deps, address: Addr) -> StdResult<AlpineUserResponse>{
-    match get_user_by_address(deps.storage, address) {
-        Ok(user) => Ok(AlpineUserResponse { user }),
-        Err(e) => Err(StdError::GenericErr { msg: e.to_string() })
+fn get_creator_tiers(deps: Deps, creator: String) -> Result<CreatorTiersResponse, ContractError> {
+    let creator = find_alpine_username(deps.storage, creator)?;
+    let creator_tiers = load_creator_tiers(deps.storage, &creator.address);
+    Ok(CreatorTiersResponse { tiers: creator_tiers.tiers, window: creator_tiers.window })
+}
+
+// Only aggregates are published, and only for creators who opted in and had at least the configured number of
+// distinct donors this week, so that no single donation can be picked out
+fn get_leaderboard(deps: Deps, env: Env, limit: Option<u32>) -> Result<LeaderboardResponse, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_LEADERBOARD_LIMIT).min(MAX_LEADERBOARD_LIMIT) as usize;
+    let state = read_state(deps.storage).load()?;
+    let min_donors = state.leaderboard_min_donors as usize;
+    let week = week_index(env.block.time);
+    // Looked up once, rather than going through every user for each creator
+    let usernames: HashMap<Addr, String> = state.users.into_iter().map(|user| (user.address, user.username)).collect();
+
+    let mut creators = vec![];
+    for address in load_leaderboard_creators(deps.storage)? {
+        let weekly = match weekly_stats().get(deps.storage, &address) {
+            Some(weekly) if weekly.week == week && weekly.donors.len() >= min_donors => weekly,
+            _ => continue
+        };
+        let received_at_least = weekly.received.into_iter()
+            .map(|coin| Coin { denom: coin.denom, amount: round_down_to_power_of_ten(coin.amount) })
+            .collect();
+        let username = match usernames.get(&address) {
+            Some(username) => username.clone(),
+            None => return Err(ContractError::UserNotFound { user: address.to_string() })
+        };
+        creators.push(LeaderboardEntry { username, donation_count: weekly.donation_count, received_at_least });
+    }
+    creators.sort_by(|a, b| b.donation_count.cmp(&a.donation_count).then_with(|| a.username.cmp(&b.username)));
+    creators.truncate(limit);
+    Ok(LeaderboardResponse { creators })
+}
+
+fn round_down_to_power_of_ten(amount: Uint128) -> Uint128 {
+    let mut power = 1u128;
+    while amount.u128() / power >= 10 {
+        power *= 10;
+    }
+    match amount.is_zero() {
+        true => amount,
+        false => Uint128::from(power)
     }
 }
 
-fn get_user_by_name(deps: Deps, username: String) -> StdResult<AlpineUserResponse> {
-    let user = match find_alpine_username(deps.storage, username.clone()) {
-        Ok(user) => { user },
-        Err(_) => { AlpineUser::empty() }
+// Public so that donors can check where part of their donation will go before they send it
+fn get_charity_pledge(deps: Deps, creator: String) -> Result<CharityPledgeResponse, ContractError> {
+    let creator = find_alpine_username(deps.storage, creator)?;
+    Ok(CharityPledgeResponse { pledge: charity_pledges().get(deps.storage, &creator.address) })
+}
+
+// The expiry is only set when the recipient has a fee override
+fn get_fee_rate(deps: Deps, env: Env, recipient: String, denom: String) -> Result<FeeRateResponse, ContractError> {
+    let recipient = find_alpine_username(deps.storage, recipient)?;
+    let fee = match load_fee_override(deps.storage, &recipient.address, env.block.time) {
+        Some(fee_override) => fee_override,
+        None => {
+            let volume = load_received_volume(deps.storage, &recipient.address, &denom);
+            let basis_points = read_state(deps.storage).load()?.fee_schedule.rate(&denom, volume);
+            FeeOverride { basis_points, expires: None }
+        }
     };
+    Ok(FeeRateResponse { fee })
+}
+
+// Find the highest tier of a creator which the permit signer has reached within the creator's window
+fn get_supporter_tier(deps: Deps, env: Env, creator: String, signer_address: Addr) -> Result<SupporterTierResponse, ContractError> {
+    let creator = find_alpine_username(deps.storage, creator)?;
+    let creator_tiers = load_creator_tiers(deps.storage, &creator.address);
+    let support = recent_support(deps.storage, &signer_address, &creator.address, creator_tiers.window, env.block.time)?;
+
+    let tier = creator_tiers.tiers.into_iter().rev().find(|tier| {
+        support.iter().any(|coin| coin.denom == tier.denom && coin.amount >= tier.amount)
+    });
+
+    Ok(SupporterTierResponse { supporter: signer_address, tier })
+}
+
+fn campaign_response(campaign: Campaign) -> CampaignResponse {
+    let progress_percent = campaign.raised.multiply_ratio(100u128, campaign.target.amount).u128() as u64;
+    CampaignResponse {
+        id: campaign.id,
+        creator: campaign.creator.username,
+        description: campaign.description,
+        target: campaign.target,
+        deadline: campaign.deadline,
+        raised: campaign.raised,
+        progress_percent,
+        donation_count: campaign.donation_count,
+        all_or_nothing: campaign.all_or_nothing,
+        claimed: campaign.claimed
+    }
+}
+
+fn get_campaign(deps: Deps, id: u64) -> Result<CampaignResponse, ContractError> {
+    match campaigns().get(deps.storage, &id) {
+        Some(campaign) => Ok(campaign_response(campaign)),
+        None => Err(ContractError::CampaignNotFound { id })
+    }
+}
+
+fn get_campaigns(deps: Deps, creator: String) -> Result<CampaignsResponse, ContractError> {
+    let creator = find_alpine_username(deps.storage, creator)?;
+    let ids = campaigns_by_creator().get(deps.storage, &creator.address).unwrap_or_default();
+    let campaigns = ids.into_iter()
+        .filter_map(|id| campaigns().get(deps.storage, &id))
+        .map(campaign_response)
+        .collect();
+    Ok(CampaignsResponse { campaigns })
+}
+
+fn is_username_available(deps: Deps, username: String) -> Result<UsernameAvailableResponse, ContractError> {
+    let is_available = !contains_username(deps.storage, username)?;
+    Ok(UsernameAvailableResponse { is_available })
+}
+
+// Find discoverable users whose username starts with the prefix, ignoring case. Results are ordered by
+// username, and paginated by passing the last username of the previous page as `start_after`
+fn search_users(deps: Deps, prefix: String, limit: Option<u32>, start_after: Option<String>) -> Result<MultiUserResponse, ContractError> {
+    let prefix = prefix.to_lowercase();
+    if prefix.is_empty() {
+        return Err(ContractError::EmptySearchPrefix {  })
+    }
+    let limit = limit.unwrap_or(DEFAULT_SEARCH_LIMIT).min(MAX_SEARCH_LIMIT) as usize;
+    let start_after = start_after.map(|username| username.to_lowercase());
+    let users = search_directory(deps.storage, &prefix, start_after.as_deref(), limit);
+
+    Ok(MultiUserResponse { users })
+}
+
+fn get_user_by_addr(deps: Deps, address: Addr) -> Result<AlpineUserResponse, ContractError> {
+    let state = read_state(deps.storage).load()?;
+    if state.disable_address_lookup {
+        return Err(ContractError::AddressLookupDisabled {  })
+    }
+
+    let user = get_user_by_address(deps.storage, address)?;
+    Ok(AlpineUserResponse { user })
+}
 
+fn get_user_by_name(deps: Deps, username: String) -> Result<AlpineUserResponse, ContractError> {
+    let user = find_alpine_username(deps.storage, username)?;
     Ok(AlpineUserResponse { user })
 }
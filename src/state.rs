@@ -1,29 +1,494 @@
-use cosmwasm_std::{Addr, Timestamp, Deps, Storage, StdResult};
+use cosmwasm_std::{Addr, Coin, Timestamp, Deps, Storage, StdResult, Uint128};
 use cosmwasm_storage::{Singleton, singleton, ReadonlySingleton, singleton_read};
 use schemars::JsonSchema;
-use serde::{Serialize, Deserialize};
+use secret_toolkit_serialization::Json;
+use secret_toolkit_storage::{Keymap, KeymapBuilder, WithoutIter};
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
 
 use crate::error::ContractError;
+use crate::fee::FeeSchedule;
 
 const STATE_KEY: &[u8] = b"state";
+const USERNAME_SKELETONS_KEY: &[u8] = b"username_skeletons";
+const USER_SETTINGS_KEY: &[u8] = b"user_settings";
+const DIRECTORY_KEY: &[u8] = b"directory";
+const SOCIAL_HANDLES_KEY: &[u8] = b"social_handles";
+const USER_SOCIAL_HANDLES_KEY: &[u8] = b"user_social_handles";
+const ESCROWS_KEY: &[u8] = b"escrows";
+const ESCROWS_BY_TARGET_KEY: &[u8] = b"escrows_by_target";
+const ESCROWS_BY_TARGET_ENTRIES_KEY: &[u8] = b"escrows_by_target_entries";
+const ESCROWS_BY_SENDER_KEY: &[u8] = b"escrows_by_sender";
+const BALANCES_KEY: &[u8] = b"balances";
+const SUBSCRIPTIONS_KEY: &[u8] = b"subscriptions";
+const SUBSCRIPTIONS_BY_SUBSCRIBER_KEY: &[u8] = b"subscriptions_by_subscriber";
+const SUBSCRIPTIONS_BY_CREATOR_KEY: &[u8] = b"subscriptions_by_creator";
+const CREATOR_TIERS_KEY: &[u8] = b"creator_tiers";
+const CAMPAIGNS_KEY: &[u8] = b"campaigns";
+const CAMPAIGNS_BY_CREATOR_KEY: &[u8] = b"campaigns_by_creator";
+const CAMPAIGN_PLEDGES_KEY: &[u8] = b"campaign_pledges";
+const CAMPAIGN_REFUNDS_KEY: &[u8] = b"campaign_refunds";
+const PAYOUT_SPLITS_KEY: &[u8] = b"payout_splits";
+const CHARITY_PLEDGES_KEY: &[u8] = b"charity_pledges";
+const FEE_OVERRIDES_KEY: &[u8] = b"fee_overrides";
+const USER_STATS_KEY: &[u8] = b"user_stats";
+const SUPPORTER_TOTALS_KEY: &[u8] = b"supporter_totals";
+const TOP_SUPPORTERS_KEY: &[u8] = b"top_supporters";
+const WEEKLY_STATS_KEY: &[u8] = b"weekly_stats";
+const LEADERBOARD_CREATORS_KEY: &[u8] = b"leaderboard_creators";
+const PLATFORM_STATS_KEY: &[u8] = b"platform_stats";
+const CREATOR_LAST_ACTIVE_KEY: &[u8] = b"creator_last_active";
+const ACTIVE_CREATORS_BY_DAY_KEY: &[u8] = b"active_creators_by_day";
+const DONATIONS_KEY: &[u8] = b"donations";
+const DONATIONS_BY_SENDER_KEY: &[u8] = b"donations_by_sender";
+const DONATIONS_BY_SENDER_ENTRIES_KEY: &[u8] = b"donations_by_sender_entries";
+const DONATIONS_BY_RECIPIENT_KEY: &[u8] = b"donations_by_recipient";
+const DONATIONS_BY_RECIPIENT_ENTRIES_KEY: &[u8] = b"donations_by_recipient_entries";
+const UNREAD_DONATIONS_KEY: &[u8] = b"unread_donations";
+const UNREAD_COUNTS_KEY: &[u8] = b"unread_counts";
+
+// Where donation fees are paid out to until the owner configures the team wallets
+pub const DEFAULT_FEE_ADDRESS: &str = "secret1225mx24unphqw8sdwndz8jmplmuzxg74dzjg0c";
+
+// Subscriptions are paid out monthly unless the subscriber picks another period
+pub const DEFAULT_SUBSCRIPTION_PERIOD: u64 = 30 * 24 * 60 * 60;
+
+// Supporter tiers are based on the last 30 days of support unless the creator configures otherwise
+pub const DEFAULT_TIER_WINDOW: u64 = 30 * 24 * 60 * 60;
+
+// Creators are only shown on the public leaderboard once at least this many people have donated to them in a week,
+// unless the owner configures otherwise
+pub const DEFAULT_LEADERBOARD_MIN_DONORS: u32 = 5;
+
+// Escrowed donations can be reclaimed by their sender after 30 days, unless the owner configures otherwise
+pub const DEFAULT_ESCROW_TIMEOUT: u64 = 30 * 24 * 60 * 60;
+
+// How many of a creator's top supporters are kept in each denom
+pub const MAX_TOP_SUPPORTERS: usize = 50;
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct State{
     pub donation_count: u64,
     pub users: Vec<AlpineUser>,
+    // Donations recorded before they were stored by ID. Migrating moves them into the donation indexes
+    #[serde(default)]
     pub donations: Vec<DonationInfo>,
     pub contract_address: String,
-    pub owner: String
+    pub owner: String,
+    // When set, users can't be looked up by wallet address through the public queries
+    #[serde(default)]
+    pub disable_address_lookup: bool,
+    #[serde(default)]
+    pub escrow_count: u64,
+    #[serde(default)]
+    pub subscription_count: u64,
+    #[serde(default)]
+    pub campaign_count: u64,
+    // Seconds after which the sender of an unclaimed escrowed donation can reclaim it
+    #[serde(default = "default_escrow_timeout")]
+    pub escrow_timeout: u64,
+    // Donation fees which haven't been withdrawn yet. Holds at most one coin per denom
+    #[serde(default)]
+    pub fee_treasury: Vec<Coin>,
+    // The wallets which withdrawn fees are split between
+    #[serde(default = "default_team_wallets")]
+    pub team_wallets: Vec<TeamWallet>,
+    #[serde(default)]
+    pub fee_schedule: FeeSchedule,
+    // How many distinct donors a creator needs in a week to be shown on the public leaderboard
+    #[serde(default = "default_leaderboard_min_donors")]
+    pub leaderboard_min_donors: u32
+}
+
+fn default_escrow_timeout() -> u64 {
+    DEFAULT_ESCROW_TIMEOUT
+}
+
+fn default_leaderboard_min_donors() -> u32 {
+    DEFAULT_LEADERBOARD_MIN_DONORS
+}
+
+pub fn default_team_wallets() -> Vec<TeamWallet> {
+    vec![TeamWallet { address: Addr::unchecked(DEFAULT_FEE_ADDRESS), weight: 1 }]
 }
 
-pub fn update_state(storage: &mut dyn Storage) -> Singleton<State> {
+// A wallet which receives a share of the withdrawn fees, proportional to its weight
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TeamWallet {
+    pub address: Addr,
+    pub weight: u32
+}
+
+pub fn update_state(storage: &mut dyn Storage) -> Singleton<'_, State> {
     singleton(storage, STATE_KEY)
 }
 
-pub fn read_state(storage: &dyn Storage) -> ReadonlySingleton<State> {
+pub fn read_state(storage: &dyn Storage) -> ReadonlySingleton<'_, State> {
     singleton_read(storage, STATE_KEY)
 }
 
+// Keymap which only supports point lookups. Iterable keymaps and keysets in secret-toolkit-storage 0.7 leave the
+// stored value behind when the most recently inserted key is removed, so anything which needs to forget keys uses
+// this instead, and keeps a `Vec` under one key when it needs to list entries.
+// Values are stored as JSON like the state, so that new fields can be added with `#[serde(default)]`
+pub type LookupMap<'a, K, T> = Keymap<'a, K, T, Json, WithoutIter>;
+
+// Index of username skeletons to the address which registered them. Used to enforce uniqueness
+pub fn username_skeletons<'a>() -> LookupMap<'a, String, Addr> {
+    KeymapBuilder::new(USERNAME_SKELETONS_KEY).without_iter().build()
+}
+
+// Per-user settings, keyed by wallet address
+pub fn user_settings<'a>() -> LookupMap<'a, Addr, UserSettings> {
+    KeymapBuilder::new(USER_SETTINGS_KEY).without_iter().build()
+}
+
+// Directory of users who opted into search, as a trie keyed by prefixes of lowercased usernames. A search only
+// reads the nodes on the way to its prefix and to the users it returns
+fn directory<'a>() -> LookupMap<'a, String, DirectoryNode> {
+    KeymapBuilder::new(DIRECTORY_KEY).without_iter().build()
+}
+
+// The listed user whose lowercased username is the node's key, if any, and the characters which continue it
+// into longer listed usernames, in order. Nodes are removed once no listed username starts with their key
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+struct DirectoryNode {
+    user: Option<AlpineUser>,
+    children: String
+}
+
+fn list_user(storage: &mut dyn Storage, user: &AlpineUser) -> StdResult<()> {
+    let name = user.username.to_lowercase();
+    let mut key = String::new();
+    for c in name.chars() {
+        let mut node = directory().get(storage, &key).unwrap_or_default();
+        if !node.children.contains(c) {
+            let mut children: Vec<char> = node.children.chars().chain([c]).collect();
+            children.sort_unstable();
+            node.children = children.into_iter().collect();
+            directory().insert(storage, &key, &node)?;
+        }
+        key.push(c);
+    }
+    let mut node = directory().get(storage, &name).unwrap_or_default();
+    node.user = Some(user.clone());
+    directory().insert(storage, &name, &node)
+}
+
+fn unlist_user(storage: &mut dyn Storage, user: &AlpineUser) -> StdResult<()> {
+    let mut key = user.username.to_lowercase();
+    let mut node = match directory().get(storage, &key) {
+        Some(node) => node,
+        None => return Ok(())
+    };
+    node.user = None;
+    // Work back up the trie, removing the nodes which no longer lead to anyone
+    loop {
+        if node.user.is_some() || !node.children.is_empty() {
+            return directory().insert(storage, &key, &node)
+        }
+        directory().remove(storage, &key)?;
+        let c = match key.pop() {
+            Some(c) => c,
+            None => return Ok(())
+        };
+        node = directory().get(storage, &key).unwrap_or_default();
+        node.children = node.children.chars().filter(|child| *child != c).collect();
+    }
+}
+
+// Up to `limit` listed users whose lowercased username starts with `prefix` and comes after `start_after`, in order
+pub fn search_directory(storage: &dyn Storage, prefix: &str, start_after: Option<&str>, limit: usize) -> Vec<AlpineUser> {
+    let mut users = vec![];
+    let mut stack = vec![prefix.to_string()];
+    while let Some(key) = stack.pop() {
+        if users.len() >= limit {
+            break
+        }
+        // Every username under a key which comes before `start_after`, without being the start of it, does too
+        if matches!(start_after, Some(after) if key.as_str() < after && !after.starts_with(&key)) {
+            continue
+        }
+        let node = match directory().get(storage, &key) {
+            Some(node) => node,
+            None => continue
+        };
+        if let Some(user) = node.user {
+            if !matches!(start_after, Some(after) if key.as_str() <= after) {
+                users.push(user);
+            }
+        }
+        for c in node.children.chars().rev() {
+            let mut child = key.clone();
+            child.push(c);
+            stack.push(child);
+        }
+    }
+    users
+}
+
+// Verified social handles ("platform:handle") mapped to the address of the user who owns them
+pub fn social_handles<'a>() -> LookupMap<'a, String, Addr> {
+    KeymapBuilder::new(SOCIAL_HANDLES_KEY).without_iter().build()
+}
+
+// The verified social handles of each user
+pub fn user_social_handles<'a>() -> LookupMap<'a, Addr, Vec<String>> {
+    KeymapBuilder::new(USER_SOCIAL_HANDLES_KEY).without_iter().build()
+}
+
+// Donations held by the contract until their recipient registers, keyed by escrow ID
+pub fn escrows<'a>() -> LookupMap<'a, u64, EscrowedDonation> {
+    KeymapBuilder::new(ESCROWS_KEY).without_iter().build()
+}
+
+// IDs of the escrowed donations waiting for a username or social handle, oldest first. Reclaimed escrows are
+// left in the list and skipped once they reach the front
+pub fn escrows_by_target<'a>() -> IdList<'a, String> {
+    IdList::new(ESCROWS_BY_TARGET_KEY, ESCROWS_BY_TARGET_ENTRIES_KEY)
+}
+
+// IDs of the escrowed donations sent from an address which haven't been released or reclaimed
+pub fn escrows_by_sender<'a>() -> LookupMap<'a, Addr, Vec<u64>> {
+    KeymapBuilder::new(ESCROWS_BY_SENDER_KEY).without_iter().build()
+}
+
+// Donations held for users who claim them instead of having them forwarded, keyed by wallet address.
+// Holds at most one coin per denom
+pub fn balances<'a>() -> LookupMap<'a, Addr, Vec<Coin>> {
+    KeymapBuilder::new(BALANCES_KEY).without_iter().build()
+}
+
+// Prepaid subscriptions, keyed by subscription ID
+pub fn subscriptions<'a>() -> LookupMap<'a, u64, Subscription> {
+    KeymapBuilder::new(SUBSCRIPTIONS_KEY).without_iter().build()
+}
+
+// IDs of the active subscriptions paid for by an address
+pub fn subscriptions_by_subscriber<'a>() -> LookupMap<'a, Addr, Vec<u64>> {
+    KeymapBuilder::new(SUBSCRIPTIONS_BY_SUBSCRIBER_KEY).without_iter().build()
+}
+
+// IDs of the active subscriptions paying out to an address
+pub fn subscriptions_by_creator<'a>() -> LookupMap<'a, Addr, Vec<u64>> {
+    KeymapBuilder::new(SUBSCRIPTIONS_BY_CREATOR_KEY).without_iter().build()
+}
+
+// The membership tiers defined by each creator
+pub fn creator_tiers<'a>() -> LookupMap<'a, Addr, CreatorTiers> {
+    KeymapBuilder::new(CREATOR_TIERS_KEY).without_iter().build()
+}
+
+// Fundraising campaigns, keyed by campaign ID
+pub fn campaigns<'a>() -> LookupMap<'a, u64, Campaign> {
+    KeymapBuilder::new(CAMPAIGNS_KEY).without_iter().build()
+}
+
+// IDs of the campaigns created by an address
+pub fn campaigns_by_creator<'a>() -> LookupMap<'a, Addr, Vec<u64>> {
+    KeymapBuilder::new(CAMPAIGNS_BY_CREATOR_KEY).without_iter().build()
+}
+
+// Donations held for all-or-nothing campaigns until the creator claims them, keyed by campaign ID and the order
+// they were pledged in. They're recorded as donations once they're claimed
+pub fn campaign_pledges<'a>() -> LookupMap<'a, (u64, u64), DonationInfo> {
+    KeymapBuilder::new(CAMPAIGN_PLEDGES_KEY).without_iter().build()
+}
+
+// What each donor has pledged to an all-or-nothing campaign, keyed by campaign ID and donor address, so that a
+// refund doesn't have to go through every pledge. Holds at most one coin per denom
+pub fn campaign_refunds<'a>() -> LookupMap<'a, (u64, Addr), Vec<Coin>> {
+    KeymapBuilder::new(CAMPAIGN_REFUNDS_KEY).without_iter().build()
+}
+
+// How each user's donations are split between their collaborators. Users without a split receive everything
+pub fn payout_splits<'a>() -> LookupMap<'a, Addr, Vec<PayoutShare>> {
+    KeymapBuilder::new(PAYOUT_SPLITS_KEY).without_iter().build()
+}
+
+// The share of each user's donations which is passed on to a charity. Users without a pledge keep everything
+pub fn charity_pledges<'a>() -> LookupMap<'a, Addr, CharityPledge> {
+    KeymapBuilder::new(CHARITY_PLEDGES_KEY).without_iter().build()
+}
+
+// Every recorded donation, keyed by donation ID
+pub fn donations<'a>() -> LookupMap<'a, u64, DonationInfo> {
+    KeymapBuilder::new(DONATIONS_KEY).without_iter().build()
+}
+
+// IDs of the donations sent from each address, oldest first
+pub fn donations_by_sender<'a>() -> IdList<'a, Addr> {
+    IdList::new(DONATIONS_BY_SENDER_KEY, DONATIONS_BY_SENDER_ENTRIES_KEY)
+}
+
+// IDs of the donations received by each address, oldest first
+pub fn donations_by_recipient<'a>() -> IdList<'a, Addr> {
+    IdList::new(DONATIONS_BY_RECIPIENT_KEY, DONATIONS_BY_RECIPIENT_ENTRIES_KEY)
+}
+
+// The donations each address has received but not acknowledged yet, keyed by (recipient, donation ID)
+pub fn unread_donations<'a>() -> LookupMap<'a, (Addr, u64), bool> {
+    KeymapBuilder::new(UNREAD_DONATIONS_KEY).without_iter().build()
+}
+
+// How many donations each address has received but not acknowledged yet
+pub fn unread_counts<'a>() -> LookupMap<'a, Addr, u64> {
+    KeymapBuilder::new(UNREAD_COUNTS_KEY).without_iter().build()
+}
+
+pub fn is_unread(storage: &dyn Storage, recipient: &Addr, id: u64) -> bool {
+    unread_donations().contains(storage, &(recipient.clone(), id))
+}
+
+pub fn load_unread_count(storage: &dyn Storage, recipient: &Addr) -> u64 {
+    unread_counts().get(storage, recipient).unwrap_or_default()
+}
+
+fn mark_donation_unread(storage: &mut dyn Storage, recipient: &Addr, id: u64) -> StdResult<()> {
+    unread_donations().insert(storage, &(recipient.clone(), id), &true)?;
+    let count = load_unread_count(storage, recipient) + 1;
+    unread_counts().insert(storage, recipient, &count)
+}
+
+// Mark donations as read by their recipient. Returns how many are still unread
+pub fn mark_donations_read(storage: &mut dyn Storage, recipient: &Addr, ids: &[u64]) -> StdResult<u64> {
+    let mut count = load_unread_count(storage, recipient);
+    for id in ids {
+        if is_unread(storage, recipient, *id) {
+            unread_donations().remove(storage, &(recipient.clone(), *id))?;
+            count -= 1;
+        }
+    }
+    match count {
+        0 if unread_counts().contains(storage, recipient) => unread_counts().remove(storage, recipient)?,
+        0 => {},
+        _ => unread_counts().insert(storage, recipient, &count)?
+    }
+    Ok(count)
+}
+
+// Donation fees agreed by the owner with particular recipients, such as charities and partner creators
+pub fn fee_overrides<'a>() -> LookupMap<'a, Addr, FeeOverride> {
+    KeymapBuilder::new(FEE_OVERRIDES_KEY).without_iter().build()
+}
+
+// Running totals of the donations each user has received, keyed by wallet address
+pub fn user_stats<'a>() -> LookupMap<'a, Addr, UserStats> {
+    KeymapBuilder::new(USER_STATS_KEY).without_iter().build()
+}
+
+// What each supporter has given each creator, keyed by (creator, supporter)
+pub fn supporter_totals<'a>() -> LookupMap<'a, (Addr, SupporterKey), SupporterTotal> {
+    KeymapBuilder::new(SUPPORTER_TOTALS_KEY).without_iter().build()
+}
+
+// What each user has received in the current week, keyed by wallet address
+pub fn weekly_stats<'a>() -> LookupMap<'a, Addr, WeeklyStats> {
+    KeymapBuilder::new(WEEKLY_STATS_KEY).without_iter().build()
+}
+
+// The supporters who have given each creator the most of a denom, keyed by (creator, denom). Holds at most
+// MAX_TOP_SUPPORTERS, largest first
+pub fn top_supporters<'a>() -> LookupMap<'a, (Addr, String), Vec<RankedSupporter>> {
+    KeymapBuilder::new(TOP_SUPPORTERS_KEY).without_iter().build()
+}
+
+// Add an ID to the list of IDs stored under a key
+fn add_to_index<K: Serialize + DeserializeOwned>(storage: &mut dyn Storage, index: LookupMap<K, Vec<u64>>, key: &K, id: u64) -> StdResult<()> {
+    let mut ids = index.get(storage, key).unwrap_or_default();
+    ids.push(id);
+    index.insert(storage, key, &ids)
+}
+
+// A list of IDs under each key, stored one ID per entry so that adding to a long list doesn't read or rewrite the
+// rest of it. IDs are added at the end and taken off the front, and keep their position in between
+pub struct IdList<'a, K: Serialize + DeserializeOwned> {
+    bounds: LookupMap<'a, K, IdListBounds>,
+    entries: LookupMap<'a, (K, u64), u64>
+}
+
+// The positions of the IDs in a list, from `start` up to but not including `end`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct IdListBounds {
+    pub start: u64,
+    pub end: u64
+}
+
+impl IdListBounds {
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+impl<'a, K: Serialize + DeserializeOwned + Clone> IdList<'a, K> {
+    fn new(bounds_namespace: &'a [u8], entries_namespace: &'a [u8]) -> Self {
+        IdList {
+            bounds: KeymapBuilder::new(bounds_namespace).without_iter().build(),
+            entries: KeymapBuilder::new(entries_namespace).without_iter().build()
+        }
+    }
+
+    pub fn bounds(&self, storage: &dyn Storage, key: &K) -> IdListBounds {
+        self.bounds.get(storage, key).unwrap_or_default()
+    }
+
+    pub fn get(&self, storage: &dyn Storage, key: &K, position: u64) -> Option<u64> {
+        self.entries.get(storage, &(key.clone(), position))
+    }
+
+    pub fn push(&self, storage: &mut dyn Storage, key: &K, id: u64) -> StdResult<()> {
+        let mut bounds = self.bounds(storage, key);
+        self.entries.insert(storage, &(key.clone(), bounds.end), &id)?;
+        bounds.end += 1;
+        self.bounds.insert(storage, key, &bounds)
+    }
+
+    // Take the first ID off the list, if there is one
+    pub fn pop_front(&self, storage: &mut dyn Storage, key: &K) -> StdResult<Option<u64>> {
+        let mut bounds = self.bounds(storage, key);
+        if bounds.is_empty() {
+            return Ok(None)
+        }
+        let id = self.get(storage, key, bounds.start);
+        self.entries.remove(storage, &(key.clone(), bounds.start))?;
+        bounds.start += 1;
+        match bounds.is_empty() {
+            true => self.bounds.remove(storage, key)?,
+            false => self.bounds.insert(storage, key, &bounds)?
+        }
+        Ok(id)
+    }
+
+    // Take the last ID off the list, if there is one
+    pub fn pop_back(&self, storage: &mut dyn Storage, key: &K) -> StdResult<Option<u64>> {
+        let mut bounds = self.bounds(storage, key);
+        if bounds.is_empty() {
+            return Ok(None)
+        }
+        bounds.end -= 1;
+        let id = self.get(storage, key, bounds.end);
+        self.entries.remove(storage, &(key.clone(), bounds.end))?;
+        match bounds.is_empty() {
+            true => self.bounds.remove(storage, key)?,
+            false => self.bounds.insert(storage, key, &bounds)?
+        }
+        Ok(id)
+    }
+}
+
+// Remove an ID from the list of IDs stored under a key, and the list itself once it's empty
+fn remove_from_index<K: Serialize + DeserializeOwned>(storage: &mut dyn Storage, index: LookupMap<K, Vec<u64>>, key: &K, id: u64) -> StdResult<()> {
+    let mut ids = index.get(storage, key).unwrap_or_default();
+    ids.retain(|existing| *existing != id);
+    match ids.is_empty() {
+        true => index.remove(storage, key),
+        false => index.insert(storage, key, &ids)
+    }
+}
+
 // Increment the number of donations. Only called during donation send
 pub fn increment_donations(storage: &mut dyn Storage) -> StdResult<u64> {
     let mut state = read_state(storage).load()?;
@@ -45,10 +510,7 @@ impl AlpineUser {
             Err(_) => return Err(ContractError::InvalidWalletAddress { address: address.to_string() })
         };
         
-        let username = match username {
-            Some(name) => name,
-            None => String::from("")
-        };
+        let username = username.unwrap_or_default();
         
         Ok(AlpineUser { username, address })
     }
@@ -58,6 +520,101 @@ impl AlpineUser {
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct UserSettings {
+    // Whether the user can be found through the user search. Off by default
+    pub discoverable: bool,
+    // Whether donations are held in the contract until the user withdraws them, instead of being forwarded
+    // to their wallet right away. Off by default
+    #[serde(default)]
+    pub claim_donations: bool,
+    // Whether the user's weekly donation counts and rounded totals can be shown on the public leaderboard.
+    // Off by default
+    #[serde(default)]
+    pub public_leaderboard: bool
+}
+
+pub fn load_user_settings(storage: &dyn Storage, address: &Addr) -> UserSettings {
+    user_settings().get(storage, address).unwrap_or_default()
+}
+
+// Save a user's settings and keep the search directory in sync with their discoverability
+pub fn save_user_settings(storage: &mut dyn Storage, user: &AlpineUser, settings: UserSettings) -> Result<UserSettings, ContractError> {
+    if load_user_settings(storage, &user.address).discoverable != settings.discoverable {
+        match settings.discoverable {
+            true => list_user(storage, user)?,
+            false => unlist_user(storage, user)?
+        }
+    }
+    let mut leaderboard_creators = load_leaderboard_creators(storage)?;
+    let listed = leaderboard_creators.contains(&user.address);
+    if listed != settings.public_leaderboard {
+        leaderboard_creators.retain(|address| address != &user.address);
+        if settings.public_leaderboard {
+            leaderboard_creators.push(user.address.clone());
+        }
+        singleton(storage, LEADERBOARD_CREATORS_KEY).save(&leaderboard_creators)?;
+    }
+    user_settings().insert(storage, &user.address, &settings)?;
+    Ok(settings)
+}
+
+// The users who opted into the public leaderboard
+pub fn load_leaderboard_creators(storage: &dyn Storage) -> StdResult<Vec<Addr>> {
+    Ok(singleton_read(storage, LEADERBOARD_CREATORS_KEY).may_load()?.unwrap_or_default())
+}
+
+pub fn load_balances(storage: &dyn Storage, address: &Addr) -> Vec<Coin> {
+    balances().get(storage, address).unwrap_or_default()
+}
+
+// Add funds to a list of coins which holds at most one coin per denom
+pub fn add_coin(coins: &mut Vec<Coin>, funds: &Coin) {
+    match coins.iter_mut().find(|coin| coin.denom == funds.denom) {
+        Some(coin) => coin.amount += funds.amount,
+        None => coins.push(funds.clone())
+    }
+}
+
+// Add funds to a user's balance
+pub fn credit_balance(storage: &mut dyn Storage, address: &Addr, funds: &Coin) -> Result<(), ContractError> {
+    let mut user_balances = load_balances(storage, address);
+    add_coin(&mut user_balances, funds);
+    balances().insert(storage, address, &user_balances)?;
+    Ok(())
+}
+
+// Add a donation fee to the treasury
+pub fn credit_fees(storage: &mut dyn Storage, fee: &Coin) -> Result<(), ContractError> {
+    if fee.amount.is_zero() {
+        return Ok(())
+    }
+    let mut state = read_state(storage).load()?;
+    add_coin(&mut state.fee_treasury, fee);
+    update_state(storage).save(&state)?;
+    Ok(())
+}
+
+// Take funds out of a user's balance. Errors out if the balance doesn't cover the amount
+pub fn debit_balance(storage: &mut dyn Storage, address: &Addr, funds: &Coin) -> Result<(), ContractError> {
+    let mut user_balances = load_balances(storage, address);
+    let balance = user_balances.iter().find(|balance| balance.denom == funds.denom).map_or(Uint128::zero(), |balance| balance.amount);
+    if balance < funds.amount {
+        return Err(ContractError::InsufficientBalance { denom: funds.denom.clone(), balance })
+    }
+
+    for balance in user_balances.iter_mut().filter(|balance| balance.denom == funds.denom) {
+        balance.amount -= funds.amount;
+    }
+    user_balances.retain(|balance| !balance.amount.is_zero());
+    if user_balances.is_empty() {
+        balances().remove(storage, address)?;
+    } else {
+        balances().insert(storage, address, &user_balances)?;
+    }
+    Ok(())
+}
+
 pub fn find_alpine_username(storage: &dyn Storage, username: String) -> Result<AlpineUser, ContractError> {
     let state = read_state(storage).load()?;
     for user in state.users {
@@ -68,6 +625,13 @@ pub fn find_alpine_username(storage: &dyn Storage, username: String) -> Result<A
     Err(ContractError::UserNotFound { user: username.to_string() })
 }
 
+pub fn find_social_handle(storage: &dyn Storage, handle: &str) -> Result<AlpineUser, ContractError> {
+    match social_handles().get(storage, &handle.to_string()) {
+        Some(address) => get_user_by_address(storage, address),
+        None => Err(ContractError::UserNotFound { user: handle.to_string() })
+    }
+}
+
 pub fn get_user_by_address(storage: &dyn Storage, address: Addr) -> Result<AlpineUser, ContractError> {
     let state = read_state(storage).load()?;
     for user in state.users{
@@ -83,48 +647,757 @@ pub struct DonationInfo {
     pub id: u64,
     pub sender: AlpineUser,
     pub recipient: AlpineUser,
-    pub amount: Vec<cosmwasm_std::Coin>,
+    pub amount: Vec<Coin>,
     pub message: String,
-    pub timestamp: Option<Timestamp>
+    pub timestamp: Option<Timestamp>,
+    // Set when the donation pays out periods of a subscription
+    #[serde(default)]
+    pub subscription_id: Option<u64>,
+    // Set when the donation counts toward a campaign
+    #[serde(default)]
+    pub campaign_id: Option<u64>,
+    // Set when part of the donation was sent on to the recipient's charity
+    #[serde(default)]
+    pub charity: Option<CharityDonation>,
+    // The donation fee which was charged, and the rate it effectively came to in basis points. Missing on donations
+    // recorded before fees were stored
+    #[serde(default)]
+    pub fee: Option<Coin>,
+    #[serde(default)]
+    pub fee_basis_points: Option<u32>,
+    // What reached the recipient, or their collaborators, after the fee and any charity share
+    #[serde(default)]
+    pub net: Option<Coin>,
+    // Where the donation was recorded, so that it can be matched to its transaction. For escrowed donations and
+    // campaign pledges this is the transaction which paid them out
+    #[serde(default)]
+    pub block_height: Option<u64>,
+    #[serde(default)]
+    pub tx_index: Option<u32>,
+    #[serde(default)]
+    pub chain_id: Option<String>
 }
 
 impl DonationInfo {
+    // A donation which hasn't been recorded yet. Its ID is assigned when it's recorded
+    pub fn new(sender: AlpineUser, recipient: AlpineUser, amount: Vec<Coin>, message: String, timestamp: Timestamp) -> DonationInfo {
+        DonationInfo {
+            id: 0,
+            sender,
+            recipient,
+            amount,
+            message,
+            timestamp: Some(timestamp),
+            subscription_id: None,
+            campaign_id: None,
+            charity: None,
+            fee: None,
+            fee_basis_points: None,
+            net: None,
+            block_height: None,
+            tx_index: None,
+            chain_id: None
+        }
+    }
+}
 
+// A donation to a username or social handle which nobody has registered yet. The contract holds the funds
+// until the target is registered, or until the sender reclaims them after `expires`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct EscrowedDonation {
+    pub id: u64,
+    pub sender: AlpineUser,
+    // Normalized username, or "platform:handle" for social handles
+    pub target: String,
+    pub amount: Vec<cosmwasm_std::Coin>,
+    pub message: String,
+    pub timestamp: Timestamp,
+    pub expires: Timestamp
+}
+
+pub fn save_escrow(storage: &mut dyn Storage, escrow: &EscrowedDonation) -> Result<(), ContractError> {
+    escrows().insert(storage, &escrow.id, escrow)?;
+    escrows_by_target().push(storage, &escrow.target, escrow.id)?;
+    add_to_index(storage, escrows_by_sender(), &escrow.sender.address, escrow.id)?;
+    Ok(())
 }
 
-pub fn clear_data(storage: &mut dyn Storage) -> Result<bool, ContractError> {
+pub fn remove_escrow(storage: &mut dyn Storage, escrow: &EscrowedDonation) -> Result<(), ContractError> {
+    escrows().remove(storage, &escrow.id)?;
+    remove_from_index(storage, escrows_by_sender(), &escrow.sender.address, escrow.id)?;
+    Ok(())
+}
+
+// Remove and return the oldest escrowed donations waiting for the target, looking at up to `limit` of them.
+// Also returns how many were looked at, including ones which had been reclaimed
+pub fn take_escrows(storage: &mut dyn Storage, target: &str, limit: u64) -> Result<(Vec<EscrowedDonation>, u64), ContractError> {
+    let target = target.to_string();
+    let mut taken = vec![];
+    let mut looked_at = 0;
+    while looked_at < limit {
+        let id = match escrows_by_target().pop_front(storage, &target)? {
+            Some(id) => id,
+            None => break
+        };
+        looked_at += 1;
+        if let Some(escrow) = escrows().get(storage, &id) {
+            remove_escrow(storage, &escrow)?;
+            taken.push(escrow);
+        }
+    }
+    Ok((taken, looked_at))
+}
+
+// A subscription prepaid for a number of periods. The share of each period can be claimed for the creator once
+// the period is over, and the subscriber can cancel to get the periods which haven't started refunded
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Subscription {
+    pub id: u64,
+    pub subscriber: AlpineUser,
+    pub creator: AlpineUser,
+    // What the creator receives each period, before the donation fee
+    pub rate: Coin,
+    // Length of a period in seconds
+    pub period: u64,
+    pub periods: u32,
+    pub claimed_periods: u32,
+    pub start: Timestamp
+}
+
+impl Subscription {
+    // The number of periods which have ended by `now`
+    pub fn elapsed_periods(&self, now: Timestamp) -> u32 {
+        let elapsed = now.seconds().saturating_sub(self.start.seconds()) / self.period;
+        elapsed.min(self.periods as u64) as u32
+    }
+}
+
+pub fn save_subscription(storage: &mut dyn Storage, subscription: &Subscription) -> Result<(), ContractError> {
+    if !subscriptions().contains(storage, &subscription.id) {
+        add_to_index(storage, subscriptions_by_subscriber(), &subscription.subscriber.address, subscription.id)?;
+        add_to_index(storage, subscriptions_by_creator(), &subscription.creator.address, subscription.id)?;
+    }
+    subscriptions().insert(storage, &subscription.id, subscription)?;
+    Ok(())
+}
+
+pub fn remove_subscription(storage: &mut dyn Storage, subscription: &Subscription) -> Result<(), ContractError> {
+    subscriptions().remove(storage, &subscription.id)?;
+    remove_from_index(storage, subscriptions_by_subscriber(), &subscription.subscriber.address, subscription.id)?;
+    remove_from_index(storage, subscriptions_by_creator(), &subscription.creator.address, subscription.id)?;
+    Ok(())
+}
+
+// Load the subscriptions whose IDs are listed in an index
+pub fn load_subscriptions(storage: &dyn Storage, ids: Vec<u64>) -> Vec<Subscription> {
+    ids.into_iter().filter_map(|id| subscriptions().get(storage, &id)).collect()
+}
+
+// A membership tier. Supporters reach it by giving at least `amount` of `denom` to the creator within the window
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Tier {
+    pub name: String,
+    pub denom: String,
+    pub amount: Uint128
+}
+
+// A creator's tiers, ordered from lowest to highest, and the length of the rolling window in seconds
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CreatorTiers {
+    pub tiers: Vec<Tier>,
+    pub window: u64
+}
+
+impl Default for CreatorTiers {
+    fn default() -> Self {
+        CreatorTiers { tiers: vec![], window: DEFAULT_TIER_WINDOW }
+    }
+}
+
+pub fn load_creator_tiers(storage: &dyn Storage, creator: &Addr) -> CreatorTiers {
+    creator_tiers().get(storage, creator).unwrap_or_default()
+}
+
+// Total up what a supporter has given a creator within the window ending at `now`. Subscription payouts aren't
+// counted as they happen. Instead every active subscription counts for its rate, scaled to the length of the window
+pub fn recent_support(storage: &dyn Storage, supporter: &Addr, creator: &Addr, window: u64, now: Timestamp) -> Result<Vec<Coin>, ContractError> {
+    let since = now.minus_seconds(window.min(now.seconds()));
+
+    // The supporter's donations are walked from the newest, so only the ones within the window are read
+    let mut support = vec![];
+    let index = donations_by_sender();
+    let bounds = index.bounds(storage, supporter);
+    for position in (bounds.start..bounds.end).rev() {
+        let donation = match index.get(storage, supporter, position).and_then(|id| donations().get(storage, &id)) {
+            Some(donation) => donation,
+            None => continue
+        };
+        match donation.timestamp {
+            Some(timestamp) if timestamp > since => {},
+            _ => break
+        }
+        if donation.subscription_id.is_none() && &donation.recipient.address == creator {
+            add_coin(&mut support, &donation.amount[0]);
+        }
+    }
+
+    let ids = subscriptions_by_subscriber().get(storage, supporter).unwrap_or_default();
+    for subscription in load_subscriptions(storage, ids) {
+        if &subscription.creator.address == creator && subscription.elapsed_periods(now) < subscription.periods {
+            let amount = subscription.rate.amount.multiply_ratio(window, subscription.period);
+            add_coin(&mut support, &Coin { denom: subscription.rate.denom, amount });
+        }
+    }
+
+    Ok(support)
+}
+
+// A collaborator's share of the donations to a user, in basis points. The shares of a split add up to 10000
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PayoutShare {
+    pub address: Addr,
+    pub weight: u32
+}
+
+pub fn load_payout_split(storage: &dyn Storage, address: &Addr) -> Vec<PayoutShare> {
+    payout_splits().get(storage, address).unwrap_or_default()
+}
+
+// A charity which a creator passes a percentage of every donation on to, after the donation fee
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CharityPledge {
+    pub address: Addr,
+    pub percent: u32
+}
+
+// The part of a donation which was sent on to a charity
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CharityDonation {
+    pub address: Addr,
+    pub amount: Coin
+}
+
+// A donation fee in basis points which applies to a recipient instead of the default, until it expires
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FeeOverride {
+    pub basis_points: u32,
+    pub expires: Option<Timestamp>
+}
+
+// The fee override which applies to a recipient right now, if any
+pub fn load_fee_override(storage: &dyn Storage, recipient: &Addr, now: Timestamp) -> Option<FeeOverride> {
+    fee_overrides().get(storage, recipient).filter(|fee_override| !matches!(fee_override.expires, Some(expires) if expires <= now))
+}
+
+// How much of a denom a user has received so far, before fees
+pub fn load_received_volume(storage: &dyn Storage, address: &Addr, denom: &str) -> Uint128 {
+    load_user_stats(storage, address).received
+        .into_iter()
+        .find(|volume| volume.denom == denom)
+        .map_or(Uint128::zero(), |volume| volume.amount)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct UserStats {
+    // Everything received before fees, and what reached the user after fees and charity. One coin per denom
+    pub received: Vec<Coin>,
+    pub earned: Vec<Coin>,
+    pub donation_count: u64,
+    // Distinct addresses which have donated, including anonymously
+    pub supporter_count: u64,
+    // The biggest single donation in each denom
+    pub largest_donations: Vec<Coin>,
+    // What was received before fees in `month`, counted in months since January 1970
+    pub month: u32,
+    pub month_received: Vec<Coin>
+}
+
+pub fn load_user_stats(storage: &dyn Storage, address: &Addr) -> UserStats {
+    user_stats().get(storage, address).unwrap_or_default()
+}
+
+// Add a recorded donation to its recipient's running totals
+pub fn record_donation_stats(storage: &mut dyn Storage, donation: &DonationInfo, now: Timestamp) -> Result<(), ContractError> {
+    let recipient = &donation.recipient.address;
+    let amount = &donation.amount[0];
+    let mut stats = load_user_stats(storage, recipient);
+
+    add_coin(&mut stats.received, amount);
+    if let Some(net) = &donation.net {
+        add_coin(&mut stats.earned, net);
+    }
+    stats.donation_count += 1;
+    match stats.largest_donations.iter_mut().find(|largest| largest.denom == amount.denom) {
+        Some(largest) => largest.amount = largest.amount.max(amount.amount),
+        None => stats.largest_donations.push(amount.clone())
+    }
+    let month = month_index(now);
+    if stats.month != month {
+        stats.month = month;
+        stats.month_received = vec![];
+    }
+    add_coin(&mut stats.month_received, amount);
+
+    let anonymous = donation.sender.username.is_empty();
+    let key = SupporterKey { address: donation.sender.address.clone(), anonymous };
+    let other_key = SupporterKey { address: key.address.clone(), anonymous: !anonymous };
+    let mut total = match supporter_totals().get(storage, &(recipient.clone(), key.clone())) {
+        Some(total) => total,
+        None => {
+            if !supporter_totals().contains(storage, &(recipient.clone(), other_key)) {
+                stats.supporter_count += 1;
+            }
+            let supporter = match anonymous {
+                true => AlpineUser::empty(),
+                false => donation.sender.clone()
+            };
+            SupporterTotal { supporter, totals: vec![], donation_count: 0 }
+        }
+    };
+    add_coin(&mut total.totals, amount);
+    total.donation_count += 1;
+    supporter_totals().insert(storage, &(recipient.clone(), key.clone()), &total)?;
+    if let Some(supporter_total) = total.totals.iter().find(|coin| coin.denom == amount.denom) {
+        rank_supporter(storage, recipient, key, supporter_total)?;
+    }
+
+    user_stats().insert(storage, recipient, &stats)?;
+
+    let week = week_index(now);
+    let mut weekly = weekly_stats().get(storage, recipient).filter(|weekly| weekly.week == week)
+        .unwrap_or(WeeklyStats { week, ..Default::default() });
+    weekly.donation_count += 1;
+    add_coin(&mut weekly.received, amount);
+    if !weekly.donors.contains(&donation.sender.address) {
+        weekly.donors.push(donation.sender.address.clone());
+    }
+    weekly_stats().insert(storage, recipient, &weekly)?;
+    Ok(())
+}
+
+// Totals across every donation, for the owner
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct PlatformStats {
+    // Before fees. Each of these holds at most one coin per denom
+    pub volume: Vec<Coin>,
+    pub fees: Vec<Coin>,
+    pub largest_donations: Vec<LargestDonation>
+}
+
+// The biggest single donation in a denom
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LargestDonation {
+    pub id: u64,
+    pub amount: Coin
+}
+
+pub fn load_platform_stats(storage: &dyn Storage) -> StdResult<PlatformStats> {
+    Ok(singleton_read(storage, PLATFORM_STATS_KEY).may_load()?.unwrap_or_default())
+}
+
+// The day each creator last received a donation, counted in days since January 1970
+fn creator_last_active<'a>() -> LookupMap<'a, Addr, u64> {
+    KeymapBuilder::new(CREATOR_LAST_ACTIVE_KEY).without_iter().build()
+}
+
+// How many creators last received a donation on each day. Every creator is only counted on their latest day,
+// so adding up a range of days gives the number of creators who were active in it
+fn active_creators_by_day<'a>() -> LookupMap<'a, u64, u64> {
+    KeymapBuilder::new(ACTIVE_CREATORS_BY_DAY_KEY).without_iter().build()
+}
+
+// Add a recorded donation to the platform's totals, and mark its recipient as active today
+pub fn record_platform_stats(storage: &mut dyn Storage, donation: &DonationInfo, now: Timestamp) -> Result<(), ContractError> {
+    let amount = &donation.amount[0];
+    let mut stats = load_platform_stats(storage)?;
+    add_coin(&mut stats.volume, amount);
+    if let Some(fee) = donation.fee.as_ref().filter(|fee| !fee.amount.is_zero()) {
+        add_coin(&mut stats.fees, fee);
+    }
+    match stats.largest_donations.iter_mut().find(|largest| largest.amount.denom == amount.denom) {
+        Some(largest) if largest.amount.amount >= amount.amount => {},
+        Some(largest) => *largest = LargestDonation { id: donation.id, amount: amount.clone() },
+        None => stats.largest_donations.push(LargestDonation { id: donation.id, amount: amount.clone() })
+    }
+    singleton(storage, PLATFORM_STATS_KEY).save(&stats)?;
+
+    let today = day_index(now);
+    let recipient = &donation.recipient.address;
+    let last_active = creator_last_active().get(storage, recipient);
+    if last_active != Some(today) {
+        if let Some(day) = last_active {
+            remove_active_creator(storage, day)?;
+        }
+        let count = active_creators_by_day().get(storage, &today).unwrap_or_default();
+        active_creators_by_day().insert(storage, &today, &(count + 1))?;
+        creator_last_active().insert(storage, recipient, &today)?;
+    }
+    Ok(())
+}
+
+fn remove_active_creator(storage: &mut dyn Storage, day: u64) -> StdResult<()> {
+    match active_creators_by_day().get(storage, &day).unwrap_or_default() {
+        0 | 1 => active_creators_by_day().remove(storage, &day),
+        count => active_creators_by_day().insert(storage, &day, &(count - 1))
+    }
+}
+
+// The number of creators who received a donation in the `days` days up to and including today
+pub fn count_active_creators(storage: &dyn Storage, now: Timestamp, days: u32) -> u64 {
+    let today = day_index(now);
+    (0..days as u64)
+        .take_while(|offset| *offset <= today)
+        .map(|offset| active_creators_by_day().get(storage, &(today - offset)).unwrap_or_default())
+        .sum()
+}
+
+// The number of whole days since January 1970
+pub fn day_index(time: Timestamp) -> u64 {
+    time.seconds() / 86_400
+}
+
+// Donations to a user in one week, which the public leaderboard is built from
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct WeeklyStats {
+    pub week: u64,
+    pub donation_count: u64,
+    // Before fees. Holds at most one coin per denom
+    pub received: Vec<Coin>,
+    pub donors: Vec<Addr>
+}
+
+// The number of weeks between the Monday before January 1970 and a timestamp, so weeks start on Mondays
+pub fn week_index(time: Timestamp) -> u64 {
+    // January 1 1970 was a Thursday
+    (time.seconds() / 86_400 + 3) / 7
+}
+
+// Move a supporter to their place among a creator's top supporters in a denom, now that their total there is
+// `total`. Totals only grow, so anyone who drops off the end can only come back with a new donation. Supporters
+// who reached an amount first stay ahead of the ones who match it later
+fn rank_supporter(storage: &mut dyn Storage, creator: &Addr, key: SupporterKey, total: &Coin) -> StdResult<()> {
+    let index_key = (creator.clone(), total.denom.clone());
+    let mut ranked = top_supporters().get(storage, &index_key).unwrap_or_default();
+    ranked.retain(|entry| entry.key != key);
+    let position = ranked.iter().position(|entry| entry.amount < total.amount).unwrap_or(ranked.len());
+    if position >= MAX_TOP_SUPPORTERS {
+        return Ok(())
+    }
+    ranked.insert(position, RankedSupporter { key, amount: total.amount });
+    ranked.truncate(MAX_TOP_SUPPORTERS);
+    top_supporters().insert(storage, &index_key, &ranked)
+}
+
+// Supporters who give both under their name and anonymously have a total for each, so that the anonymous one
+// doesn't reveal who they are
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SupporterKey {
+    pub address: Addr,
+    pub anonymous: bool
+}
+
+// A supporter's total in one denom, in a creator's list of top supporters
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RankedSupporter {
+    pub key: SupporterKey,
+    pub amount: Uint128
+}
+
+// What a supporter has given a creator before fees. Anonymous supporters are stored as an empty user
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SupporterTotal {
+    pub supporter: AlpineUser,
+    pub totals: Vec<Coin>,
+    pub donation_count: u64
+}
+
+// The number of calendar months between January 1970 and a timestamp
+pub fn month_index(time: Timestamp) -> u32 {
+    // Convert days since the epoch to a civil date, counting years from March so that leap days come last
+    let days = time.seconds() / 86_400 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let (month, year_offset) = match month_from_march < 10 {
+        true => (month_from_march + 2, 0),
+        false => (month_from_march - 10, 1)
+    };
+    let year = era * 400 + year_of_era + year_offset;
+    ((year - 1970) * 12 + month) as u32
+}
+
+// A fundraising goal set by a creator. Donations sent with the campaign's ID count toward its target
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Campaign {
+    pub id: u64,
+    pub creator: AlpineUser,
+    pub description: String,
+    pub target: Coin,
+    pub deadline: Timestamp,
+    // Total donated toward the target, before the donation fee
+    pub raised: Uint128,
+    pub donation_count: u64,
+    // Donations to all-or-nothing campaigns are held until the deadline. The creator only gets them
+    // if the target was met, and otherwise they're refunded
+    #[serde(default)]
+    pub all_or_nothing: bool,
+    #[serde(default)]
+    pub claimed: bool,
+    // How many donations were pledged to an all-or-nothing campaign, and how many of them the creator has claimed
+    #[serde(default)]
+    pub pledge_count: u64,
+    #[serde(default)]
+    pub claimed_pledges: u64
+}
+
+pub fn save_campaign(storage: &mut dyn Storage, campaign: &Campaign) -> Result<(), ContractError> {
+    if !campaigns().contains(storage, &campaign.id) {
+        add_to_index(storage, campaigns_by_creator(), &campaign.creator.address, campaign.id)?;
+    }
+    campaigns().insert(storage, &campaign.id, campaign)?;
+    Ok(())
+}
+
+// Hold a donation to an all-or-nothing campaign until the deadline
+pub fn save_pledge(storage: &mut dyn Storage, mut campaign: Campaign, pledge: &DonationInfo) -> Result<(), ContractError> {
+    campaign_pledges().insert(storage, &(campaign.id, campaign.pledge_count), pledge)?;
+    campaign.pledge_count += 1;
+    save_campaign(storage, &campaign)?;
+
+    let key = (campaign.id, pledge.sender.address.clone());
+    let mut refund = campaign_refunds().get(storage, &key).unwrap_or_default();
+    pledge.amount.iter().for_each(|coin| add_coin(&mut refund, coin));
+    campaign_refunds().insert(storage, &key, &refund)?;
+    Ok(())
+}
+
+// Link a verified social handle to a user, replacing any previous owner of the handle
+pub fn link_social_handle(storage: &mut dyn Storage, handle: &str, address: &Addr) -> Result<(), ContractError> {
+    if let Some(previous_owner) = social_handles().get(storage, &handle.to_string()) {
+        let mut handles = user_social_handles().get(storage, &previous_owner).unwrap_or_default();
+        handles.retain(|h| h != handle);
+        user_social_handles().insert(storage, &previous_owner, &handles)?;
+    }
+
+    social_handles().insert(storage, &handle.to_string(), address)?;
+    let mut handles = user_social_handles().get(storage, address).unwrap_or_default();
+    handles.push(handle.to_string());
+    user_social_handles().insert(storage, address, &handles)?;
+    Ok(())
+}
+
+// Remove up to `limit` donations, newest first, along with their index entries, unread flags and the stats of
+// recipients who have none left. Once every donation is gone the users are removed too. Returns how many
+// donations are left to clear
+pub fn clear_data(storage: &mut dyn Storage, limit: u64) -> Result<u64, ContractError> {
     let mut state = read_state(storage).load()?;
+    let end = state.donation_count.saturating_sub(limit);
+    for id in (end..state.donation_count).rev() {
+        if let Some(donation) = donations().get(storage, &id) {
+            donations().remove(storage, &id)?;
+            // Donations are indexed in the order of their IDs, so this is the last one in both indexes
+            let recipient = &donation.recipient.address;
+            donations_by_sender().pop_back(storage, &donation.sender.address)?;
+            donations_by_recipient().pop_back(storage, recipient)?;
+            remove_supporter_donation(storage, &donation)?;
+            if is_unread(storage, recipient, id) {
+                unread_donations().remove(storage, &(recipient.clone(), id))?;
+            }
+            if donations_by_recipient().bounds(storage, recipient).is_empty() {
+                clear_donation_stats(storage, recipient)?;
+                if unread_counts().contains(storage, recipient) {
+                    unread_counts().remove(storage, recipient)?;
+                }
+            }
+        }
+    }
+    state.donation_count = end;
     state.donations = vec![];
+    if end > 0 {
+        update_state(storage).save(&state)?;
+        return Ok(end)
+    }
+
+    for user in &state.users {
+        let skeleton = username_skeleton(&user.username);
+        if username_skeletons().contains(storage, &skeleton) {
+            username_skeletons().remove(storage, &skeleton)?;
+        }
+        if user_settings().contains(storage, &user.address) {
+            save_user_settings(storage, user, UserSettings::default())?;
+            user_settings().remove(storage, &user.address)?;
+        }
+        for handle in user_social_handles().get(storage, &user.address).unwrap_or_default() {
+            social_handles().remove(storage, &handle)?;
+        }
+        if user_social_handles().contains(storage, &user.address) {
+            user_social_handles().remove(storage, &user.address)?;
+        }
+        if let Some(day) = creator_last_active().get(storage, &user.address) {
+            remove_active_creator(storage, day)?;
+            creator_last_active().remove(storage, &user.address)?;
+        }
+    }
+    singleton(storage, PLATFORM_STATS_KEY).save(&PlatformStats::default())?;
     state.users = vec![];
-    state.donation_count = 0;
     update_state(storage).save(&state)?;
-    Ok(true)
+    Ok(0)
 }
 
-pub fn update_donations(storage: &mut dyn Storage, donation: DonationInfo) -> Result<DonationInfo, ContractError> {
-    let mut state = read_state(storage).load()?;
-    for dono in state.donations.clone(){
-        if dono.id == donation.id{
-            return Err(ContractError::Unauthorized {  })
+// Take a cleared donation off its supporter's total for the recipient, and remove the total with their last
+// donation. Only the count is kept up to date, as the recipient's stats are cleared once they have no donations left
+fn remove_supporter_donation(storage: &mut dyn Storage, donation: &DonationInfo) -> StdResult<()> {
+    let key = (donation.recipient.address.clone(), SupporterKey {
+        address: donation.sender.address.clone(),
+        anonymous: donation.sender.username.is_empty()
+    });
+    match supporter_totals().get(storage, &key) {
+        Some(total) if total.donation_count > 1 => {
+            supporter_totals().insert(storage, &key, &SupporterTotal { donation_count: total.donation_count - 1, ..total })
+        },
+        Some(_) => supporter_totals().remove(storage, &key),
+        None => Ok(())
+    }
+}
+
+// Remove the running totals of what a user has received, along with their top supporters in each denom
+fn clear_donation_stats(storage: &mut dyn Storage, recipient: &Addr) -> StdResult<()> {
+    for coin in load_user_stats(storage, recipient).received {
+        let key = (recipient.clone(), coin.denom);
+        if top_supporters().contains(storage, &key) {
+            top_supporters().remove(storage, &key)?;
         }
     }
-    state.donations.append(&mut vec![donation.clone()]);
+    if user_stats().contains(storage, recipient) {
+        user_stats().remove(storage, recipient)?;
+    }
+    if weekly_stats().contains(storage, recipient) {
+        weekly_stats().remove(storage, recipient)?;
+    }
+    Ok(())
+}
+
+// Record a donation under its ID and index it by its sender and recipient
+pub fn update_donations(storage: &mut dyn Storage, donation: DonationInfo) -> Result<DonationInfo, ContractError> {
+    if donations().contains(storage, &donation.id) {
+        return Err(ContractError::Unauthorized {  })
+    }
+    donations().insert(storage, &donation.id, &donation)?;
+    donations_by_sender().push(storage, &donation.sender.address, donation.id)?;
+    donations_by_recipient().push(storage, &donation.recipient.address, donation.id)?;
+    mark_donation_unread(storage, &donation.recipient.address, donation.id)?;
+
+    let mut state = read_state(storage).load()?;
     state.donation_count += 1;
     update_state(storage).save(&state)?;
     Ok(donation)
 }
 
+pub fn load_donations(storage: &dyn Storage, ids: Vec<u64>) -> Vec<DonationInfo> {
+    ids.into_iter().filter_map(|id| donations().get(storage, &id)).collect()
+}
+
+// Move the donations kept in the state into the donation indexes, and add them to their recipients' running
+// totals as of when they were sent. Needed when migrating from a version which kept every donation in the state
+pub fn migrate_donations(storage: &mut dyn Storage) -> Result<(), ContractError> {
+    let mut state = read_state(storage).load()?;
+    let legacy_donations = std::mem::take(&mut state.donations);
+    update_state(storage).save(&state)?;
+
+    for donation in legacy_donations {
+        if donations().contains(storage, &donation.id) {
+            continue
+        }
+        donations().insert(storage, &donation.id, &donation)?;
+        donations_by_sender().push(storage, &donation.sender.address, donation.id)?;
+        donations_by_recipient().push(storage, &donation.recipient.address, donation.id)?;
+        record_donation_stats(storage, &donation, donation.timestamp.unwrap_or_default())?;
+    }
+    Ok(())
+}
+
 pub fn donation_count(storage: &dyn Storage) -> StdResult<u64> {
     let state = read_state(storage).load()?;
     Ok(state.donation_count)
 }
 
+// Check whether a username is taken. Names are compared by their skeleton, so this also
+// catches names which only differ by casing or confusable characters
 pub fn contains_username(storage: &dyn Storage, username: String) -> Result<bool, ContractError> {
+    Ok(username_skeletons().contains(storage, &username_skeleton(&username)))
+}
+
+// Save a newly registered user and index their username skeleton
+pub fn save_user(storage: &mut dyn Storage, user: AlpineUser) -> Result<AlpineUser, ContractError> {
+    let mut state = read_state(storage).load()?;
+    username_skeletons().insert(storage, &username_skeleton(&user.username), &user.address)?;
+    state.users.push(user.clone());
+    update_state(storage).save(&state)?;
+    Ok(user)
+}
+
+// Rebuild the skeleton index from the saved users. Needed when migrating from a version without the index.
+// If two existing users already share a skeleton, the first one to have registered keeps it
+pub fn rebuild_username_index(storage: &mut dyn Storage) -> Result<(), ContractError> {
     let state = read_state(storage).load()?;
-    for user in state.users{
-        if user.username.to_lowercase() == username.to_lowercase(){
-            return Ok(true)
+    for user in state.users {
+        let skeleton = username_skeleton(&user.username);
+        if !username_skeletons().contains(storage, &skeleton) {
+            username_skeletons().insert(storage, &skeleton, &user.address)?;
         }
     }
-    return Ok(false)
+    Ok(())
+}
+
+// Reduce a username to a canonical "skeleton" so that names which only look alike map to the same value.
+// Casing is ignored, non-ASCII digits are folded to ASCII, dashes and underscores are treated the same, and
+// characters which are easily confused with each other (l/I/1, o/0, s/5, rn/m, vv/w) are folded together.
+pub fn username_skeleton(username: &str) -> String {
+    let folded: String = username
+        .to_lowercase()
+        .chars()
+        .map(|c| match fold_digit(c) {
+            '0' => 'o',
+            '1' | 'i' | 'l' => 'l',
+            '5' => 's',
+            '-' => '_',
+            c => c
+        })
+        .collect();
+
+    folded.replace("rn", "m").replace("vv", "w")
+}
+
+// The first code point of each Unicode block of decimal digits (0-9) which is likely to be used in a username.
+// Digits from any other block can't be folded and are refused by username validation
+const UNICODE_DIGIT_ZEROS: [u32; 26] = [
+    0x0660, 0x06F0, 0x07C0, 0x0966, 0x09E6, 0x0A66, 0x0AE6, 0x0B66, 0x0BE6, 0x0C66, 0x0CE6, 0x0D66,
+    0x0DE6, 0x0E50, 0x0ED0, 0x0F20, 0x1040, 0x17E0, 0x1810, 0x2080, 0xFF10, 0x1D7CE, 0x1D7D8, 0x1D7E2,
+    0x1D7EC, 0x1D7F6
+];
+
+// Fold a non-ASCII digit to its ASCII equivalent. Any other character is returned as-is
+pub fn fold_digit(c: char) -> char {
+    if c.is_ascii() || !c.is_numeric() {
+        return c
+    }
+
+    let code = c as u32;
+    let value = match code {
+        0x00B9 => Some(1),
+        0x00B2 | 0x00B3 => Some(code - 0x00B0),
+        0x2070 | 0x2074..=0x2079 => Some(code - 0x2070),
+        0x2460..=0x2468 => Some(code - 0x245F),
+        _ => UNICODE_DIGIT_ZEROS
+            .iter()
+            .find(|zero| (**zero..**zero + 10).contains(&code))
+            .map(|zero| code - zero)
+    };
+
+    match value.and_then(|v| char::from_digit(v, 10)) {
+        Some(digit) => digit,
+        None => c
+    }
 }